@@ -0,0 +1,287 @@
+use chrono::{DateTime, TimeZone};
+use chrono_tz::Tz;
+
+use super::telegram::{self, Telegram};
+use super::value::{Decimal, Quantity};
+use super::TelegramConsumer;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statistic {
+    // Power, voltage, current and other values that describe a single moment
+    Instantaneous {
+        obis: String,
+        min: Quantity,
+        max: Quantity,
+        mean: Quantity,
+    },
+    // Counters that only increase, such as energy delivered
+    Cumulative {
+        obis: String,
+        start: Quantity,
+        end: Quantity,
+        delta: Quantity,
+    },
+}
+
+// The statistics of all telegrams that were read in a window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub start: DateTime<Tz>,
+    pub end: DateTime<Tz>,
+    pub count: i64,
+    pub statistics: Vec<Statistic>,
+}
+
+struct Accumulator {
+    obis: String,
+    cumulative: bool,
+    first: Quantity,
+    last: Quantity,
+    min: Quantity,
+    max: Quantity,
+    sum: Decimal,
+    count: i64,
+}
+impl Accumulator {
+    fn new(obis: &str, quantity: Quantity) -> Self {
+        Accumulator {
+            obis: String::from(obis),
+            cumulative: telegram::is_cumulative(obis),
+            first: quantity,
+            last: quantity,
+            min: quantity,
+            max: quantity,
+            sum: quantity.value,
+            count: 1,
+        }
+    }
+
+    fn add(&mut self, quantity: Quantity) {
+        let quantity = match quantity.convert(self.first.unit) {
+            Ok(quantity) => quantity,
+            Err(_) => return,
+        };
+        self.last = quantity;
+        if quantity.value < self.min.value {
+            self.min = quantity;
+        }
+        if quantity.value > self.max.value {
+            self.max = quantity;
+        }
+        self.sum = self.sum + quantity.value;
+        self.count += 1;
+    }
+
+    fn statistic(&self) -> Statistic {
+        if self.cumulative {
+            Statistic::Cumulative {
+                obis: self.obis.clone(),
+                start: self.first,
+                end: self.last,
+                delta: Quantity::new(self.last.value - self.first.value, self.first.unit),
+            }
+        } else {
+            Statistic::Instantaneous {
+                obis: self.obis.clone(),
+                min: self.min,
+                max: self.max,
+                mean: Quantity::new(self.sum.divide(self.count), self.first.unit),
+            }
+        }
+    }
+}
+
+struct Window {
+    start: i64,
+    count: i64,
+    accumulators: Vec<Accumulator>,
+    last: Telegram,
+}
+
+// Collects telegrams into fixed windows that align to wall-clock boundaries of the meter
+// timestamp, and sends one telegram per window to its sink when the window closes.
+// That telegram is the last one of the window, with its instantaneous values replaced
+// by their mean and a summary of all values in the window.
+pub struct AggregatingConsumer {
+    length: i64,
+    window: Option<Window>,
+    downstream: Box<dyn TelegramConsumer>,
+}
+impl AggregatingConsumer {
+    pub fn new(length: u64, downstream: Box<dyn TelegramConsumer>) -> Self {
+        AggregatingConsumer {
+            length: length as i64,
+            window: None,
+            downstream,
+        }
+    }
+
+    // Returns the telegram of the previous window when the telegram starts a new window.
+    fn aggregate(&mut self, telegram: &Telegram) -> Option<Telegram> {
+        let timestamp = match telegram.timestamp() {
+            Some(timestamp) => timestamp.timestamp(),
+            None => {
+                log::debug!("Not aggregating telegram without timestamp");
+                return None;
+            }
+        };
+        let start = timestamp - timestamp.rem_euclid(self.length);
+
+        let closed = match &self.window {
+            Some(window) if window.start != start => self.window.take().map(|w| self.close(w)),
+            _ => None,
+        };
+
+        let window = self.window.get_or_insert_with(|| Window {
+            start,
+            count: 0,
+            accumulators: Vec::new(),
+            last: telegram.clone(),
+        });
+        window.count += 1;
+        window.last = telegram.clone();
+        for object in &telegram.objects {
+            if let Some(quantity) = object.quantity() {
+                match window
+                    .accumulators
+                    .iter_mut()
+                    .find(|accumulator| accumulator.obis == object.obis)
+                {
+                    Some(accumulator) => accumulator.add(quantity),
+                    None => window
+                        .accumulators
+                        .push(Accumulator::new(&object.obis, quantity)),
+                }
+            }
+        }
+
+        closed
+    }
+
+    fn close(&self, window: Window) -> Telegram {
+        let mut result = window.last;
+        let statistics: Vec<Statistic> = window
+            .accumulators
+            .iter()
+            .map(Accumulator::statistic)
+            .collect();
+
+        for statistic in &statistics {
+            if let Statistic::Instantaneous { obis, mean, .. } = statistic {
+                if let Some(object) = result.objects.iter_mut().find(|o| &o.obis == obis) {
+                    object.set_quantity(*mean);
+                }
+            }
+        }
+
+        let timezone = result
+            .timestamp()
+            .map(|timestamp| timestamp.timezone())
+            .unwrap_or(chrono_tz::Europe::Amsterdam);
+        result.summary = Some(Summary {
+            start: timezone.timestamp_opt(window.start, 0).unwrap(),
+            end: timezone
+                .timestamp_opt(window.start + self.length, 0)
+                .unwrap(),
+            count: window.count,
+            statistics,
+        });
+        result
+    }
+}
+impl TelegramConsumer for AggregatingConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        if let Some(aggregate) = self.aggregate(telegram) {
+            self.downstream.consume(&aggregate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use crate::dsmr::timestamp::parse_timestamp;
+
+    struct NoopConsumer {}
+    impl TelegramConsumer for NoopConsumer {
+        fn consume(&mut self, _telegram: &Telegram) {}
+    }
+
+    fn telegram(timestamp: &str, delivered: &str, power: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n1-0:1.8.1({}*kWh)\r\n1-0:1.7.0({}*kW)\r\n!\r\n",
+            timestamp, delivered, power
+        ))
+    }
+
+    #[test]
+    fn aggregate_window_of_five_minutes() {
+        let mut consumer = AggregatingConsumer::new(300, Box::new(NoopConsumer {}));
+
+        assert!(consumer
+            .aggregate(&telegram("231026204015S", "000032.159", "00.300"))
+            .is_none());
+        assert!(consumer
+            .aggregate(&telegram("231026204230S", "000032.170", "00.100"))
+            .is_none());
+        assert!(consumer
+            .aggregate(&telegram("231026204459S", "000032.180", "00.500"))
+            .is_none());
+        let result = consumer
+            .aggregate(&telegram("231026204500S", "000032.190", "00.200"))
+            .unwrap();
+
+        assert_eq!(result.value("1-0:1.7.0"), Some("0.300*kW"));
+        assert_eq!(result.value("1-0:1.8.1"), Some("000032.180*kWh"));
+
+        let summary = result.summary.unwrap();
+        assert_eq!(summary.start, parse_timestamp("231026204000S").unwrap());
+        assert_eq!(summary.end, parse_timestamp("231026204500S").unwrap());
+        assert_eq!(summary.count, 3);
+        assert_eq!(
+            summary.statistics,
+            vec![
+                Statistic::Cumulative {
+                    obis: String::from("1-0:1.8.1"),
+                    start: Quantity::parse("32.159*kWh").unwrap(),
+                    end: Quantity::parse("32.180*kWh").unwrap(),
+                    delta: Quantity::parse("0.021*kWh").unwrap(),
+                },
+                Statistic::Instantaneous {
+                    obis: String::from("1-0:1.7.0"),
+                    min: Quantity::parse("0.100*kW").unwrap(),
+                    max: Quantity::parse("0.500*kW").unwrap(),
+                    mean: Quantity::parse("0.300*kW").unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregate_hourly_window_across_dst_change() {
+        let mut consumer = AggregatingConsumer::new(3600, Box::new(NoopConsumer {}));
+
+        consumer.aggregate(&telegram("231029023000S", "000032.159", "00.300"));
+        let result = consumer
+            .aggregate(&telegram("231029020000W", "000032.170", "00.100"))
+            .unwrap();
+
+        let summary = result.summary.unwrap();
+        assert_eq!(summary.start, parse_timestamp("231029020000S").unwrap());
+        assert_eq!(summary.end, parse_timestamp("231029020000W").unwrap());
+    }
+
+    #[test]
+    fn skip_telegrams_without_timestamp() {
+        let mut consumer = AggregatingConsumer::new(60, Box::new(NoopConsumer {}));
+
+        let result = consumer.aggregate(&Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n1-0:1.7.0(00.300*kW)\r\n!\r\n",
+        ));
+
+        assert!(result.is_none());
+        assert!(consumer.window.is_none());
+    }
+}
@@ -0,0 +1,418 @@
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use super::mqtt::MqttClient;
+use super::settings::{AlertCondition, AlertRule, AlertSettings, MqttSettings};
+use super::telegram::Telegram;
+use super::value::{Decimal, Quantity, Unit};
+use super::TelegramConsumer;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+impl AlertState {
+    fn name(&self) -> &'static str {
+        match self {
+            AlertState::Firing => "firing",
+            AlertState::Resolved => "resolved",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub rule: String,
+    pub state: AlertState,
+    pub message: String,
+    // Meter timestamp of the telegram that fired or resolved the alert
+    pub at: DateTime<Tz>,
+}
+impl Alert {
+    fn to_json(&self) -> String {
+        serde_json::json!({
+            "rule": self.rule,
+            "state": self.state.name(),
+            "message": self.message,
+            "timestamp": self.at.to_rfc3339(),
+        })
+        .to_string()
+    }
+}
+
+pub trait Notifier {
+    fn notify(&mut self, alert: &Alert);
+}
+
+// POSTs alerts as JSON, without waiting for the answer.
+struct WebhookNotifier {
+    url: String,
+}
+impl Notifier for WebhookNotifier {
+    fn notify(&mut self, alert: &Alert) {
+        let url = self.url.clone();
+        let body = alert.to_json();
+        thread::spawn(move || {
+            let result = reqwest::blocking::Client::new()
+                .post(&url)
+                .header("Content-Type", "application/json")
+                .timeout(WEBHOOK_TIMEOUT)
+                .body(body)
+                .send();
+            match result {
+                Ok(response) if !response.status().is_success() => {
+                    log::warn!("Webhook {} answered alert with {}", url, response.status())
+                }
+                Ok(_) => {}
+                Err(msg) => log::warn!("Could not send alert to {} due to {}", url, msg),
+            }
+        });
+    }
+}
+
+struct MqttNotifier {
+    topic: String,
+    client: MqttClient,
+}
+impl Notifier for MqttNotifier {
+    fn notify(&mut self, alert: &Alert) {
+        if let Err(msg) = self
+            .client
+            .publish(&self.topic, alert.to_json().as_bytes(), false)
+        {
+            log::warn!("Could not publish alert due to {}", msg);
+        }
+    }
+}
+
+// Runs a command with the alert in the environment variables DSMR_ALERT_RULE, DSMR_ALERT_STATE,
+// DSMR_ALERT_MESSAGE and DSMR_ALERT_TIMESTAMP, without waiting for it to finish.
+struct CommandNotifier {
+    command: String,
+}
+impl Notifier for CommandNotifier {
+    fn notify(&mut self, alert: &Alert) {
+        let mut command = Command::new(&self.command);
+        command
+            .env("DSMR_ALERT_RULE", &alert.rule)
+            .env("DSMR_ALERT_STATE", alert.state.name())
+            .env("DSMR_ALERT_MESSAGE", &alert.message)
+            .env("DSMR_ALERT_TIMESTAMP", alert.at.to_rfc3339());
+        let name = self.command.clone();
+        thread::spawn(move || match command.status() {
+            Ok(status) if !status.success() => {
+                log::warn!("Alert command {} exited with {}", name, status)
+            }
+            Ok(_) => {}
+            Err(msg) => log::warn!("Could not run alert command {} due to {}", name, msg),
+        });
+    }
+}
+
+struct Check {
+    breached: bool,
+    cleared: bool,
+    description: String,
+}
+
+#[derive(Default)]
+struct RuleState {
+    // Meter timestamp since when the condition holds
+    since: Option<i64>,
+    active: bool,
+    fired_at: Option<i64>,
+    // Values of the OBIS code of an `unchanged` rule
+    last_values: Option<Vec<String>>,
+}
+
+fn value_in(telegram: &Telegram, obis: &str, unit: Unit) -> Option<Decimal> {
+    telegram
+        .quantity(obis)
+        .and_then(|quantity| quantity.convert(unit).ok())
+        .map(|quantity| quantity.value)
+}
+
+fn check(rule: &AlertRule, state: &mut RuleState, telegram: &Telegram) -> Option<Check> {
+    match &rule.condition {
+        AlertCondition::Above(obis, limit) => {
+            let value = value_in(telegram, obis, limit.unit)?;
+            Some(Check {
+                breached: value > limit.value,
+                cleared: value <= limit.value - rule.hysteresis,
+                description: format!(
+                    "{} is {} (limit {})",
+                    obis,
+                    Quantity::new(value, limit.unit),
+                    limit
+                ),
+            })
+        }
+        AlertCondition::Below(obis, limit) => {
+            let value = value_in(telegram, obis, limit.unit)?;
+            Some(Check {
+                breached: value < limit.value,
+                cleared: value >= limit.value + rule.hysteresis,
+                description: format!(
+                    "{} is {} (limit {})",
+                    obis,
+                    Quantity::new(value, limit.unit),
+                    limit
+                ),
+            })
+        }
+        AlertCondition::Unchanged(obis) => {
+            let values = telegram.object(obis).map(|object| object.values.clone());
+            let changed = values.is_some() && values != state.last_values;
+            if changed {
+                state.last_values = values;
+            }
+            Some(Check {
+                breached: !changed,
+                cleared: changed,
+                description: if changed {
+                    format!("{} has a new value", obis)
+                } else {
+                    format!("{} has no new value", obis)
+                },
+            })
+        }
+    }
+}
+
+// Evaluates alert rules on each telegram, using the meter timestamp for their durations,
+// and sends the alerts that fire or resolve to the configured notifiers.
+pub struct AlertingConsumer {
+    rules: Vec<(AlertRule, RuleState)>,
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+impl AlertingConsumer {
+    pub fn new(settings: &AlertSettings, mqtt: Option<&MqttSettings>) -> Self {
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+        if let Some(url) = &settings.webhook {
+            notifiers.push(Box::new(WebhookNotifier { url: url.clone() }));
+        }
+        if let (Some(topic), Some(mqtt)) = (&settings.mqtt_topic, mqtt) {
+            // A second connection with the same client id would close the one of the MQTT sink
+            let mut mqtt = mqtt.clone();
+            mqtt.client_id.push_str("-alerts");
+            notifiers.push(Box::new(MqttNotifier {
+                topic: topic.clone(),
+                client: MqttClient::new(&mqtt),
+            }));
+        }
+        if let Some(command) = &settings.command {
+            notifiers.push(Box::new(CommandNotifier {
+                command: command.clone(),
+            }));
+        }
+
+        AlertingConsumer {
+            rules: settings
+                .rules
+                .iter()
+                .map(|rule| (rule.clone(), RuleState::default()))
+                .collect(),
+            notifiers,
+        }
+    }
+
+    fn evaluate(&mut self, telegram: &Telegram) -> Vec<Alert> {
+        let at = match telegram.timestamp() {
+            Some(at) => at,
+            None => return Vec::new(),
+        };
+        let now = at.timestamp();
+
+        let mut alerts = Vec::new();
+        for (rule, state) in &mut self.rules {
+            let check = match check(rule, state, telegram) {
+                Some(check) => check,
+                None => continue,
+            };
+            let alert = |state: AlertState| Alert {
+                rule: rule.name.clone(),
+                state,
+                message: check.description.clone(),
+                at,
+            };
+
+            if state.active {
+                if check.cleared {
+                    state.active = false;
+                    state.since = None;
+                    alerts.push(alert(AlertState::Resolved));
+                }
+            } else if check.breached {
+                let since = *state.since.get_or_insert(now);
+                let cooled_down = state
+                    .fired_at
+                    .is_none_or(|fired_at| now - fired_at >= rule.cooldown as i64);
+                if now - since >= rule.duration as i64 && cooled_down {
+                    state.active = true;
+                    state.fired_at = Some(now);
+                    alerts.push(alert(AlertState::Firing));
+                }
+            } else {
+                state.since = None;
+            }
+        }
+        alerts
+    }
+}
+impl TelegramConsumer for AlertingConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        for alert in self.evaluate(telegram) {
+            match alert.state {
+                AlertState::Firing => log::warn!("Alert {}: {}", alert.rule, alert.message),
+                AlertState::Resolved => {
+                    log::info!("Alert {} resolved: {}", alert.rule, alert.message)
+                }
+            }
+            for notifier in &mut self.notifiers {
+                notifier.notify(&alert);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use crate::dsmr::settings::read_alert_rule;
+
+    fn consumer(rule: &str) -> AlertingConsumer {
+        let settings = AlertSettings {
+            rules: vec![read_alert_rule(rule).unwrap()],
+            webhook: None,
+            mqtt_topic: None,
+            command: None,
+        };
+        AlertingConsumer::new(&settings, None)
+    }
+
+    fn telegram(timestamp: &str, current: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n1-0:31.7.0({}*A)\r\n!\r\n",
+            timestamp, current
+        ))
+    }
+
+    fn states(alerts: Vec<Alert>) -> Vec<AlertState> {
+        alerts.iter().map(|alert| alert.state).collect()
+    }
+
+    #[test]
+    fn fire_after_duration_and_resolve_with_hysteresis() {
+        let mut consumer = consumer("fuse=1-0:31.7.0>25*A|for=60|hysteresis=2");
+
+        assert!(consumer
+            .evaluate(&telegram("231026204000S", "030"))
+            .is_empty());
+        assert!(consumer
+            .evaluate(&telegram("231026204030S", "030"))
+            .is_empty());
+        let alerts = consumer.evaluate(&telegram("231026204100S", "026"));
+        assert_eq!(states(alerts.clone()), vec![AlertState::Firing]);
+        assert_eq!(alerts[0].rule, "fuse");
+        assert_eq!(alerts[0].message, "1-0:31.7.0 is 26 A (limit 25 A)");
+
+        // Within the hysteresis, the alert stays active
+        assert!(consumer
+            .evaluate(&telegram("231026204130S", "024"))
+            .is_empty());
+        assert_eq!(
+            states(consumer.evaluate(&telegram("231026204200S", "023"))),
+            vec![AlertState::Resolved]
+        );
+    }
+
+    #[test]
+    fn condition_must_hold_for_whole_duration() {
+        let mut consumer = consumer("fuse=1-0:31.7.0>25*A|for=60");
+
+        consumer.evaluate(&telegram("231026204000S", "030"));
+        consumer.evaluate(&telegram("231026204030S", "020"));
+
+        assert!(consumer
+            .evaluate(&telegram("231026204100S", "030"))
+            .is_empty());
+        assert_eq!(
+            states(consumer.evaluate(&telegram("231026204200S", "030"))),
+            vec![AlertState::Firing]
+        );
+    }
+
+    #[test]
+    fn do_not_fire_again_during_cooldown() {
+        let mut consumer = consumer("fuse=1-0:31.7.0>25*A|cooldown=600");
+
+        assert_eq!(
+            states(consumer.evaluate(&telegram("231026204000S", "030"))),
+            vec![AlertState::Firing]
+        );
+        assert_eq!(
+            states(consumer.evaluate(&telegram("231026204100S", "020"))),
+            vec![AlertState::Resolved]
+        );
+        assert!(consumer
+            .evaluate(&telegram("231026204200S", "030"))
+            .is_empty());
+        assert_eq!(
+            states(consumer.evaluate(&telegram("231026205000S", "030"))),
+            vec![AlertState::Firing]
+        );
+    }
+
+    #[test]
+    fn fire_when_value_does_not_change() {
+        let mut consumer = consumer("no_gas=unchanged(0-1:24.2.1)|for=7200");
+        let gas = |timestamp: &str, captured: &str| {
+            Telegram::parse(&format!(
+                "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n0-1:24.2.1({})(00004.381*m3)\r\n!\r\n",
+                timestamp, captured
+            ))
+        };
+
+        assert!(consumer
+            .evaluate(&gas("231026200000S", "231026200000S"))
+            .is_empty());
+        assert!(consumer
+            .evaluate(&gas("231026200010S", "231026200000S"))
+            .is_empty());
+        assert!(consumer
+            .evaluate(&gas("231026210000S", "231026200000S"))
+            .is_empty());
+        let alerts = consumer.evaluate(&gas("231026220010S", "231026200000S"));
+        assert_eq!(states(alerts.clone()), vec![AlertState::Firing]);
+        assert_eq!(alerts[0].message, "0-1:24.2.1 has no new value");
+
+        assert_eq!(
+            states(consumer.evaluate(&gas("231026221000S", "231026221000S"))),
+            vec![AlertState::Resolved]
+        );
+    }
+
+    #[test]
+    fn alert_as_json() {
+        let alert = Alert {
+            rule: String::from("fuse"),
+            state: AlertState::Firing,
+            message: String::from("1-0:31.7.0 is 26 A (limit 25 A)"),
+            at: crate::dsmr::timestamp::parse_timestamp("231026204100S").unwrap(),
+        };
+
+        assert_eq!(
+            alert.to_json(),
+            r#"{"message":"1-0:31.7.0 is 26 A (limit 25 A)","rule":"fuse","state":"firing","timestamp":"2023-10-26T20:41:00+02:00"}"#
+        );
+    }
+}
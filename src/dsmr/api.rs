@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chrono::DateTime;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use super::json;
+use super::settings::HttpSettings;
+use super::status::Health;
+use super::telegram::Telegram;
+use super::TelegramConsumer;
+
+struct Reading {
+    // Meter timestamp, if the telegram has one
+    timestamp: Option<i64>,
+    json: Value,
+}
+
+pub struct ApiState {
+    latest: Option<Telegram>,
+    history: VecDeque<Reading>,
+    capacity: usize,
+    received: u64,
+}
+impl ApiState {
+    fn new(capacity: usize) -> Self {
+        ApiState {
+            latest: None,
+            history: VecDeque::with_capacity(capacity),
+            capacity,
+            received: 0,
+        }
+    }
+
+    fn add(&mut self, telegram: &Telegram) {
+        if self.capacity > 0 {
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(Reading {
+                timestamp: telegram.timestamp().map(|timestamp| timestamp.timestamp()),
+                json: json::reading(telegram),
+            });
+        }
+        self.latest = Some(telegram.clone());
+        self.received += 1;
+    }
+}
+
+struct Answer {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+impl Answer {
+    fn json(body: Value) -> Self {
+        Answer {
+            status: 200,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Answer {
+            status,
+            content_type: "application/json",
+            body: json!({ "error": message }).to_string(),
+        }
+    }
+}
+
+// Decodes `%XX` escapes. A `+` is kept, as it is more likely part of a time zone offset than a space.
+pub fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = value
+            .get(index + 1..index + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn query_timestamp(query: &str, name: &str) -> Result<Option<i64>, String> {
+    let value = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| url_decode(value));
+    match value {
+        Some(value) if !value.is_empty() => DateTime::parse_from_rfc3339(&value)
+            .map(|timestamp| Some(timestamp.timestamp()))
+            .map_err(|_| format!("Parameter {} is not an RFC 3339 timestamp", name)),
+        _ => Ok(None),
+    }
+}
+
+fn history(state: &ApiState, query: &str) -> Answer {
+    let (from, to) = match (query_timestamp(query, "from"), query_timestamp(query, "to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(msg), _) | (_, Err(msg)) => return Answer::error(400, &msg),
+    };
+
+    let readings: Vec<&Value> = state
+        .history
+        .iter()
+        .filter(|reading| match reading.timestamp {
+            Some(timestamp) => {
+                from.is_none_or(|from| from <= timestamp) && to.is_none_or(|to| timestamp <= to)
+            }
+            None => from.is_none() && to.is_none(),
+        })
+        .map(|reading| &reading.json)
+        .collect();
+    Answer::json(json!(readings))
+}
+
+fn status(state: &ApiState, health: &Health) -> Answer {
+    let sinks: serde_json::Map<String, Value> = health
+        .snapshot()
+        .into_iter()
+        .map(|(name, status)| {
+            let status = json!({
+                "healthy": status.healthy(),
+                "last_success": status.last_success.map(|at| at.to_rfc3339()),
+                "last_failure": status.last_failure.map(|at| at.to_rfc3339()),
+                "last_error": status.last_error,
+                "failures": status.failures,
+            });
+            (name, status)
+        })
+        .collect();
+
+    Answer::json(json!({
+        "telegrams": state.received,
+        "last_telegram": state
+            .latest
+            .as_ref()
+            .and_then(Telegram::timestamp)
+            .map(|timestamp| timestamp.to_rfc3339()),
+        "history": state.history.len(),
+        "sinks": sinks,
+    }))
+}
+
+fn answer(state: &ApiState, health: &Health, method: &Method, url: &str) -> Answer {
+    if *method != Method::Get {
+        return Answer::error(405, "Only GET is supported");
+    }
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+
+    match (path, &state.latest) {
+        ("/telegram/latest", Some(telegram)) => Answer {
+            status: 200,
+            content_type: "text/plain",
+            body: telegram.raw.clone(),
+        },
+        ("/reading/latest", Some(telegram)) => Answer::json(json::reading(telegram)),
+        ("/telegram/latest" | "/reading/latest", None) => {
+            Answer::error(404, "No telegram was read yet")
+        }
+        ("/reading/history", _) => history(state, query),
+        ("/status", _) => status(state, health),
+        _ => Answer::error(404, "Not found"),
+    }
+}
+
+fn serve(server: Server, state: Arc<Mutex<ApiState>>, health: Health) {
+    for request in server.incoming_requests() {
+        let answer = {
+            let state = state.lock().unwrap();
+            answer(&state, &health, request.method(), request.url())
+        };
+        let content_type = Header::from_bytes("Content-Type", answer.content_type).unwrap();
+        let response = Response::from_string(answer.body)
+            .with_status_code(answer.status)
+            .with_header(content_type);
+        if let Err(msg) = request.respond(response) {
+            log::debug!("Could not answer HTTP request due to {}", msg);
+        }
+    }
+}
+
+// Keeps the latest telegram and a history of readings, and serves them over HTTP from a
+// separate thread, together with the status of the other sinks.
+pub struct ApiConsumer {
+    state: Arc<Mutex<ApiState>>,
+}
+impl ApiConsumer {
+    pub fn start(settings: &HttpSettings, health: Health) -> Result<Self, String> {
+        let server = Server::http(&settings.address).map_err(|msg| {
+            format!(
+                "Could not serve HTTP API on {} due to {}",
+                settings.address, msg
+            )
+        })?;
+        log::info!("Serving HTTP API on {}", settings.address);
+
+        let state = Arc::new(Mutex::new(ApiState::new(settings.history)));
+        let shared = state.clone();
+        thread::spawn(move || serve(server, shared, health));
+        Ok(ApiConsumer { state })
+    }
+}
+impl TelegramConsumer for ApiConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        self.state.lock().unwrap().add(telegram);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn telegram(timestamp: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n1-0:1.7.0(00.302*kW)\r\n!\r\n",
+            timestamp
+        ))
+    }
+
+    fn get(state: &ApiState, url: &str) -> Answer {
+        answer(state, &Health::default(), &Method::Get, url)
+    }
+
+    #[test]
+    fn answer_latest_telegram_and_reading() {
+        let mut state = ApiState::new(10);
+        assert_eq!(get(&state, "/telegram/latest").status, 404);
+
+        state.add(&telegram("231026204015S"));
+        let raw = get(&state, "/telegram/latest");
+        assert_eq!(raw.content_type, "text/plain");
+        assert_eq!(raw.body, telegram("231026204015S").raw);
+
+        let reading: Value = serde_json::from_str(&get(&state, "/reading/latest").body).unwrap();
+        assert_eq!(reading["values"]["1-0:1.7.0"]["value"], 0.302);
+    }
+
+    #[test]
+    fn answer_history_between_timestamps() {
+        let mut state = ApiState::new(2);
+        state.add(&telegram("231026204010S"));
+        state.add(&telegram("231026204015S"));
+        state.add(&telegram("231026204020S"));
+
+        let all: Value = serde_json::from_str(&get(&state, "/reading/history").body).unwrap();
+        assert_eq!(all.as_array().unwrap().len(), 2);
+
+        let answer = get(
+            &state,
+            "/reading/history?from=2023-10-26T20:40:16%2B02:00&to=2023-10-26T18:40:30Z",
+        );
+        let filtered: Value = serde_json::from_str(&answer.body).unwrap();
+        assert_eq!(filtered.as_array().unwrap().len(), 1);
+        assert_eq!(filtered[0]["timestamp"], "2023-10-26T20:40:20+02:00");
+
+        assert_eq!(get(&state, "/reading/history?from=yesterday").status, 400);
+    }
+
+    #[test]
+    fn answer_status_of_sinks() {
+        let mut state = ApiState::new(10);
+        state.add(&telegram("231026204015S"));
+        let health = Health::default();
+        health.failure("mqtt://localhost:1883", "connection refused");
+
+        let answer = answer(&state, &health, &Method::Get, "/status");
+        let status: Value = serde_json::from_str(&answer.body).unwrap();
+
+        assert_eq!(status["telegrams"], 1);
+        assert_eq!(status["last_telegram"], "2023-10-26T20:40:15+02:00");
+        assert_eq!(status["sinks"]["mqtt://localhost:1883"]["healthy"], false);
+        assert_eq!(status["sinks"]["mqtt://localhost:1883"]["failures"], 1);
+    }
+
+    #[test]
+    fn reject_unknown_requests() {
+        let state = ApiState::new(10);
+
+        assert_eq!(get(&state, "/readings").status, 404);
+        assert_eq!(
+            answer(&state, &Health::default(), &Method::Post, "/status").status,
+            405
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+
+use super::settings::ClockSettings;
+use super::telegram::Telegram;
+
+// Compares the clock of the meter with the clock of the host and warns when they drift apart.
+// Downstream databases aggregate on the meter timestamp, so a drifting clock breaks aggregation.
+pub struct ClockDriftConsumer {
+    threshold: Duration,
+    drifting: bool,
+}
+impl ClockDriftConsumer {
+    pub fn new(settings: &ClockSettings) -> Self {
+        ClockDriftConsumer {
+            threshold: Duration::seconds(settings.drift_threshold as i64),
+            drifting: false,
+        }
+    }
+
+    // Returns the drift (host time minus meter time) when it exceeds the threshold.
+    fn check(&mut self, meter_time: DateTime<Tz>, host_time: DateTime<Utc>) -> Option<Duration> {
+        let drift = host_time.signed_duration_since(meter_time);
+
+        if drift.abs() > self.threshold {
+            if !self.drifting {
+                log::warn!(
+                    "Meter clock ({}) is {} seconds off from host clock ({})",
+                    meter_time,
+                    drift.num_seconds(),
+                    host_time
+                );
+            }
+            self.drifting = true;
+            Some(drift)
+        } else {
+            if self.drifting {
+                log::info!("Meter clock is back in sync with host clock");
+            }
+            self.drifting = false;
+            None
+        }
+    }
+}
+impl super::TelegramConsumer for ClockDriftConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        if let Some(meter_time) = telegram.timestamp() {
+            self.check(meter_time, Utc::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use crate::dsmr::timestamp::parse_timestamp;
+    use chrono::TimeZone;
+
+    fn consumer(drift_threshold: u32) -> ClockDriftConsumer {
+        ClockDriftConsumer::new(&ClockSettings { drift_threshold })
+    }
+
+    #[test]
+    fn no_drift_within_threshold() {
+        let mut consumer = consumer(30);
+        let meter_time = parse_timestamp("231026204015S").unwrap();
+        let host_time = Utc.with_ymd_and_hms(2023, 10, 26, 18, 40, 40).unwrap();
+
+        assert_eq!(consumer.check(meter_time, host_time), None);
+    }
+
+    #[test]
+    fn drift_when_host_is_ahead() {
+        let mut consumer = consumer(30);
+        let meter_time = parse_timestamp("231026204015S").unwrap();
+        let host_time = Utc.with_ymd_and_hms(2023, 10, 26, 18, 41, 15).unwrap();
+
+        assert_eq!(
+            consumer.check(meter_time, host_time),
+            Some(Duration::seconds(60))
+        );
+    }
+
+    #[test]
+    fn drift_when_meter_is_ahead() {
+        let mut consumer = consumer(30);
+        let meter_time = parse_timestamp("231026204015S").unwrap();
+        let host_time = Utc.with_ymd_and_hms(2023, 10, 26, 18, 39, 15).unwrap();
+
+        assert_eq!(
+            consumer.check(meter_time, host_time),
+            Some(Duration::seconds(-60))
+        );
+    }
+
+    #[test]
+    fn drift_recovers() {
+        let mut consumer = consumer(30);
+        let meter_time = parse_timestamp("231026204015S").unwrap();
+
+        let drifted = Utc.with_ymd_and_hms(2023, 10, 26, 18, 45, 0).unwrap();
+        assert!(consumer.check(meter_time, drifted).is_some());
+        assert!(consumer.drifting);
+
+        let in_sync = Utc.with_ymd_and_hms(2023, 10, 26, 18, 40, 15).unwrap();
+        assert!(consumer.check(meter_time, in_sync).is_none());
+        assert!(!consumer.drifting);
+    }
+}
@@ -0,0 +1,659 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::SystemTime;
+
+use chrono::{DateTime, NaiveDate};
+
+use super::settings::{CostSettings, TariffPeriod};
+use super::telegram::Telegram;
+use super::value::{Decimal, Unit};
+use super::TelegramConsumer;
+
+const IMPORT: [&str; 2] = ["1-0:1.8.1", "1-0:1.8.2"];
+const EXPORT: [&str; 2] = ["1-0:2.8.1", "1-0:2.8.2"];
+const HOUR: i64 = 3_600;
+// Costs are attached to telegrams in whole cents
+const CURRENCY_SCALE: u32 = 2;
+// The state is saved at every day boundary, and otherwise at most once per this many seconds
+const SAVE_INTERVAL: i64 = 300;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodCosts {
+    // The day (2023-10-26) or month (2023-10) these costs are for
+    pub period: String,
+    // Costs of energy delivered to the client
+    pub import: Decimal,
+    // Revenue of energy delivered by the client
+    pub export: Decimal,
+    pub gas: Decimal,
+    pub fixed: Decimal,
+}
+impl PeriodCosts {
+    fn new(period: String) -> Self {
+        let zero = Decimal::new(0, 0);
+        PeriodCosts {
+            period,
+            import: zero,
+            export: zero,
+            gas: zero,
+            fixed: zero,
+        }
+    }
+
+    pub fn total(&self) -> Decimal {
+        self.import + self.gas + self.fixed - self.export
+    }
+
+    fn rounded(&self) -> Self {
+        PeriodCosts {
+            period: self.period.clone(),
+            import: self.import.with_scale(CURRENCY_SCALE),
+            export: self.export.with_scale(CURRENCY_SCALE),
+            gas: self.gas.with_scale(CURRENCY_SCALE),
+            fixed: self.fixed.with_scale(CURRENCY_SCALE),
+        }
+    }
+}
+
+// Running costs of the current day and month.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Costs {
+    pub day: PeriodCosts,
+    pub month: PeriodCosts,
+}
+
+#[derive(Clone)]
+struct Readings {
+    import: [Option<Decimal>; 2],
+    export: [Option<Decimal>; 2],
+    gas: Option<Decimal>,
+}
+
+fn reading(telegram: &Telegram, obis: &str, unit: Unit) -> Option<Decimal> {
+    telegram
+        .quantity(obis)
+        .and_then(|quantity| quantity.convert(unit).ok())
+        .map(|quantity| quantity.value)
+}
+
+fn format_value(value: Option<Decimal>) -> String {
+    value.map_or_else(|| String::from("-"), |value| value.to_string())
+}
+
+fn parse_value(value: &str) -> Option<Decimal> {
+    Decimal::parse(value).ok()
+}
+
+// The state file has a line for the day, the month and the last counters, such as
+// `day 2023-10-26 0.60 0.10 1.40 0.95` (import, export, gas and fixed costs) and
+// `counters 32.159 2.167 2.376 0.000 4.381`, with `-` for counters the meter does not report.
+fn parse_state(content: &str) -> (Option<PeriodCosts>, Option<PeriodCosts>, Option<Readings>) {
+    let (mut day, mut month, mut readings) = (None, None, None);
+    for line in content.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["day" | "month", period, import, export, gas, fixed] => {
+                let values = [import, export, gas, fixed].map(|value| parse_value(value));
+                let costs = match values {
+                    [Some(import), Some(export), Some(gas), Some(fixed)] => PeriodCosts {
+                        period: String::from(*period),
+                        import,
+                        export,
+                        gas,
+                        fixed,
+                    },
+                    _ => continue,
+                };
+                if parts[0] == "day" {
+                    day = Some(costs);
+                } else {
+                    month = Some(costs);
+                }
+            }
+            ["counters", import1, import2, export1, export2, gas] => {
+                readings = Some(Readings {
+                    import: [parse_value(import1), parse_value(import2)],
+                    export: [parse_value(export1), parse_value(export2)],
+                    gas: parse_value(gas),
+                })
+            }
+            _ => log::debug!("Ignoring line of cost state: {}", line),
+        }
+    }
+    (day, month, readings)
+}
+
+impl Readings {
+    fn from(telegram: &Telegram) -> Self {
+        Readings {
+            import: IMPORT.map(|obis| reading(telegram, obis, Unit::KiloWattHour)),
+            export: EXPORT.map(|obis| reading(telegram, obis, Unit::KiloWattHour)),
+            gas: telegram.gas_reading().map(|(_, reading)| reading),
+        }
+    }
+}
+
+// Returns how much a counter increased, ignoring counters that went back (e.g. a replaced meter).
+fn delta(previous: Option<Decimal>, current: Option<Decimal>) -> Option<Decimal> {
+    match (previous, current) {
+        (Some(previous), Some(current)) if current >= previous => Some(current - previous),
+        (Some(previous), Some(current)) => {
+            log::info!(
+                "Counter went back from {} to {}, ignoring it for costs",
+                previous,
+                current
+            );
+            None
+        }
+        _ => None,
+    }
+}
+
+// Hourly prices, read from a CSV file with lines such as `2024-01-01T00:00:00+01:00,0.2134,0.1000`.
+// The columns are the start of the hour, the import price and optionally the export price.
+struct DynamicPrices {
+    path: Option<String>,
+    modified: Option<SystemTime>,
+    checked_hour: Option<i64>,
+    prices: HashMap<i64, (Decimal, Option<Decimal>)>,
+}
+impl DynamicPrices {
+    fn parse_line(line: &str) -> Option<(i64, (Decimal, Option<Decimal>))> {
+        let mut columns = line.split(',').map(str::trim);
+        let hour = DateTime::parse_from_rfc3339(columns.next()?).ok()?;
+        let import = Decimal::parse(columns.next()?).ok()?;
+        let export = columns.next().and_then(|price| Decimal::parse(price).ok());
+        Some((hour.timestamp(), (import, export)))
+    }
+
+    // Reads the file again when it changed, at most once per hour.
+    fn reload(&mut self, hour: i64) {
+        let path = match &self.path {
+            Some(path) if self.checked_hour != Some(hour) => path,
+            _ => return,
+        };
+        self.checked_hour = Some(hour);
+
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+        if modified.as_ref().ok() == self.modified.as_ref() {
+            return;
+        }
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                self.prices = content.lines().filter_map(Self::parse_line).collect();
+                self.modified = modified.ok();
+                log::info!("Read {} hourly prices from {}", self.prices.len(), path);
+            }
+            Err(msg) => log::warn!("Could not read hourly prices from {} due to {}", path, msg),
+        }
+    }
+
+    fn price_at(&mut self, timestamp: i64) -> Option<(Decimal, Option<Decimal>)> {
+        let hour = timestamp - timestamp.rem_euclid(HOUR);
+        self.reload(hour);
+        self.prices.get(&hour).copied()
+    }
+}
+
+// Calculates the running costs of the current day and month, and attaches them to each telegram.
+// With a state file the costs survive a restart.
+pub struct CostConsumer {
+    tariffs: Vec<TariffPeriod>,
+    dynamic_prices: DynamicPrices,
+    state_file: Option<String>,
+    previous: Option<Readings>,
+    day: Option<PeriodCosts>,
+    month: Option<PeriodCosts>,
+    saved_at: Option<i64>,
+    // Whether the counters were read from the state file and no telegram was handled yet
+    restored: bool,
+    downstream: Box<dyn TelegramConsumer>,
+}
+impl CostConsumer {
+    pub fn new(settings: CostSettings, downstream: Box<dyn TelegramConsumer>) -> Self {
+        let (day, month, previous) = match &settings.state_file {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(content) => parse_state(&content),
+                Err(msg) => {
+                    log::info!("Not reading costs from {}: {}", path, msg);
+                    (None, None, None)
+                }
+            },
+            None => (None, None, None),
+        };
+
+        CostConsumer {
+            tariffs: settings.tariffs,
+            dynamic_prices: DynamicPrices {
+                path: settings.dynamic_prices,
+                modified: None,
+                checked_hour: None,
+                prices: HashMap::new(),
+            },
+            state_file: settings.state_file,
+            restored: previous.is_some(),
+            previous,
+            day,
+            month,
+            saved_at: None,
+            downstream,
+        }
+    }
+
+    fn save(&self) {
+        let path = match &self.state_file {
+            Some(path) => path,
+            None => return,
+        };
+        let mut content = String::new();
+        for (name, costs) in [("day", &self.day), ("month", &self.month)] {
+            if let Some(costs) = costs {
+                content.push_str(&format!(
+                    "{} {} {} {} {} {}\n",
+                    name, costs.period, costs.import, costs.export, costs.gas, costs.fixed
+                ));
+            }
+        }
+        if let Some(readings) = &self.previous {
+            content.push_str(&format!(
+                "counters {} {} {} {} {}\n",
+                format_value(readings.import[0]),
+                format_value(readings.import[1]),
+                format_value(readings.export[0]),
+                format_value(readings.export[1]),
+                format_value(readings.gas)
+            ));
+        }
+        // Replace the file at once, so it is never left half written
+        let temporary = format!("{}.tmp", path);
+        if let Err(msg) = fs::write(&temporary, content).and_then(|_| fs::rename(&temporary, path))
+        {
+            log::warn!("Could not save costs to {} due to {}", path, msg);
+        }
+    }
+
+    fn tariff(&self, date: NaiveDate) -> Option<&TariffPeriod> {
+        self.tariffs.iter().find(|tariff| {
+            tariff.from.is_none_or(|from| from <= date) && tariff.to.is_none_or(|to| date <= to)
+        })
+    }
+
+    fn calculate(&mut self, telegram: &Telegram) -> Option<Costs> {
+        let timestamp = telegram.timestamp()?;
+        let date = timestamp.date_naive();
+        let zero = Decimal::new(0, 0);
+
+        let (import, export, gas, daily) = match self.tariff(date) {
+            Some(tariff) => (tariff.import, tariff.export, tariff.gas, tariff.daily),
+            None => ([zero, zero], [zero, zero], zero, zero),
+        };
+        let (import, export) = match self.dynamic_prices.price_at(timestamp.timestamp()) {
+            Some((hourly_import, Some(hourly_export))) => ([hourly_import; 2], [hourly_export; 2]),
+            Some((hourly_import, None)) => ([hourly_import; 2], export),
+            None => (import, export),
+        };
+
+        let day = date.format("%Y-%m-%d").to_string();
+        let month = date.format("%Y-%m").to_string();
+
+        // After a restart into another day or month, the usage since the saved counters can
+        // not be split over the periods, so it is not counted for the new period
+        let restored = std::mem::take(&mut self.restored);
+        let mut changed = false;
+        let mut count_month = true;
+        let mut count_day = true;
+        if self.month.as_ref().map(|costs| &costs.period) != Some(&month) {
+            self.month = Some(PeriodCosts::new(month));
+            changed = true;
+            count_month = !restored;
+        }
+        if self.day.as_ref().map(|costs| &costs.period) != Some(&day) {
+            if let Some(previous) = &self.day {
+                let previous = previous.rounded();
+                log::info!(
+                    "Costs for {}: {} (import {}, export {}, gas {}, fixed {})",
+                    previous.period,
+                    previous.total(),
+                    previous.import,
+                    previous.export,
+                    previous.gas,
+                    previous.fixed
+                );
+            }
+            let mut costs = PeriodCosts::new(day);
+            costs.fixed = daily;
+            self.day = Some(costs);
+            if let Some(month) = &mut self.month {
+                month.fixed = month.fixed + daily;
+            }
+            changed = true;
+            count_day = !restored;
+        }
+
+        let current = Readings::from(telegram);
+        if let Some(previous) = self.previous.replace(current.clone()) {
+            let mut costs = PeriodCosts::new(String::new());
+            for tariff in 0..2 {
+                if let Some(delta) = delta(previous.import[tariff], current.import[tariff]) {
+                    costs.import = costs.import + delta * import[tariff];
+                }
+                if let Some(delta) = delta(previous.export[tariff], current.export[tariff]) {
+                    costs.export = costs.export + delta * export[tariff];
+                }
+            }
+            if let Some(delta) = delta(previous.gas, current.gas) {
+                costs.gas = delta * gas;
+            }
+
+            for (period, count) in [(&mut self.day, count_day), (&mut self.month, count_month)] {
+                if let (Some(period), true) = (period.as_mut(), count) {
+                    period.import = period.import + costs.import;
+                    period.export = period.export + costs.export;
+                    period.gas = period.gas + costs.gas;
+                }
+            }
+        }
+
+        let timestamp = timestamp.timestamp();
+        if changed
+            || self
+                .saved_at
+                .is_none_or(|saved_at| timestamp - saved_at >= SAVE_INTERVAL)
+        {
+            self.save();
+            self.saved_at = Some(timestamp);
+        }
+
+        match (&self.day, &self.month) {
+            (Some(day), Some(month)) => Some(Costs {
+                day: day.rounded(),
+                month: month.rounded(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl TelegramConsumer for CostConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        match self.calculate(telegram) {
+            Some(costs) => {
+                let mut result = telegram.clone();
+                result.costs = Some(costs);
+                self.downstream.consume(&result);
+            }
+            None => self.downstream.consume(telegram),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    struct NoopConsumer {}
+    impl TelegramConsumer for NoopConsumer {
+        fn consume(&mut self, _telegram: &Telegram) {}
+    }
+
+    fn price(value: &str) -> Decimal {
+        Decimal::parse(value).unwrap()
+    }
+
+    fn consumer(dynamic_prices: Option<String>) -> CostConsumer {
+        consumer_with_state(dynamic_prices, None)
+    }
+
+    fn consumer_with_state(
+        dynamic_prices: Option<String>,
+        state_file: Option<String>,
+    ) -> CostConsumer {
+        let settings = CostSettings {
+            tariffs: vec![TariffPeriod {
+                from: NaiveDate::from_ymd_opt(2023, 1, 1),
+                to: None,
+                import: [price("0.30"), price("0.25")],
+                export: [price("0.10"), price("0.08")],
+                daily: price("0.95"),
+                gas: price("1.40"),
+            }],
+            dynamic_prices,
+            state_file,
+        };
+        CostConsumer::new(settings, Box::new(NoopConsumer {}))
+    }
+
+    fn telegram(timestamp: &str, import1: &str, export1: &str, gas: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n1-0:1.8.1({}*kWh)\r\n1-0:1.8.2(000002.167*kWh)\r\n1-0:2.8.1({}*kWh)\r\n1-0:2.8.2(000000.000*kWh)\r\n0-1:24.2.1(231026204004S)({}*m3)\r\n!\r\n",
+            timestamp, import1, export1, gas
+        ))
+    }
+
+    #[test]
+    fn running_costs_of_day_and_month() {
+        let mut consumer = consumer(None);
+
+        let first = consumer
+            .calculate(&telegram(
+                "231026204015S",
+                "000032.159",
+                "000002.376",
+                "00004.381",
+            ))
+            .unwrap();
+        assert_eq!(first.day.period, "2023-10-26");
+        assert_eq!(first.day.total(), price("0.95"));
+
+        let second = consumer
+            .calculate(&telegram(
+                "231026214015S",
+                "000034.159",
+                "000003.376",
+                "00005.381",
+            ))
+            .unwrap();
+        assert_eq!(second.day.import, price("0.60"));
+        assert_eq!(second.day.export, price("0.10"));
+        assert_eq!(second.day.gas, price("1.40"));
+        assert_eq!(second.day.total(), price("2.85"));
+        assert_eq!(
+            second.month,
+            PeriodCosts {
+                period: String::from("2023-10"),
+                ..second.day.clone()
+            }
+        );
+    }
+
+    #[test]
+    fn start_new_day() {
+        let mut consumer = consumer(None);
+
+        consumer.calculate(&telegram(
+            "231026204015S",
+            "000032.159",
+            "000002.376",
+            "00004.381",
+        ));
+        consumer.calculate(&telegram(
+            "231026214015S",
+            "000034.159",
+            "000002.376",
+            "00004.381",
+        ));
+        let result = consumer
+            .calculate(&telegram(
+                "231027004015S",
+                "000035.159",
+                "000002.376",
+                "00004.381",
+            ))
+            .unwrap();
+
+        assert_eq!(result.day.period, "2023-10-27");
+        assert_eq!(result.day.import, price("0.30"));
+        assert_eq!(result.day.fixed, price("0.95"));
+        assert_eq!(result.month.import, price("0.90"));
+        assert_eq!(result.month.fixed, price("1.90"));
+    }
+
+    #[test]
+    fn ignore_counters_going_back() {
+        let mut consumer = consumer(None);
+
+        consumer.calculate(&telegram(
+            "231026204015S",
+            "000032.159",
+            "000002.376",
+            "00004.381",
+        ));
+        let result = consumer
+            .calculate(&telegram(
+                "231026214015S",
+                "000000.159",
+                "000002.376",
+                "00004.381",
+            ))
+            .unwrap();
+
+        assert_eq!(result.day.import, price("0"));
+    }
+
+    #[test]
+    fn restore_costs_after_restart() {
+        let path = std::env::temp_dir().join(format!("dsmr-rs-costs-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        let mut first = consumer_with_state(None, Some(path.clone()));
+        first.calculate(&telegram(
+            "231026204015S",
+            "000032.159",
+            "000002.376",
+            "00004.381",
+        ));
+        first.calculate(&telegram(
+            "231026214015S",
+            "000034.159",
+            "000002.376",
+            "00004.381",
+        ));
+        first.save();
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+        let mut second = consumer_with_state(None, Some(path.clone()));
+        let result = second
+            .calculate(&telegram(
+                "231026224015S",
+                "000035.159",
+                "000002.376",
+                "00004.381",
+            ))
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.day.import, price("0.90"));
+        assert_eq!(result.day.fixed, price("0.95"));
+        assert_eq!(result.month.import, price("0.90"));
+    }
+
+    #[test]
+    fn restart_into_new_day_starts_from_counters() {
+        let path =
+            std::env::temp_dir().join(format!("dsmr-rs-costs-day-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        let mut first = consumer_with_state(None, Some(path.clone()));
+        first.calculate(&telegram(
+            "231026204015S",
+            "000032.159",
+            "000002.376",
+            "00004.381",
+        ));
+        first.calculate(&telegram(
+            "231026214015S",
+            "000034.159",
+            "000002.376",
+            "00004.381",
+        ));
+        first.save();
+
+        let mut second = consumer_with_state(None, Some(path.clone()));
+        let restarted = second
+            .calculate(&telegram(
+                "231027080000S",
+                "000036.159",
+                "000002.376",
+                "00004.381",
+            ))
+            .unwrap();
+        let next = second
+            .calculate(&telegram(
+                "231027090000S",
+                "000037.159",
+                "000002.376",
+                "00004.381",
+            ))
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Usage while the logger was down is not booked to the new day, but is to the month
+        assert_eq!(restarted.day.period, "2023-10-27");
+        assert_eq!(restarted.day.import, price("0"));
+        assert_eq!(restarted.month.import, price("1.20"));
+        assert_eq!(restarted.month.fixed, price("1.90"));
+        assert_eq!(next.day.import, price("0.30"));
+    }
+
+    #[test]
+    fn no_costs_without_timestamp() {
+        let mut consumer = consumer(None);
+
+        let result = consumer.calculate(&Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n1-0:1.8.1(000032.159*kWh)\r\n!\r\n",
+        ));
+
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn hourly_prices_override_tariffs() {
+        let path = std::env::temp_dir().join(format!("dsmr-rs-prices-{}.csv", std::process::id()));
+        fs::write(
+            &path,
+            "hour,import,export\n2023-10-26T20:00:00+02:00,0.50\n2023-10-26T21:00:00+02:00,0.40,0.20\n",
+        )
+        .unwrap();
+        let mut consumer = consumer(Some(String::from(path.to_str().unwrap())));
+
+        consumer.calculate(&telegram(
+            "231026204015S",
+            "000032.159",
+            "000002.376",
+            "00004.381",
+        ));
+        let first = consumer
+            .calculate(&telegram(
+                "231026205015S",
+                "000033.159",
+                "000003.376",
+                "00004.381",
+            ))
+            .unwrap();
+        let second = consumer
+            .calculate(&telegram(
+                "231026210015S",
+                "000034.159",
+                "000004.376",
+                "00004.381",
+            ))
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(first.day.import, price("0.50"));
+        assert_eq!(first.day.export, price("0.10"));
+        assert_eq!(second.day.import, price("0.90"));
+        assert_eq!(second.day.export, price("0.30"));
+    }
+}
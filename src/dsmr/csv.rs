@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use super::settings::CsvSettings;
+use super::telegram::Telegram;
+use super::TelegramConsumer;
+
+// Rows that could not be written are kept up to this number, and written when the file can
+// be opened again.
+const MAX_PENDING: usize = 3_600;
+
+// Quotes a field when it contains the delimiter, a quote or a line break.
+fn quote(field: &str, delimiter: char) -> String {
+    if field.contains(delimiter) || field.contains(['"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+// Returns the value of a column: `timestamp`, or the value of an OBIS code. Values with a unit
+// are given as a number, with the decimal separator that was configured.
+fn field(telegram: &Telegram, column: &str, decimal_separator: char) -> String {
+    if column == "timestamp" {
+        return telegram
+            .timestamp()
+            .map(|timestamp| timestamp.to_rfc3339())
+            .unwrap_or_default();
+    }
+    match telegram.object(column) {
+        Some(object) => match object.quantity() {
+            Some(quantity) => quantity
+                .value
+                .to_string()
+                .replace('.', &decimal_separator.to_string()),
+            None => object.values.join(" "),
+        },
+        None => String::new(),
+    }
+}
+
+// Writes selected values of each telegram as a row to a CSV file. The file name can contain a
+// date pattern such as `%Y-%m-%d`, so that a new file is started every day.
+pub struct CsvConsumer {
+    path: String,
+    columns: Vec<String>,
+    delimiter: char,
+    decimal_separator: char,
+    // The file that is written to and its name
+    file: Option<(String, File)>,
+    // Rows that were not written yet, with the name of their file
+    pending: VecDeque<(String, String)>,
+}
+impl CsvConsumer {
+    pub fn new(settings: &CsvSettings) -> Self {
+        CsvConsumer {
+            path: settings.path.clone(),
+            columns: settings.columns.clone(),
+            delimiter: settings.delimiter,
+            decimal_separator: settings.decimal_separator,
+            file: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn row(&self, fields: impl Iterator<Item = String>) -> String {
+        let fields: Vec<String> = fields.map(|field| quote(&field, self.delimiter)).collect();
+        format!("{}\r\n", fields.join(&self.delimiter.to_string()))
+    }
+
+    // Opens the file, starting it with a header when it is new.
+    fn open(&self, path: &str) -> std::io::Result<File> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(self.row(self.columns.iter().cloned()).as_bytes())?;
+        }
+        Ok(file)
+    }
+
+    // Writes the pending rows in order, opening the next file when the date changed.
+    fn write_pending(&mut self) -> std::io::Result<()> {
+        while let Some((path, row)) = self.pending.front() {
+            let file = match self.file.take() {
+                Some((current, file)) if current == *path => file,
+                _ => {
+                    log::debug!("Writing rows to {}", path);
+                    self.open(path)?
+                }
+            };
+            let (_, file) = self.file.insert((path.clone(), file));
+            file.write_all(row.as_bytes())?;
+            self.pending.pop_front();
+        }
+        Ok(())
+    }
+}
+impl TelegramConsumer for CsvConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let path = match telegram.timestamp() {
+            Some(timestamp) => timestamp.format(&self.path).to_string(),
+            None => {
+                log::debug!("Not writing telegram without timestamp to CSV");
+                return;
+            }
+        };
+        let row = self.row(
+            self.columns
+                .iter()
+                .map(|column| field(telegram, column, self.decimal_separator)),
+        );
+
+        if self.pending.len() == MAX_PENDING {
+            log::warn!("Dropping CSV row, as {} rows are waiting", MAX_PENDING);
+            self.pending.pop_front();
+        }
+        self.pending.push_back((path, row));
+        if let Err(msg) = self.write_pending() {
+            log::warn!(
+                "Could not write {} CSV rows due to {}",
+                self.pending.len(),
+                msg
+            );
+            // Open the file again for the next row
+            self.file = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::fs;
+
+    fn telegram(timestamp: &str, power: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n1-0:1.7.0({}*kW)\r\n\
+             0-0:96.13.0(Tariff 1; \"low\")\r\n!\r\n",
+            timestamp, power
+        ))
+    }
+
+    fn consumer(path: &str, delimiter: char, decimal_separator: char) -> CsvConsumer {
+        CsvConsumer::new(&CsvSettings {
+            path: String::from(path),
+            columns: vec![
+                String::from("timestamp"),
+                String::from("1-0:1.7.0"),
+                String::from("1-0:2.7.0"),
+                String::from("0-0:96.13.0"),
+            ],
+            delimiter,
+            decimal_separator,
+            pipeline: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn quote_fields() {
+        assert_eq!(quote("0.302", ','), "0.302");
+        assert_eq!(quote("0,302", ','), "\"0,302\"");
+        assert_eq!(quote("0,302", ';'), "0,302");
+        assert_eq!(quote("say \"hi\"", ';'), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn write_rows_to_daily_files() {
+        let directory = std::env::temp_dir().join(format!("dsmr-rs-csv-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("readings-%Y-%m-%d.csv");
+        let mut consumer = consumer(path.to_str().unwrap(), ';', ',');
+
+        consumer.consume(&telegram("231026235955S", "00.302"));
+        consumer.consume(&telegram("231027000000S", "01.250"));
+        consumer.consume(&telegram("231027000005S", "01.100"));
+
+        let first = fs::read_to_string(directory.join("readings-2023-10-26.csv")).unwrap();
+        let second = fs::read_to_string(directory.join("readings-2023-10-27.csv")).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            first,
+            "timestamp;1-0:1.7.0;1-0:2.7.0;0-0:96.13.0\r\n\
+             2023-10-26T23:59:55+02:00;0,302;;\"Tariff 1; \"\"low\"\"\"\r\n"
+        );
+        assert_eq!(
+            second,
+            "timestamp;1-0:1.7.0;1-0:2.7.0;0-0:96.13.0\r\n\
+             2023-10-27T00:00:00+02:00;1,250;;\"Tariff 1; \"\"low\"\"\"\r\n\
+             2023-10-27T00:00:05+02:00;1,100;;\"Tariff 1; \"\"low\"\"\"\r\n"
+        );
+    }
+
+    #[test]
+    fn keep_rows_until_file_can_be_written() {
+        let directory =
+            std::env::temp_dir().join(format!("dsmr-rs-csv-pending-{}", std::process::id()));
+        let path = directory.join("readings.csv");
+        let mut consumer = consumer(path.to_str().unwrap(), ',', '.');
+
+        consumer.consume(&telegram("231026204010S", "00.302"));
+        assert_eq!(consumer.pending.len(), 1);
+
+        fs::create_dir_all(&directory).unwrap();
+        consumer.consume(&telegram("231026204015S", "00.298"));
+
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(consumer.pending.is_empty());
+        assert_eq!(written.lines().count(), 3);
+        assert!(written.contains("2023-10-26T20:40:10+02:00,0.302,,"));
+    }
+}
@@ -0,0 +1,254 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use super::telegram::Telegram;
+use super::value::{Decimal, Quantity, Unit};
+use super::TelegramConsumer;
+
+const POWER_DELIVERED: &str = "1-0:1.7.0";
+const POWER_RECEIVED: &str = "1-0:2.7.0";
+// Power delivered, power received, voltage and current of each phase
+const PHASES: [(&str, &str, &str, &str); 3] = [
+    ("1-0:21.7.0", "1-0:22.7.0", "1-0:32.7.0", "1-0:31.7.0"),
+    ("1-0:41.7.0", "1-0:42.7.0", "1-0:52.7.0", "1-0:51.7.0"),
+    ("1-0:61.7.0", "1-0:62.7.0", "1-0:72.7.0", "1-0:71.7.0"),
+];
+// Decimals of currents calculated from power and voltage
+const CURRENT_SCALE: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseValues {
+    // 1, 2 or 3
+    pub phase: u8,
+    // Power delivered minus power received, negative when the client delivers power
+    pub net_power: Option<Quantity>,
+    // The reported current, or the current calculated from power and voltage when the
+    // meter only reports whole amperes
+    pub current: Option<Quantity>,
+}
+
+// Values calculated from the values of a telegram.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Derived {
+    // Power delivered minus power received, negative when the client delivers power
+    pub net_power: Option<Quantity>,
+    pub phases: Vec<PhaseValues>,
+    // Difference between the highest and lowest net power of the phases
+    pub imbalance: Option<Quantity>,
+    // Average gas flow between the last two readings of the gas meter
+    pub gas_flow: Option<Quantity>,
+}
+
+fn kilowatt(telegram: &Telegram, obis: &str) -> Option<Decimal> {
+    telegram
+        .quantity(obis)
+        .and_then(|quantity| quantity.convert(Unit::KiloWatt).ok())
+        .map(|quantity| quantity.value)
+}
+
+fn net_power(telegram: &Telegram, delivered: &str, received: &str) -> Option<Quantity> {
+    let delivered = kilowatt(telegram, delivered);
+    let received = kilowatt(telegram, received);
+    if delivered.is_none() && received.is_none() {
+        return None;
+    }
+    let zero = Decimal::new(0, 0);
+    Some(Quantity::new(
+        delivered.unwrap_or(zero) - received.unwrap_or(zero),
+        Unit::KiloWatt,
+    ))
+}
+
+fn current(
+    telegram: &Telegram,
+    net_power: Option<Quantity>,
+    voltage: &str,
+    current: &str,
+) -> Option<Quantity> {
+    let reported = telegram.quantity(current);
+    if reported.is_some_and(|current| current.value.scale() > 0) {
+        return reported;
+    }
+
+    let power = net_power.and_then(|power| power.convert(Unit::Watt).ok());
+    let voltage = telegram
+        .quantity(voltage)
+        .and_then(|voltage| voltage.convert(Unit::Volt).ok());
+    match (power, voltage) {
+        (Some(power), Some(voltage)) => {
+            let power = if power.value < Decimal::new(0, 0) {
+                -power.value
+            } else {
+                power.value
+            };
+            power
+                .divided_by(voltage.value, CURRENT_SCALE)
+                .map(|current| Quantity::new(current, Unit::Ampere))
+                .or(reported)
+        }
+        _ => reported,
+    }
+}
+
+// Calculates net power, per-phase values and gas flow, and attaches them to each telegram.
+pub struct DerivingConsumer {
+    // The last two different readings of the gas meter
+    gas_readings: Vec<(DateTime<Tz>, Decimal)>,
+    downstream: Box<dyn TelegramConsumer>,
+}
+impl DerivingConsumer {
+    pub fn new(downstream: Box<dyn TelegramConsumer>) -> Self {
+        DerivingConsumer {
+            gas_readings: Vec::new(),
+            downstream,
+        }
+    }
+
+    fn gas_flow(&mut self, telegram: &Telegram) -> Option<Quantity> {
+        if let Some(reading) = telegram.gas_reading() {
+            if self
+                .gas_readings
+                .last()
+                .is_none_or(|last| last.0 < reading.0)
+            {
+                self.gas_readings.push(reading);
+                let excess = self.gas_readings.len().saturating_sub(2);
+                self.gas_readings.drain(..excess);
+            }
+        }
+
+        match self.gas_readings.as_slice() {
+            [(previous_at, previous), (at, reading)] if reading >= previous => {
+                let seconds = (*at - *previous_at).num_seconds();
+                Some(Quantity::new(
+                    (*reading - *previous).times(3600, seconds),
+                    Unit::CubicMetrePerHour,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn derive(&mut self, telegram: &Telegram) -> Derived {
+        let phases: Vec<PhaseValues> = PHASES
+            .iter()
+            .zip(1..)
+            .filter_map(|(&(delivered, received, voltage, current_obis), phase)| {
+                let net_power = net_power(telegram, delivered, received);
+                let current = current(telegram, net_power, voltage, current_obis);
+                if net_power.is_none() && current.is_none() {
+                    return None;
+                }
+                Some(PhaseValues {
+                    phase,
+                    net_power,
+                    current,
+                })
+            })
+            .collect();
+
+        let powers: Vec<Decimal> = phases
+            .iter()
+            .filter_map(|phase| phase.net_power)
+            .map(|power| power.value)
+            .collect();
+        let imbalance = match (powers.iter().max(), powers.iter().min()) {
+            (Some(max), Some(min)) if powers.len() > 1 => {
+                Some(Quantity::new(*max - *min, Unit::KiloWatt))
+            }
+            _ => None,
+        };
+
+        Derived {
+            net_power: net_power(telegram, POWER_DELIVERED, POWER_RECEIVED),
+            phases,
+            imbalance,
+            gas_flow: self.gas_flow(telegram),
+        }
+    }
+}
+
+impl TelegramConsumer for DerivingConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let mut result = telegram.clone();
+        result.derived = Some(self.derive(telegram));
+        self.downstream.consume(&result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    struct NoopConsumer {}
+    impl TelegramConsumer for NoopConsumer {
+        fn consume(&mut self, _telegram: &Telegram) {}
+    }
+
+    fn quantity(value: &str) -> Option<Quantity> {
+        Some(Quantity::parse(value).unwrap())
+    }
+
+    #[test]
+    fn derive_net_power_and_phase_values() {
+        let mut consumer = DerivingConsumer::new(Box::new(NoopConsumer {}));
+        let telegram = Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n\
+             1-0:1.7.0(00.302*kW)\r\n1-0:2.7.0(01.000*kW)\r\n\
+             1-0:32.7.0(230.0*V)\r\n1-0:52.7.0(231.0*V)\r\n\
+             1-0:31.7.0(001*A)\r\n1-0:51.7.0(004.20*A)\r\n\
+             1-0:21.7.0(00.302*kW)\r\n1-0:41.7.0(00.000*kW)\r\n\
+             1-0:22.7.0(00.000*kW)\r\n1-0:42.7.0(01.000*kW)\r\n!\r\n",
+        );
+
+        let result = consumer.derive(&telegram);
+
+        assert_eq!(result.net_power, quantity("-0.698*kW"));
+        assert_eq!(
+            result.phases,
+            vec![
+                PhaseValues {
+                    phase: 1,
+                    net_power: quantity("0.302*kW"),
+                    // 302 W / 230 V, as the meter only reports whole amperes
+                    current: quantity("1.313*A"),
+                },
+                PhaseValues {
+                    phase: 2,
+                    net_power: quantity("-1.000*kW"),
+                    current: quantity("4.20*A"),
+                },
+            ]
+        );
+        assert_eq!(result.imbalance, quantity("1.302*kW"));
+        assert_eq!(result.gas_flow, None);
+    }
+
+    #[test]
+    fn derive_gas_flow_from_capture_times() {
+        let mut consumer = DerivingConsumer::new(Box::new(NoopConsumer {}));
+        let gas = |captured: &str, reading: &str| {
+            Telegram::parse(&format!(
+                "/ISK5\\2M550T-1013\r\n0-1:24.2.1({})({}*m3)\r\n!\r\n",
+                captured, reading
+            ))
+        };
+
+        assert_eq!(
+            consumer.derive(&gas("231026204000S", "00004.381")).gas_flow,
+            None
+        );
+        assert_eq!(
+            consumer.derive(&gas("231026204000S", "00004.381")).gas_flow,
+            None
+        );
+
+        let result = consumer.derive(&gas("231026204500S", "00004.431"));
+        assert_eq!(result.gas_flow, quantity("0.600*m3/h"));
+
+        // The flow is kept until the next reading
+        let result = consumer.derive(&gas("231026204500S", "00004.431"));
+        assert_eq!(result.gas_flow, quantity("0.600*m3/h"));
+    }
+}
@@ -0,0 +1,223 @@
+use chrono::Utc;
+use serde_json::{Map, Value};
+
+use super::publisher::sensor_values;
+use super::settings::{EmoncmsSettings, ValueMapping};
+use super::status::Health;
+use super::telegram::Telegram;
+use super::TelegramConsumer;
+
+// Returns the inputs of a telegram as a JSON object, with the names of the known sensors when no
+// inputs are configured.
+fn inputs(mappings: &[ValueMapping], telegram: &Telegram) -> Map<String, Value> {
+    if mappings.is_empty() {
+        return sensor_values(telegram)
+            .into_iter()
+            .map(|value| {
+                (
+                    String::from(value.name),
+                    value.quantity.value.to_f64().into(),
+                )
+            })
+            .collect();
+    }
+    mappings
+        .iter()
+        .filter_map(|mapping| {
+            let value = telegram.number(&mapping.obis, mapping.unit)?;
+            Some((mapping.name.clone(), value.to_f64().into()))
+        })
+        .collect()
+}
+
+// Posts the values of each telegram as inputs of a node to Emoncms, once per telegram.
+pub struct EmoncmsConsumer {
+    url: String,
+    apikey: String,
+    node: String,
+    inputs: Vec<ValueMapping>,
+    client: reqwest::blocking::Client,
+    health: Health,
+}
+impl EmoncmsConsumer {
+    pub fn new(settings: &EmoncmsSettings, health: Health) -> Self {
+        EmoncmsConsumer {
+            url: format!("{}/input/post", settings.url.trim_end_matches('/')),
+            apikey: settings.apikey.clone(),
+            node: settings.node.clone(),
+            inputs: settings.inputs.clone(),
+            client: reqwest::blocking::Client::new(),
+            health,
+        }
+    }
+}
+impl TelegramConsumer for EmoncmsConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let inputs = inputs(&self.inputs, telegram);
+        if inputs.is_empty() {
+            log::debug!("Not posting telegram without inputs to Emoncms");
+            return;
+        }
+        let time = telegram
+            .timestamp()
+            .map_or_else(|| Utc::now().timestamp(), |timestamp| timestamp.timestamp());
+
+        log::trace!("- posting inputs to {}", self.url);
+        let params = [
+            ("node", self.node.clone()),
+            ("time", time.to_string()),
+            ("fulljson", Value::Object(inputs).to_string()),
+            ("apikey", self.apikey.clone()),
+        ];
+        let result = self
+            .client
+            .post(&self.url)
+            .form(&params)
+            .send()
+            .and_then(|response| Ok((response.status(), response.text()?)));
+
+        match result {
+            Ok((status, _)) if !status.is_success() => {
+                log::warn!("Emoncms {} answered with status {}", self.url, status);
+                let error = format!("Response with status {}", status);
+                self.health.failure(&self.url, &error);
+            }
+            // Emoncms answers with status 200 when the API key or the inputs are invalid
+            Ok((_, body)) if body.replace(' ', "").contains("\"success\":false") => {
+                log::warn!("Emoncms {} did not accept inputs: {}", self.url, body);
+                self.health.failure(&self.url, &body);
+            }
+            Ok((status, _)) => {
+                log::trace!("Got response with status {}", status);
+                self.health.success(&self.url);
+            }
+            Err(msg) => {
+                log::warn!("Could not post inputs to Emoncms due to {}", msg);
+                self.health.failure(&self.url, &msg.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::dsmr::value::Unit;
+
+    fn telegram() -> Telegram {
+        Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n\
+             1-0:1.8.1(000032.159*kWh)\r\n1-0:1.7.0(00.302*kW)\r\n0-0:96.7.21(00004)\r\n!\r\n",
+        )
+    }
+
+    fn settings(url: &str, inputs: Vec<ValueMapping>) -> EmoncmsSettings {
+        EmoncmsSettings {
+            url: String::from(url),
+            apikey: String::from("secret"),
+            node: String::from("meter"),
+            inputs,
+            pipeline: Vec::new(),
+        }
+    }
+
+    // Answers a single request with the given body, and returns the request.
+    fn server(body: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_lowercase();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .and_then(|length| length.parse::<usize>().ok());
+                    if length.is_some_and(|length| body.len() >= length) {
+                        break;
+                    }
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (format!("http://{}", address), server)
+    }
+
+    #[test]
+    fn inputs_of_telegram() {
+        let telegram = telegram();
+
+        let result = inputs(&[], &telegram);
+        assert_eq!(
+            Value::Object(result).to_string(),
+            "{\"electricity_delivered_1\":32.159,\"power_delivered\":0.302}"
+        );
+
+        let mappings = vec![
+            ValueMapping {
+                obis: String::from("1-0:1.7.0"),
+                unit: Some(Unit::Watt),
+                name: String::from("power"),
+            },
+            ValueMapping {
+                obis: String::from("0-0:96.7.21"),
+                unit: None,
+                name: String::from("failures"),
+            },
+            ValueMapping {
+                obis: String::from("1-0:2.7.0"),
+                unit: None,
+                name: String::from("returned"),
+            },
+        ];
+        let result = inputs(&mappings, &telegram);
+        assert_eq!(
+            Value::Object(result).to_string(),
+            "{\"failures\":4.0,\"power\":302.0}"
+        );
+    }
+
+    #[test]
+    fn post_inputs_to_emoncms() {
+        let (url, server) = server("{\"success\": true}");
+
+        let mut consumer = EmoncmsConsumer::new(&settings(&url, Vec::new()), Health::default());
+        consumer.consume(&telegram());
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /input/post HTTP/1.1"));
+        assert!(request.contains("node=meter&time=1698345615&fulljson="));
+        assert!(request.ends_with("&apikey=secret"));
+        assert!(consumer.health.snapshot()[&consumer.url].healthy());
+    }
+
+    #[test]
+    fn report_inputs_that_were_not_accepted() {
+        let (url, server) = server("{\"success\": false, \"message\": \"Invalid API key\"}");
+
+        let mut consumer = EmoncmsConsumer::new(&settings(&url, Vec::new()), Health::default());
+        consumer.consume(&telegram());
+
+        server.join().unwrap();
+        let status = &consumer.health.snapshot()[&consumer.url];
+        assert!(!status.healthy());
+        assert_eq!(status.failures, 1);
+    }
+}
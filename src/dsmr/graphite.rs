@@ -0,0 +1,358 @@
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use chrono::Utc;
+
+use super::publisher::{equipment_identifier, sensor_values};
+use super::settings::{GraphiteSettings, Transport};
+use super::status::Health;
+use super::telegram::Telegram;
+use super::TelegramConsumer;
+
+// Datagrams are kept below the common MTU, so they are not fragmented
+const MAX_DATAGRAM: usize = 1_400;
+const TIMEOUT: Duration = Duration::from_secs(5);
+// Lines that could not be sent over TCP are kept up to this number, and sent after reconnecting
+const MAX_PENDING: usize = 10_000;
+
+// Replaces characters that Graphite uses as separators or does not allow in a metric name.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// Returns the path and value of each metric in the telegram. The template can contain
+// `{equipment_id}`, `{obis_name}` such as `power_delivered`, and `{obis}` such as `1-0_1_7_0`.
+fn metrics(template: &str, telegram: &Telegram) -> Vec<(String, String)> {
+    let equipment_id =
+        sanitize(&equipment_identifier(telegram).unwrap_or_else(|| String::from("meter")));
+    sensor_values(telegram)
+        .into_iter()
+        .map(|value| {
+            let path = template
+                .replace("{equipment_id}", &equipment_id)
+                .replace("{obis_name}", value.name)
+                .replace("{obis}", &sanitize(value.obis));
+            (path, value.quantity.value.to_string())
+        })
+        .collect()
+}
+
+// Packs lines into as few datagrams as possible, without splitting a line.
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams: Vec<String> = Vec::new();
+    for line in lines {
+        match datagrams.last_mut() {
+            Some(datagram) if datagram.len() + line.len() <= MAX_DATAGRAM => {
+                datagram.push_str(line)
+            }
+            _ => datagrams.push(line.clone()),
+        }
+    }
+    datagrams
+}
+
+enum Socket {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+impl Socket {
+    fn connect(transport: Transport, address: &str) -> io::Result<Socket> {
+        let target = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address not found"))?;
+        match transport {
+            Transport::Tcp => {
+                let stream = TcpStream::connect_timeout(&target, TIMEOUT)?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                Ok(Socket::Tcp(stream))
+            }
+            Transport::Udp => {
+                let local: SocketAddr = if target.is_ipv4() {
+                    "0.0.0.0:0".parse().unwrap()
+                } else {
+                    "[::]:0".parse().unwrap()
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(target)?;
+                Ok(Socket::Udp(socket))
+            }
+        }
+    }
+
+    fn send(&mut self, lines: &[String]) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.write_all(lines.concat().as_bytes()),
+            Socket::Udp(socket) => datagrams(lines)
+                .iter()
+                .try_for_each(|datagram| socket.send(datagram.as_bytes()).map(|_| ())),
+        }
+    }
+}
+
+// Sends lines to a Carbon or StatsD server, connecting again after a failure.
+struct MetricSender {
+    transport: Transport,
+    address: String,
+    // Name of the sink in logs and the health report
+    name: String,
+    socket: Option<Socket>,
+    pending: Vec<String>,
+    failing: bool,
+    health: Health,
+}
+impl MetricSender {
+    fn new(scheme: &str, transport: Transport, address: &str, health: Health) -> Self {
+        MetricSender {
+            transport,
+            address: String::from(address),
+            name: format!("{}://{}", scheme, address),
+            socket: None,
+            pending: Vec::new(),
+            failing: false,
+            health,
+        }
+    }
+
+    fn try_send(&mut self) -> io::Result<()> {
+        let socket = match &mut self.socket {
+            Some(socket) => socket,
+            None => self
+                .socket
+                .insert(Socket::connect(self.transport, &self.address)?),
+        };
+        socket.send(&self.pending)
+    }
+
+    fn send(&mut self, lines: Vec<String>) {
+        self.pending.extend(lines);
+        if self.pending.len() > MAX_PENDING {
+            let dropped = self.pending.len() - MAX_PENDING;
+            log::warn!(
+                "Dropping {} metrics that were not sent to {}",
+                dropped,
+                self.name
+            );
+            self.pending.drain(..dropped);
+        }
+
+        match self.try_send() {
+            Ok(()) => {
+                if self.failing {
+                    log::info!("Sending metrics to {} again", self.name);
+                    self.failing = false;
+                }
+                self.pending.clear();
+                self.health.success(&self.name);
+            }
+            Err(msg) => {
+                if !self.failing {
+                    log::warn!("Could not send metrics to {} due to {}", self.name, msg);
+                    self.failing = true;
+                }
+                self.socket = None;
+                // Datagrams are not sent again, as StatsD gauges have no timestamp
+                if self.transport == Transport::Udp {
+                    self.pending.clear();
+                }
+                self.health.failure(&self.name, &msg.to_string());
+            }
+        }
+    }
+}
+
+// Sends the values of telegrams to Carbon in its plaintext protocol, in batches of telegrams.
+pub struct CarbonConsumer {
+    template: String,
+    batch_size: usize,
+    batch: Vec<String>,
+    telegrams: usize,
+    sender: MetricSender,
+}
+impl CarbonConsumer {
+    pub fn new(
+        settings: &GraphiteSettings,
+        transport: Transport,
+        address: &str,
+        health: Health,
+    ) -> Self {
+        CarbonConsumer {
+            template: settings.template.clone(),
+            batch_size: settings.batch_size,
+            batch: Vec::new(),
+            telegrams: 0,
+            sender: MetricSender::new("carbon", transport, address, health),
+        }
+    }
+}
+impl TelegramConsumer for CarbonConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let timestamp = telegram
+            .timestamp()
+            .map_or_else(|| Utc::now().timestamp(), |timestamp| timestamp.timestamp());
+        for (path, value) in metrics(&self.template, telegram) {
+            self.batch
+                .push(format!("{} {} {}\n", path, value, timestamp));
+        }
+        self.telegrams += 1;
+
+        if self.telegrams >= self.batch_size {
+            self.sender.send(std::mem::take(&mut self.batch));
+            self.telegrams = 0;
+        }
+    }
+}
+
+// Sends the values of each telegram to StatsD as gauges.
+pub struct StatsdConsumer {
+    template: String,
+    sender: MetricSender,
+}
+impl StatsdConsumer {
+    pub fn new(settings: &GraphiteSettings, address: &str, health: Health) -> Self {
+        StatsdConsumer {
+            template: settings.template.clone(),
+            sender: MetricSender::new("statsd", Transport::Udp, address, health),
+        }
+    }
+}
+impl TelegramConsumer for StatsdConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let lines = metrics(&self.template, telegram)
+            .into_iter()
+            .map(|(path, value)| format!("{}:{}|g\n", path, value))
+            .collect();
+        self.sender.send(lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn telegram(timestamp: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n\
+             0-0:96.1.1(4530303334303036303436393733363134)\r\n\
+             1-0:1.8.1(000032.159*kWh)\r\n1-0:1.7.0(00.302*kW)\r\n!\r\n",
+            timestamp
+        ))
+    }
+
+    fn settings(batch_size: usize) -> GraphiteSettings {
+        GraphiteSettings {
+            carbon: None,
+            statsd: None,
+            template: String::from("energy.{equipment_id}.{obis_name}"),
+            batch_size,
+            pipeline: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn metrics_from_template() {
+        assert_eq!(
+            metrics(
+                "energy.{equipment_id}.{obis_name}",
+                &telegram("231026204015S")
+            ),
+            vec![
+                (
+                    String::from("energy.E0034006046973614.electricity_delivered_1"),
+                    String::from("32.159")
+                ),
+                (
+                    String::from("energy.E0034006046973614.power_delivered"),
+                    String::from("0.302")
+                ),
+            ]
+        );
+        let telegram = Telegram::parse("/ISK5\\2M550T-1013\r\n1-0:1.7.0(00.302*kW)\r\n!\r\n");
+        assert_eq!(
+            metrics("home.{equipment_id}.{obis}", &telegram),
+            vec![(String::from("home.meter.1-0_1_7_0"), String::from("0.302"))]
+        );
+    }
+
+    #[test]
+    fn pack_lines_into_datagrams() {
+        let line = format!("{}\n", "a".repeat(599));
+        let lines = vec![line.clone(), line.clone(), line.clone()];
+
+        let result = datagrams(&lines);
+
+        assert_eq!(result, vec![[line.as_str(), &line].concat(), line.clone()]);
+    }
+
+    #[test]
+    fn send_batches_to_carbon() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let health = Health::default();
+        let mut consumer = CarbonConsumer::new(&settings(2), Transport::Tcp, &address, health);
+        consumer.consume(&telegram("231026204015S"));
+        assert!(consumer.sender.socket.is_none());
+        consumer.consume(&telegram("231026204020S"));
+        drop(consumer);
+
+        assert_eq!(
+            server.join().unwrap(),
+            "energy.E0034006046973614.electricity_delivered_1 32.159 1698345615\n\
+             energy.E0034006046973614.power_delivered 0.302 1698345615\n\
+             energy.E0034006046973614.electricity_delivered_1 32.159 1698345620\n\
+             energy.E0034006046973614.power_delivered 0.302 1698345620\n"
+        );
+    }
+
+    #[test]
+    fn keep_metrics_until_carbon_is_reachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let health = Health::default();
+        let mut consumer =
+            CarbonConsumer::new(&settings(1), Transport::Tcp, &address, health.clone());
+        consumer.consume(&telegram("231026204015S"));
+
+        assert_eq!(consumer.sender.pending.len(), 2);
+        assert!(!health.snapshot()[&format!("carbon://{}", address)].healthy());
+    }
+
+    #[test]
+    fn send_gauges_to_statsd() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let address = server.local_addr().unwrap().to_string();
+
+        let mut consumer = StatsdConsumer::new(&settings(1), &address, Health::default());
+        consumer.consume(&telegram("231026204015S"));
+
+        let mut buffer = [0u8; MAX_DATAGRAM];
+        let size = server.recv(&mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buffer[..size]),
+            "energy.E0034006046973614.electricity_delivered_1:32.159|g\n\
+             energy.E0034006046973614.power_delivered:0.302|g\n"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use serde_json::{json, Map, Number, Value};
+
+use super::cost::PeriodCosts;
+use super::telegram::{CosemObject, Telegram};
+use super::totals::PeriodTotals;
+use super::value::{Decimal, Quantity};
+
+// Writes the decimal with all its digits, such as 32.160, without rounding it to a float.
+fn number(value: Decimal) -> Value {
+    match value.to_string().parse::<Number>() {
+        Ok(number) => Value::Number(number),
+        Err(_) => Value::Null,
+    }
+}
+
+fn quantity(quantity: Option<Quantity>) -> Value {
+    match quantity {
+        Some(quantity) => json!({
+            "value": number(quantity.value),
+            "unit": quantity.unit.symbol(),
+        }),
+        None => Value::Null,
+    }
+}
+
+// Objects with a unit become `{"value": 32.159, "unit": "kWh"}`, others their value as a string,
+// or all their values when there are several.
+fn object(object: &CosemObject) -> Value {
+    match (object.quantity(), object.values.as_slice()) {
+        (Some(value), _) => quantity(Some(value)),
+        (None, [value]) => Value::from(value.as_str()),
+        (None, values) => Value::from(values.to_vec()),
+    }
+}
+
+fn period_totals(totals: &PeriodTotals) -> Value {
+    json!({
+        "period": totals.period,
+        "import": number(totals.import),
+        "export": number(totals.export),
+        "gas": number(totals.gas),
+    })
+}
+
+fn period_costs(costs: &PeriodCosts) -> Value {
+    json!({
+        "period": costs.period,
+        "import": number(costs.import),
+        "export": number(costs.export),
+        "gas": number(costs.gas),
+        "fixed": number(costs.fixed),
+        "total": number(costs.total()),
+    })
+}
+
+// Returns the parsed telegram as a JSON document, including the values that were calculated
+// from it, for APIs and streams that serve readings.
+pub fn reading(telegram: &Telegram) -> Value {
+    let values: Map<String, Value> = telegram
+        .objects
+        .iter()
+        .map(|cosem| (cosem.obis.clone(), object(cosem)))
+        .collect();
+
+    let mut reading = json!({
+        "timestamp": telegram.timestamp().map(|timestamp| timestamp.to_rfc3339()),
+        "header": telegram.header,
+        "values": values,
+        "flags": telegram.flags,
+    });
+    if let Some(derived) = &telegram.derived {
+        let phases: Vec<Value> = derived
+            .phases
+            .iter()
+            .map(|phase| {
+                json!({
+                    "phase": phase.phase,
+                    "net_power": quantity(phase.net_power),
+                    "current": quantity(phase.current),
+                })
+            })
+            .collect();
+        reading["derived"] = json!({
+            "net_power": quantity(derived.net_power),
+            "phases": phases,
+            "imbalance": quantity(derived.imbalance),
+            "gas_flow": quantity(derived.gas_flow),
+        });
+    }
+    if let Some(totals) = &telegram.totals {
+        reading["totals"] = json!({
+            "day": period_totals(&totals.day),
+            "month": period_totals(&totals.month),
+        });
+    }
+    if let Some(costs) = &telegram.costs {
+        reading["costs"] = json!({
+            "day": period_costs(&costs.day),
+            "month": period_costs(&costs.month),
+        });
+    }
+    reading
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use crate::dsmr::totals::Totals;
+
+    #[test]
+    fn reading_of_telegram() {
+        let mut telegram = Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n1-0:1.8.1(000032.159*kWh)\r\n\
+             0-1:24.2.1(231026204004S)(00004.381*m3)\r\n0-0:96.13.0()\r\n!\r\n",
+        );
+        telegram.flags.push(String::from("monotonic"));
+
+        assert_eq!(
+            reading(&telegram),
+            json!({
+                "timestamp": "2023-10-26T20:40:15+02:00",
+                "header": "/ISK5\\2M550T-1013",
+                "values": {
+                    "0-0:1.0.0": "231026204015S",
+                    "1-0:1.8.1": {"value": 32.159, "unit": "kWh"},
+                    "0-1:24.2.1": {"value": 4.381, "unit": "m3"},
+                    "0-0:96.13.0": "",
+                },
+                "flags": ["monotonic"],
+            })
+        );
+    }
+
+    #[test]
+    fn reading_with_totals() {
+        let mut telegram = Telegram::parse("/ISK5\\2M550T-1013\r\n!\r\n");
+        let totals = PeriodTotals {
+            period: String::from("2023-10-26"),
+            import: Decimal::parse("1.250").unwrap(),
+            export: Decimal::parse("0.000").unwrap(),
+            gas: Decimal::parse("0.300").unwrap(),
+        };
+        telegram.totals = Some(Totals {
+            day: totals.clone(),
+            month: totals,
+        });
+
+        let result = reading(&telegram);
+
+        assert_eq!(result["timestamp"], Value::Null);
+        // Values keep all their digits
+        assert_eq!(
+            result["totals"]["day"].to_string(),
+            r#"{"export":0.000,"gas":0.300,"import":1.250,"period":"2023-10-26"}"#
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::json;
+use super::settings::{JsonLinesOutput, JsonLinesSettings};
+use super::telegram::Telegram;
+use super::TelegramConsumer;
+
+// Subscribers that do not accept a line within this time are disconnected, so they cannot
+// hold up the reader.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn accept(listener: UnixListener, subscribers: Arc<Mutex<Vec<UnixStream>>>) {
+    for connection in listener.incoming().flatten() {
+        if let Err(msg) = connection.set_write_timeout(Some(WRITE_TIMEOUT)) {
+            log::warn!("Could not accept subscriber due to {}", msg);
+            continue;
+        }
+        log::debug!("Writing readings to a new subscriber");
+        subscribers.lock().unwrap().push(connection);
+    }
+}
+
+// Binds the socket, replacing a socket that was left behind by an earlier run.
+fn bind(path: &str) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+    UnixListener::bind(path)
+}
+
+enum Output {
+    Stdout,
+    Socket(Arc<Mutex<Vec<UnixStream>>>),
+}
+
+// Writes each telegram as a reading in JSON on a line of its own, to standard output or to all
+// subscribers of a Unix domain socket, so it can be read by shell tools and local daemons.
+pub struct JsonLinesConsumer {
+    output: Output,
+}
+impl JsonLinesConsumer {
+    pub fn start(settings: &JsonLinesSettings) -> Result<Self, String> {
+        let output = match &settings.output {
+            JsonLinesOutput::Stdout => Output::Stdout,
+            JsonLinesOutput::Socket(path) => {
+                let listener = bind(path).map_err(|msg| {
+                    format!("Could not write readings to {} due to {}", path, msg)
+                })?;
+                log::info!("Writing readings to subscribers of {}", path);
+
+                let subscribers = Arc::new(Mutex::new(Vec::new()));
+                let shared = subscribers.clone();
+                thread::spawn(move || accept(listener, shared));
+                Output::Socket(subscribers)
+            }
+        };
+        Ok(JsonLinesConsumer { output })
+    }
+}
+impl TelegramConsumer for JsonLinesConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let line = format!("{}\n", json::reading(telegram));
+        match &self.output {
+            Output::Stdout => {
+                let mut stdout = io::stdout().lock();
+                if let Err(msg) = stdout
+                    .write_all(line.as_bytes())
+                    .and_then(|_| stdout.flush())
+                {
+                    log::warn!("Could not write reading to standard output due to {}", msg);
+                }
+            }
+            Output::Socket(subscribers) => {
+                let mut subscribers = subscribers.lock().unwrap();
+                subscribers.retain_mut(|subscriber| match subscriber.write_all(line.as_bytes()) {
+                    Ok(()) => true,
+                    Err(msg) => {
+                        log::debug!("Stopped writing readings to a subscriber due to {}", msg);
+                        false
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+    use std::time::Instant;
+
+    use serde_json::Value;
+
+    fn wait_for_subscribers(consumer: &JsonLinesConsumer, count: usize) {
+        let start = Instant::now();
+        while match &consumer.output {
+            Output::Socket(subscribers) => subscribers.lock().unwrap().len() < count,
+            Output::Stdout => false,
+        } {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn write_lines_to_all_subscribers() {
+        let path = std::env::temp_dir().join(format!("dsmr-rs-jsonl-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        // A socket that was left behind is replaced
+        drop(UnixListener::bind(path).unwrap());
+
+        let mut consumer = JsonLinesConsumer::start(&JsonLinesSettings {
+            output: JsonLinesOutput::Socket(String::from(path)),
+            pipeline: Vec::new(),
+        })
+        .unwrap();
+        let first = UnixStream::connect(path).unwrap();
+        let second = UnixStream::connect(path).unwrap();
+        wait_for_subscribers(&consumer, 2);
+
+        consumer.consume(&Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n1-0:1.7.0(00.302*kW)\r\n!\r\n",
+        ));
+        consumer.consume(&Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204020S)\r\n1-0:1.7.0(00.298*kW)\r\n!\r\n",
+        ));
+
+        for subscriber in [first, second] {
+            subscriber
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut lines = BufReader::new(subscriber).lines();
+            let reading: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+            assert_eq!(reading["timestamp"], "2023-10-26T20:40:15+02:00");
+            let reading: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+            assert_eq!(reading["timestamp"], "2023-10-26T20:40:20+02:00");
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn drop_subscribers_that_disconnected() {
+        let path =
+            std::env::temp_dir().join(format!("dsmr-rs-jsonl-drop-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut consumer = JsonLinesConsumer::start(&JsonLinesSettings {
+            output: JsonLinesOutput::Socket(String::from(path)),
+            pipeline: Vec::new(),
+        })
+        .unwrap();
+        drop(UnixStream::connect(path).unwrap());
+        wait_for_subscribers(&consumer, 1);
+
+        let telegram = Telegram::parse("/ISK5\\2M550T-1013\r\n1-0:1.7.0(00.302*kW)\r\n!\r\n");
+        consumer.consume(&telegram);
+        consumer.consume(&telegram);
+
+        wait_for_subscribers(&consumer, 0);
+        if let Output::Socket(subscribers) = &consumer.output {
+            assert!(subscribers.lock().unwrap().is_empty());
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,3 +1,6 @@
+use super::telegram::Telegram;
+use super::value::Unit;
+
 pub struct LoggingConsumer {
     host_counter: u32,
     telegram_counter: u32,
@@ -10,11 +13,37 @@ impl LoggingConsumer {
         }
     }
 }
+// Some meters report energy in Wh rather than kWh; normalise to kWh so log lines are comparable.
+fn log_meter_readings(telegram: &Telegram) {
+    let counters = [
+        ("1-0:1.8.1", "delivered to client (tariff 1)"),
+        ("1-0:1.8.2", "delivered to client (tariff 2)"),
+        ("1-0:2.8.1", "delivered by client (tariff 1)"),
+        ("1-0:2.8.2", "delivered by client (tariff 2)"),
+    ];
+    for (obis, description) in counters.iter() {
+        if let Some(Ok(reading)) = telegram
+            .quantity(obis)
+            .map(|quantity| quantity.convert(Unit::KiloWattHour))
+        {
+            log::info!("Meter reading {}: {}", description, reading);
+        }
+    }
+}
+
 impl super::TelegramConsumer for LoggingConsumer {
-    fn consume(&mut self, _telegram: &str) {
+    fn consume(&mut self, telegram: &Telegram) {
         self.telegram_counter += 1;
         if self.telegram_counter == 10000 {
             log::info!("Submitted 10000 telegrams to {} host(s)", self.host_counter);
+            log_meter_readings(telegram);
+            if let Some(totals) = &telegram.totals {
+                log::info!(
+                    "Delivered to client today: {} kWh, this month: {} kWh",
+                    totals.day.import,
+                    totals.month.import
+                );
+            }
             self.telegram_counter = 0;
         }
     }
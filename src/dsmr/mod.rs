@@ -1,8 +1,40 @@
+pub mod aggregate;
+pub mod alert;
+pub mod api;
+pub mod clock;
+pub mod cost;
+pub mod csv;
+pub mod derived;
+pub mod emoncms;
+pub mod graphite;
+pub mod json;
+pub mod jsonl;
 pub mod logger;
+pub mod modbus;
+pub mod mqtt;
+pub mod otlp;
+pub mod peak;
+pub mod pipeline;
+pub mod publisher;
+pub mod pvoutput;
+pub mod ratelimit;
 pub mod reader;
+pub mod relay;
 pub mod sender;
 pub mod settings;
+pub mod sqlite;
+pub mod status;
+pub mod stream;
+pub mod telegram;
+pub mod timestamp;
+pub mod totals;
+pub mod validator;
+pub mod value;
+pub mod volkszaehler;
+pub mod webhook;
+
+use telegram::Telegram;
 
 pub trait TelegramConsumer {
-    fn consume(&mut self, telegram: &str);
+    fn consume(&mut self, telegram: &Telegram);
 }
@@ -0,0 +1,405 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::relay::allowed;
+use super::settings::{AllowedNetwork, ModbusSettings};
+use super::telegram::Telegram;
+use super::value::Unit;
+use super::TelegramConsumer;
+
+// Clients that send no request within this time are disconnected.
+const READ_TIMEOUT: Duration = Duration::from_secs(300);
+// Largest number of registers that can be read at once
+const MAX_COUNT: u16 = 125;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+// Answered when there is no recent telegram, so controllers do not act on old values
+const SERVER_DEVICE_FAILURE: u8 = 0x04;
+const GATEWAY_TARGET_FAILED: u8 = 0x0B;
+
+// Values served in the input register layout of an Eastron SDM630 meter, which many heat pump
+// and EV charger controllers can read. Each value is a 32-bit float in big-endian order over two
+// registers, starting at the given (zero-based) address. The same registers can be read as
+// holding registers. Power is positive for import and negative for export.
+//
+// | Address | Value                    | Unit |
+// |---------|--------------------------|------|
+// | 0       | Phase 1 voltage          | V    |
+// | 2       | Phase 2 voltage          | V    |
+// | 4       | Phase 3 voltage          | V    |
+// | 6       | Phase 1 current          | A    |
+// | 8       | Phase 2 current          | A    |
+// | 10      | Phase 3 current          | A    |
+// | 12      | Phase 1 power            | W    |
+// | 14      | Phase 2 power            | W    |
+// | 16      | Phase 3 power            | W    |
+// | 52      | Total system power       | W    |
+// | 72      | Total import energy      | kWh  |
+// | 74      | Total export energy      | kWh  |
+// | 342     | Total energy             | kWh  |
+const REGISTERS: usize = 344;
+
+// Returns the sum of the values that are in the telegram, if any is.
+fn sum(telegram: &Telegram, obis: &[&str], unit: Unit) -> Option<f64> {
+    obis.iter()
+        .filter_map(|obis| telegram.number(obis, Some(unit)))
+        .map(|value| value.to_f64())
+        .reduce(|total, value| total + value)
+}
+
+// Returns the power that was imported minus the power that was exported.
+fn power(telegram: &Telegram, delivered: &str, returned: &str) -> Option<f64> {
+    let delivered = sum(telegram, &[delivered], Unit::Watt);
+    let returned = sum(telegram, &[returned], Unit::Watt);
+    if delivered.is_none() && returned.is_none() {
+        return None;
+    }
+    Some(delivered.unwrap_or_default() - returned.unwrap_or_default())
+}
+
+// Returns the registers of the telegram. Values that are not in the telegram are zero.
+fn registers(telegram: &Telegram) -> Vec<u16> {
+    let import = sum(telegram, &["1-0:1.8.1", "1-0:1.8.2"], Unit::KiloWattHour);
+    let export = sum(telegram, &["1-0:2.8.1", "1-0:2.8.2"], Unit::KiloWattHour);
+    let total = match (import, export) {
+        (None, None) => None,
+        (import, export) => Some(import.unwrap_or_default() + export.unwrap_or_default()),
+    };
+    let values = [
+        (0, sum(telegram, &["1-0:32.7.0"], Unit::Volt)),
+        (2, sum(telegram, &["1-0:52.7.0"], Unit::Volt)),
+        (4, sum(telegram, &["1-0:72.7.0"], Unit::Volt)),
+        (6, sum(telegram, &["1-0:31.7.0"], Unit::Ampere)),
+        (8, sum(telegram, &["1-0:51.7.0"], Unit::Ampere)),
+        (10, sum(telegram, &["1-0:71.7.0"], Unit::Ampere)),
+        (12, power(telegram, "1-0:21.7.0", "1-0:22.7.0")),
+        (14, power(telegram, "1-0:41.7.0", "1-0:42.7.0")),
+        (16, power(telegram, "1-0:61.7.0", "1-0:62.7.0")),
+        (52, power(telegram, "1-0:1.7.0", "1-0:2.7.0")),
+        (72, import),
+        (74, export),
+        (342, total),
+    ];
+
+    let mut registers = vec![0u16; REGISTERS];
+    for (address, value) in values {
+        if let Some(value) = value {
+            let bits = (value as f32).to_bits();
+            registers[address] = (bits >> 16) as u16;
+            registers[address + 1] = bits as u16;
+        }
+    }
+    registers
+}
+
+// Returns the response to a request, or an exception response when it can not be answered.
+// There are no registers when no recent telegram was read.
+fn respond(request: &[u8], registers: Option<&[u16]>) -> Vec<u8> {
+    let function = match request.first() {
+        Some(function) => *function,
+        None => return vec![0x80, ILLEGAL_FUNCTION],
+    };
+    let exception = |code: u8| vec![function | 0x80, code];
+    if function != READ_HOLDING_REGISTERS && function != READ_INPUT_REGISTERS {
+        return exception(ILLEGAL_FUNCTION);
+    }
+    if request.len() != 5 {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    let start = u16::from_be_bytes([request[1], request[2]]) as usize;
+    let count = u16::from_be_bytes([request[3], request[4]]);
+    if count == 0 || count > MAX_COUNT {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    let end = start + count as usize;
+    if end > REGISTERS {
+        return exception(ILLEGAL_DATA_ADDRESS);
+    }
+    let registers = match registers {
+        Some(registers) => registers,
+        None => return exception(SERVER_DEVICE_FAILURE),
+    };
+
+    let mut response = vec![function, (count * 2) as u8];
+    for register in &registers[start..end] {
+        response.extend_from_slice(&register.to_be_bytes());
+    }
+    response
+}
+
+// The registers of the last telegram, with the moment it was read.
+type Latest = Arc<Mutex<Option<(Instant, Vec<u16>)>>>;
+
+// Answers the requests of a client until it disconnects. Each request starts with a header of
+// seven bytes: transaction, protocol, length of the rest and unit identifier.
+fn serve(mut stream: TcpStream, latest: Latest, unit_id: u8, timeout: Option<u64>) {
+    let mut header = [0u8; 7];
+    while stream.read_exact(&mut header).is_ok() {
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if header[2..4] != [0, 0] || length < 2 {
+            log::debug!("Closing Modbus connection after invalid request");
+            return;
+        }
+        let mut request = vec![0u8; length - 1];
+        if stream.read_exact(&mut request).is_err() {
+            return;
+        }
+
+        let response = if header[6] != unit_id {
+            vec![
+                request.first().copied().unwrap_or_default() | 0x80,
+                GATEWAY_TARGET_FAILED,
+            ]
+        } else {
+            let latest = latest.lock().unwrap();
+            let registers = latest
+                .as_ref()
+                .filter(|(read, _)| {
+                    timeout.is_none_or(|timeout| read.elapsed().as_secs() < timeout)
+                })
+                .map(|(_, registers)| registers.as_slice());
+            respond(&request, registers)
+        };
+
+        let mut frame = Vec::with_capacity(7 + response.len());
+        frame.extend_from_slice(&header[0..4]);
+        frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend_from_slice(&response);
+        if stream.write_all(&frame).is_err() {
+            return;
+        }
+    }
+}
+
+fn accept(
+    listener: TcpListener,
+    latest: Latest,
+    unit_id: u8,
+    timeout: Option<u64>,
+    max_clients: usize,
+    allow: Vec<AllowedNetwork>,
+) {
+    // Number of clients that are served, each on a thread of its own
+    let clients = Arc::new(AtomicUsize::new(0));
+    for connection in listener.incoming().flatten() {
+        let peer = match connection.peer_addr() {
+            Ok(peer) => peer,
+            Err(_) => continue,
+        };
+        if !allowed(&allow, peer.ip()) {
+            log::warn!("Refusing Modbus client {}, as it is not allowed", peer);
+            continue;
+        }
+        if clients.load(Ordering::SeqCst) >= max_clients {
+            log::warn!(
+                "Refusing Modbus client {}, as there are already {} clients",
+                peer,
+                max_clients
+            );
+            continue;
+        }
+        if let Err(msg) = connection.set_read_timeout(Some(READ_TIMEOUT)) {
+            log::warn!("Could not accept Modbus client {} due to {}", peer, msg);
+            continue;
+        }
+        log::debug!("Serving Modbus registers to {}", peer);
+        let latest = latest.clone();
+        let clients = clients.clone();
+        clients.fetch_add(1, Ordering::SeqCst);
+        thread::spawn(move || {
+            serve(connection, latest, unit_id, timeout);
+            clients.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+// Serves the latest values over Modbus TCP as an emulated grid meter, so controllers that
+// balance their load on the grid connection can read the meter.
+pub struct ModbusConsumer {
+    latest: Latest,
+}
+impl ModbusConsumer {
+    pub fn start(settings: &ModbusSettings) -> Result<Self, String> {
+        let listener = TcpListener::bind(&settings.address).map_err(|msg| {
+            format!(
+                "Could not serve Modbus on {} due to {}",
+                settings.address, msg
+            )
+        })?;
+        log::info!("Serving Modbus on {}", settings.address);
+
+        let latest = Arc::new(Mutex::new(None));
+        let shared = latest.clone();
+        let unit_id = settings.unit_id;
+        let timeout = settings.timeout;
+        let max_clients = settings.max_clients;
+        let allow = settings.allow.clone();
+        thread::spawn(move || accept(listener, shared, unit_id, timeout, max_clients, allow));
+        Ok(ModbusConsumer { latest })
+    }
+}
+impl TelegramConsumer for ModbusConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let registers = registers(telegram);
+        *self.latest.lock().unwrap() = Some((Instant::now(), registers));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn telegram() -> Telegram {
+        Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n\
+             1-0:1.8.1(000032.159*kWh)\r\n1-0:1.8.2(000010.000*kWh)\r\n\
+             1-0:2.8.1(000001.500*kWh)\r\n1-0:1.7.0(00.302*kW)\r\n1-0:2.7.0(00.000*kW)\r\n\
+             1-0:32.7.0(230.1*V)\r\n1-0:31.7.0(002*A)\r\n\
+             1-0:21.7.0(00.000*kW)\r\n1-0:22.7.0(01.250*kW)\r\n!\r\n",
+        )
+    }
+
+    fn float(registers: &[u16], address: usize) -> f32 {
+        f32::from_bits((registers[address] as u32) << 16 | registers[address + 1] as u32)
+    }
+
+    #[test]
+    fn registers_of_telegram() {
+        let registers = registers(&telegram());
+
+        assert_eq!(registers.len(), REGISTERS);
+        assert_eq!(float(&registers, 0), 230.1);
+        assert_eq!(float(&registers, 2), 0.0);
+        assert_eq!(float(&registers, 6), 2.0);
+        assert_eq!(float(&registers, 12), -1250.0);
+        assert_eq!(float(&registers, 14), 0.0);
+        assert_eq!(float(&registers, 52), 302.0);
+        assert_eq!(float(&registers, 72), 42.159);
+        assert_eq!(float(&registers, 74), 1.5);
+        assert_eq!(float(&registers, 342), 43.659);
+    }
+
+    #[test]
+    fn respond_to_requests() {
+        let registers = [0x4366, 0x1999, 0, 0];
+        let registers = [&registers[..], &[0u16; REGISTERS - 4]].concat();
+
+        assert_eq!(
+            respond(&[0x04, 0, 0, 0, 2], Some(&registers)),
+            vec![0x04, 4, 0x43, 0x66, 0x19, 0x99]
+        );
+        assert_eq!(
+            respond(&[0x03, 0, 1, 0, 1], Some(&registers)),
+            vec![0x03, 2, 0x19, 0x99]
+        );
+        assert_eq!(
+            respond(&[0x06, 0, 0, 0, 1], Some(&registers)),
+            vec![0x86, 1]
+        );
+        assert_eq!(
+            respond(&[0x04, 0, 0, 0, 0], Some(&registers)),
+            vec![0x84, 3]
+        );
+        assert_eq!(respond(&[0x04, 0, 0, 0], Some(&registers)), vec![0x84, 3]);
+        assert_eq!(
+            respond(&[0x04, 1, 0x56, 0, 4], Some(&registers)),
+            vec![0x84, 2]
+        );
+        assert_eq!(respond(&[0x04, 0, 0, 0, 2], None), vec![0x84, 4]);
+    }
+
+    #[test]
+    fn serve_registers_over_tcp() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let mut consumer = ModbusConsumer::start(&ModbusSettings {
+            address: address.clone(),
+            unit_id: 1,
+            timeout: Some(30),
+            max_clients: 5,
+            allow: Vec::new(),
+            pipeline: Vec::new(),
+        })
+        .unwrap();
+        let mut client = TcpStream::connect(&address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // Read the total system power, before and after a telegram
+        let request = [0x00, 0x07, 0, 0, 0, 6, 1, 0x04, 0, 52, 0, 2];
+        let mut response = [0u8; 13];
+        client.write_all(&request).unwrap();
+        client.read_exact(&mut response[..9]).unwrap();
+        assert_eq!(response[..9], [0x00, 0x07, 0, 0, 0, 3, 1, 0x84, 4]);
+
+        consumer.consume(&telegram());
+        client.write_all(&request).unwrap();
+        client.read_exact(&mut response).unwrap();
+        assert_eq!(response[..9], [0x00, 0x07, 0, 0, 0, 7, 1, 0x04, 4]);
+        assert_eq!(response[9..], 302f32.to_be_bytes());
+
+        // Other units are not served
+        client
+            .write_all(&[0x00, 0x08, 0, 0, 0, 6, 2, 0x04, 0, 52, 0, 2])
+            .unwrap();
+        client.read_exact(&mut response[..9]).unwrap();
+        assert_eq!(response[..9], [0x00, 0x08, 0, 0, 0, 3, 2, 0x84, 0x0B]);
+    }
+
+    #[test]
+    fn refuse_clients_over_limit() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let _consumer = ModbusConsumer::start(&ModbusSettings {
+            address: address.clone(),
+            unit_id: 1,
+            timeout: Some(30),
+            max_clients: 1,
+            allow: Vec::new(),
+            pipeline: Vec::new(),
+        })
+        .unwrap();
+        let request = [0x00, 0x07, 0, 0, 0, 6, 1, 0x04, 0, 52, 0, 2];
+        let read = |address: &str| {
+            let mut client = TcpStream::connect(address).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut response = [0u8; 9];
+            let result = client
+                .write_all(&request)
+                .and_then(|_| client.read_exact(&mut response));
+            (client, result.is_ok())
+        };
+
+        let (first, served) = read(&address);
+        assert!(served);
+        assert!(!read(&address).1);
+
+        // A client can connect again once the first one is gone
+        drop(first);
+        let start = Instant::now();
+        while !read(&address).1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
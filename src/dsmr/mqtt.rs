@@ -0,0 +1,381 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::settings::MqttSettings;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xc0;
+
+// Appends the remaining length of a packet, which takes 7 bits per byte.
+fn encode_length(buffer: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        buffer.push(byte);
+        if length == 0 {
+            return;
+        }
+    }
+}
+
+fn encode_string(buffer: &mut Vec<u8>, value: &[u8]) {
+    buffer.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(value);
+}
+
+fn packet(header: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![header];
+    encode_length(&mut packet, body.len());
+    packet.extend(body);
+    packet
+}
+
+// A retained message, such as the last will that the broker publishes when the client disappears.
+#[derive(Clone)]
+struct Message {
+    topic: String,
+    payload: Vec<u8>,
+}
+
+fn connect_packet(settings: &MqttSettings, keep_alive: u16, will: Option<&Message>) -> Vec<u8> {
+    // Clean session
+    let mut flags = 0x02;
+    if will.is_some() {
+        // Retained will with QoS 0
+        flags |= 0x24;
+    }
+    if settings.username.is_some() {
+        flags |= 0x80;
+    }
+    if settings.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut body = Vec::new();
+    encode_string(&mut body, b"MQTT");
+    body.extend_from_slice(&[4, flags]);
+    body.extend_from_slice(&keep_alive.to_be_bytes());
+    encode_string(&mut body, settings.client_id.as_bytes());
+    if let Some(will) = will {
+        encode_string(&mut body, will.topic.as_bytes());
+        encode_string(&mut body, &will.payload);
+    }
+    for value in [&settings.username, &settings.password]
+        .iter()
+        .copied()
+        .flatten()
+    {
+        encode_string(&mut body, value.as_bytes());
+    }
+    packet(CONNECT, body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_string(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(PUBLISH | retain as u8, body)
+}
+
+struct Connection {
+    stream: Option<TcpStream>,
+    // When the last packet was sent to the broker
+    last_sent: Instant,
+}
+impl Connection {
+    // Sends a PINGREQ when nothing was sent for the interval, after discarding the PINGRESP
+    // packets that arrived since the last ping.
+    fn ping(&mut self, interval: Duration) {
+        if self.last_sent.elapsed() < interval {
+            return;
+        }
+        if let Some(stream) = &mut self.stream {
+            let result = discard_incoming(stream).and_then(|_| stream.write_all(&[PINGREQ, 0]));
+            match result {
+                Ok(()) => self.last_sent = Instant::now(),
+                Err(msg) => {
+                    log::debug!("Could not ping the broker: {}", msg);
+                    self.stream = None;
+                }
+            }
+        }
+    }
+}
+
+fn discard_incoming(stream: &mut TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(true)?;
+    let mut buffer = [0u8; 64];
+    let result = loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => break Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => continue,
+            Err(msg) if msg.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+            Err(msg) => break Err(msg),
+        }
+    };
+    stream.set_nonblocking(false)?;
+    result
+}
+
+// Keeps the connection alive while no messages are published, such as when a pipeline holds
+// back telegrams, until the client is dropped.
+fn keep_alive(connection: Weak<Mutex<Connection>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match connection.upgrade() {
+            Some(connection) => connection.lock().unwrap().ping(interval),
+            None => return,
+        }
+    }
+}
+
+// Publishes messages to an MQTT 3.1.1 broker with QoS 0, reconnecting when the connection was lost.
+pub struct MqttClient {
+    settings: MqttSettings,
+    // Seconds without packets after which the broker considers the client gone, or 0 for never
+    keep_alive: u16,
+    will: Option<Message>,
+    // Retained message to publish after each connect, such as an availability of `online`
+    birth: Option<Message>,
+    connection: Arc<Mutex<Connection>>,
+}
+impl MqttClient {
+    pub fn new(settings: &MqttSettings) -> Self {
+        MqttClient {
+            settings: settings.clone(),
+            keep_alive: 0,
+            will: None,
+            birth: None,
+            connection: Arc::new(Mutex::new(Connection {
+                stream: None,
+                last_sent: Instant::now(),
+            })),
+        }
+    }
+
+    // Marks the client as available on the topic while it is connected. The client pings the
+    // broker at half the keep alive when it publishes nothing.
+    pub fn with_availability(mut self, topic: &str, keep_alive: u16) -> Self {
+        self.keep_alive = keep_alive;
+        if keep_alive > 0 {
+            let connection = Arc::downgrade(&self.connection);
+            let interval = Duration::from_secs(u64::from(keep_alive)) / 2;
+            thread::spawn(move || self::keep_alive(connection, interval));
+        }
+        self.will = Some(Message {
+            topic: String::from(topic),
+            payload: b"offline".to_vec(),
+        });
+        self.birth = Some(Message {
+            topic: String::from(topic),
+            payload: b"online".to_vec(),
+        });
+        self
+    }
+
+    fn connect(&self) -> Result<TcpStream, String> {
+        let broker = &self.settings.broker;
+        let address = broker
+            .to_socket_addrs()
+            .map_err(|msg| format!("Could not resolve {}: {}", broker, msg))?
+            .next()
+            .ok_or_else(|| format!("Could not resolve {}", broker))?;
+        let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)
+            .map_err(|msg| format!("Could not connect to {}: {}", broker, msg))?;
+        stream.set_read_timeout(Some(TIMEOUT)).ok();
+        stream.set_write_timeout(Some(TIMEOUT)).ok();
+
+        stream
+            .write_all(&connect_packet(
+                &self.settings,
+                self.keep_alive,
+                self.will.as_ref(),
+            ))
+            .map_err(|msg| format!("Could not connect to {}: {}", broker, msg))?;
+        let mut connack = [0u8; 4];
+        stream
+            .read_exact(&mut connack)
+            .map_err(|msg| format!("No answer from {}: {}", broker, msg))?;
+        match connack {
+            [CONNACK, 2, _, 0] => {
+                if let Some(birth) = &self.birth {
+                    stream
+                        .write_all(&publish_packet(&birth.topic, &birth.payload, true))
+                        .map_err(|msg| format!("Could not publish to {}: {}", broker, msg))?;
+                }
+                Ok(stream)
+            }
+            [CONNACK, 2, _, code] => Err(format!("{} refused the connection ({})", broker, code)),
+            _ => Err(format!("{} did not answer with CONNACK", broker)),
+        }
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<(), String> {
+        let packet = publish_packet(topic, payload, retain);
+        let mut connection = self.connection.lock().unwrap();
+        // A connection that was closed by the broker only shows when writing, so try twice
+        for _ in 0..2 {
+            if connection.stream.is_none() {
+                connection.stream = Some(self.connect()?);
+            }
+            if let Some(stream) = &mut connection.stream {
+                match stream.write_all(&packet) {
+                    Ok(()) => {
+                        connection.last_sent = Instant::now();
+                        return Ok(());
+                    }
+                    Err(msg) => {
+                        log::debug!("Lost connection to {}: {}", self.settings.broker, msg);
+                        connection.stream = None;
+                    }
+                }
+            }
+        }
+        Err(format!("Could not publish to {}", self.settings.broker))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Reads a packet with a remaining length below 128 bytes, returning its header.
+    fn read_packet(stream: &mut TcpStream) -> u8 {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).unwrap();
+        let mut body = vec![0u8; header[1] as usize];
+        stream.read_exact(&mut body).unwrap();
+        header[0]
+    }
+
+    fn settings(broker: &str) -> MqttSettings {
+        MqttSettings {
+            broker: String::from(broker),
+            client_id: String::from("dsmr-rs"),
+            username: Some(String::from("user")),
+            password: None,
+        }
+    }
+
+    #[test]
+    fn encode_remaining_length() {
+        let mut buffer = Vec::new();
+        encode_length(&mut buffer, 321);
+
+        assert_eq!(buffer, vec![0xc1, 0x02]);
+    }
+
+    #[test]
+    fn encode_connect_packet() {
+        let result = connect_packet(&settings("localhost:1883"), 0, None);
+
+        assert_eq!(
+            result,
+            [
+                &[0x10, 25, 0, 4][..],
+                b"MQTT",
+                &[4, 0x82, 0, 0, 0, 7],
+                b"dsmr-rs",
+                &[0, 4],
+                b"user"
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn encode_connect_packet_with_will() {
+        let will = Message {
+            topic: String::from("dsmr/status"),
+            payload: b"offline".to_vec(),
+        };
+        let mut settings = settings("localhost:1883");
+        settings.username = None;
+
+        let result = connect_packet(&settings, 60, Some(&will));
+
+        assert_eq!(
+            result,
+            [
+                &[0x10, 41, 0, 4][..],
+                b"MQTT",
+                &[4, 0x26, 0, 60, 0, 7],
+                b"dsmr-rs",
+                &[0, 11],
+                b"dsmr/status",
+                &[0, 7],
+                b"offline"
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn encode_retained_publish_packet() {
+        let result = publish_packet("dsmr/power", b"0.302", true);
+
+        assert_eq!(
+            result,
+            [&[0x31, 17, 0, 10][..], b"dsmr/power", b"0.302"].concat()
+        );
+    }
+
+    #[test]
+    fn publish_to_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut connect = [0u8; 27];
+            stream.read_exact(&mut connect).unwrap();
+            stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+            let mut publish = [0u8; 19];
+            stream.read_exact(&mut publish).unwrap();
+            publish.to_vec()
+        });
+
+        let mut client = MqttClient::new(&settings(&address));
+        client.publish("dsmr/power", b"0.302", false).unwrap();
+
+        assert_eq!(
+            broker.join().unwrap(),
+            publish_packet("dsmr/power", b"0.302", false)
+        );
+    }
+
+    #[test]
+    fn ping_broker_while_idle() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            assert_eq!(read_packet(&mut stream), CONNECT);
+            stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+            // Birth message and the published message
+            assert_eq!(read_packet(&mut stream), PUBLISH | 1);
+            assert_eq!(read_packet(&mut stream), PUBLISH);
+            read_packet(&mut stream)
+        });
+
+        let mut client = MqttClient::new(&settings(&address)).with_availability("dsmr/status", 2);
+        client.publish("dsmr/power", b"0.302", false).unwrap();
+
+        assert_eq!(broker.join().unwrap(), PINGREQ);
+    }
+}
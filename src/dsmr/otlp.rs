@@ -0,0 +1,309 @@
+use std::fs;
+
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use super::publisher::{equipment_identifier, sensor_values};
+use super::settings::OtlpSettings;
+use super::status::Health;
+use super::telegram::{self, Telegram};
+use super::TelegramConsumer;
+
+const VERSION: &str = "1-3:0.2.8";
+// Cumulative aggregation temporality in the OTLP protocol
+const CUMULATIVE: u8 = 2;
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn data_point(time: &str, value: f64, attributes: Vec<Value>) -> Value {
+    json!({ "timeUnixNano": time, "asDouble": value, "attributes": attributes })
+}
+
+fn gauge(name: &str, unit: &str, data_points: Vec<Value>) -> Value {
+    json!({ "name": name, "unit": unit, "gauge": { "dataPoints": data_points } })
+}
+
+// A cumulative sum that started counting at the given time.
+fn sum(name: &str, unit: &str, start: &str, mut data_points: Vec<Value>) -> Value {
+    for point in &mut data_points {
+        point["startTimeUnixNano"] = json!(start);
+    }
+    json!({
+        "name": name,
+        "unit": unit,
+        "sum": {
+            "aggregationTemporality": CUMULATIVE,
+            "isMonotonic": true,
+            "dataPoints": data_points,
+        },
+    })
+}
+
+// Exports the values of telegrams as OpenTelemetry metrics, in the JSON encoding of OTLP over
+// HTTP, together with metrics about the sinks of the logger itself.
+pub struct OtlpConsumer {
+    url: String,
+    headers: Vec<(String, String)>,
+    host: String,
+    // Number of telegrams that were exported
+    telegrams: u64,
+    // Meter time of the first exported telegram, as the start of the cumulative sums. The meter
+    // time is used so that it can not be after the time of a later data point.
+    start: Option<String>,
+    client: reqwest::blocking::Client,
+    health: Health,
+}
+impl OtlpConsumer {
+    pub fn new(settings: &OtlpSettings, health: Health) -> Self {
+        let endpoint = settings.endpoint.trim_end_matches('/');
+        let url = if endpoint.ends_with("/v1/metrics") {
+            String::from(endpoint)
+        } else {
+            format!("{}/v1/metrics", endpoint)
+        };
+        let host = fs::read_to_string("/etc/hostname")
+            .map(|host| String::from(host.trim()))
+            .unwrap_or_else(|_| String::from("localhost"));
+
+        OtlpConsumer {
+            url,
+            headers: settings.headers.clone(),
+            host,
+            telegrams: 0,
+            start: None,
+            client: reqwest::blocking::Client::new(),
+            health,
+        }
+    }
+
+    fn export(&mut self, telegram: &Telegram) -> Value {
+        let time = telegram
+            .timestamp()
+            .and_then(|timestamp| timestamp.timestamp_nanos_opt())
+            .unwrap_or_else(|| Utc::now().timestamp_nanos_opt().unwrap_or_default())
+            .to_string();
+        let start = self.start.get_or_insert_with(|| time.clone()).clone();
+
+        let mut resource = vec![
+            attribute("service.name", "dsmr-rs"),
+            attribute("host.name", &self.host),
+            attribute(
+                "meter.id",
+                &equipment_identifier(telegram).unwrap_or_else(|| String::from("unknown")),
+            ),
+        ];
+        if let Some(version) = telegram.value(VERSION) {
+            resource.push(attribute("dsmr.version", version));
+        }
+
+        let mut metrics: Vec<Value> = sensor_values(telegram)
+            .into_iter()
+            .map(|value| {
+                let name = format!("dsmr.{}", value.name);
+                let unit = value.quantity.unit.symbol();
+                let points = vec![data_point(&time, value.quantity.value.to_f64(), Vec::new())];
+                if telegram::is_cumulative(value.obis) {
+                    sum(&name, unit, &start, points)
+                } else {
+                    gauge(&name, unit, points)
+                }
+            })
+            .collect();
+
+        let points = vec![data_point(&time, self.telegrams as f64, Vec::new())];
+        metrics.push(sum("dsmr.logger.telegrams", "1", &start, points));
+        let sinks = self.health.snapshot();
+        if !sinks.is_empty() {
+            let healthy = sinks
+                .iter()
+                .map(|(sink, status)| {
+                    let value = if status.healthy() { 1.0 } else { 0.0 };
+                    data_point(&time, value, vec![attribute("sink", sink)])
+                })
+                .collect();
+            metrics.push(gauge("dsmr.logger.sink.healthy", "1", healthy));
+            let failures = sinks
+                .iter()
+                .map(|(sink, status)| {
+                    data_point(&time, status.failures as f64, vec![attribute("sink", sink)])
+                })
+                .collect();
+            // Failures are counted since the last success, so they go back to 0 on recovery
+            metrics.push(gauge("dsmr.logger.sink.failures", "1", failures));
+        }
+
+        json!({
+            "resourceMetrics": [{
+                "resource": { "attributes": resource },
+                "scopeMetrics": [{
+                    "scope": { "name": "dsmr-rs", "version": env!("CARGO_PKG_VERSION") },
+                    "metrics": metrics,
+                }],
+            }],
+        })
+    }
+}
+impl TelegramConsumer for OtlpConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        self.telegrams += 1;
+        let body = self.export(telegram).to_string();
+
+        log::trace!("- exporting metrics to {}", self.url);
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        match request.body(body).send() {
+            Ok(response) if !response.status().is_success() => {
+                log::warn!(
+                    "OTLP collector {} answered with status {}",
+                    self.url,
+                    response.status()
+                );
+                let error = format!("Response with status {}", response.status());
+                self.health.failure(&self.url, &error);
+            }
+            Ok(response) => {
+                log::trace!("Got response with status {}", response.status());
+                self.health.success(&self.url);
+            }
+            Err(msg) => {
+                log::warn!("Could not export metrics due to {}", msg);
+                self.health.failure(&self.url, &msg.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn telegram() -> Telegram {
+        Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(231026204015S)\r\n\
+             0-0:96.1.1(4530303334303036303436393733363134)\r\n\
+             1-0:1.8.1(000032.159*kWh)\r\n1-0:1.7.0(00.302*kW)\r\n!\r\n",
+        )
+    }
+
+    fn settings(endpoint: &str) -> OtlpSettings {
+        OtlpSettings {
+            endpoint: String::from(endpoint),
+            headers: vec![(String::from("X-Scope-OrgID"), String::from("home"))],
+            pipeline: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn export_values_as_metrics() {
+        let health = Health::default();
+        health.failure("http://dsmr-reader", "connection refused");
+        let mut consumer = OtlpConsumer::new(&settings("http://localhost:4318/"), health);
+        assert_eq!(consumer.url, "http://localhost:4318/v1/metrics");
+
+        let export = consumer.export(&telegram());
+
+        let resource = &export["resourceMetrics"][0]["resource"]["attributes"];
+        assert!(resource
+            .as_array()
+            .unwrap()
+            .contains(&attribute("meter.id", "E0034006046973614")));
+        assert!(resource
+            .as_array()
+            .unwrap()
+            .contains(&attribute("dsmr.version", "50")));
+
+        let metrics = &export["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(
+            metrics[0],
+            json!({
+                "name": "dsmr.electricity_delivered_1",
+                "unit": "kWh",
+                "sum": {
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                    "dataPoints": [{
+                        "startTimeUnixNano": "1698345615000000000",
+                        "timeUnixNano": "1698345615000000000",
+                        "asDouble": 32.159,
+                        "attributes": [],
+                    }],
+                },
+            })
+        );
+        assert_eq!(metrics[1]["name"], "dsmr.power_delivered");
+        assert_eq!(metrics[1]["gauge"]["dataPoints"][0]["asDouble"], 0.302);
+        assert_eq!(metrics[2]["name"], "dsmr.logger.telegrams");
+        assert_eq!(metrics[3]["name"], "dsmr.logger.sink.healthy");
+        assert_eq!(
+            metrics[3]["gauge"]["dataPoints"][0],
+            json!({
+                "timeUnixNano": "1698345615000000000",
+                "asDouble": 0.0,
+                "attributes": [attribute("sink", "http://dsmr-reader")],
+            })
+        );
+        assert_eq!(metrics[4]["name"], "dsmr.logger.sink.failures");
+        assert_eq!(metrics[4]["gauge"]["dataPoints"][0]["asDouble"], 1.0);
+
+        // Sums keep their start time
+        let export = consumer.export(&Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204020S)\r\n1-0:1.8.1(000032.160*kWh)\r\n!\r\n",
+        ));
+        let point =
+            &export["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][0]["sum"]["dataPoints"][0];
+        assert_eq!(point["startTimeUnixNano"], "1698345615000000000");
+        assert_eq!(point["timeUnixNano"], "1698345620000000000");
+    }
+
+    #[test]
+    fn post_metrics_to_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let collector = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            // Read until the body is complete, as given by its length
+            loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_lowercase();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .and_then(|length| length.parse::<usize>().ok());
+                    if length.is_some_and(|length| body.len() >= length) {
+                        break;
+                    }
+                }
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let health = Health::default();
+        let mut consumer = OtlpConsumer::new(&settings(&format!("http://{}", address)), health);
+        consumer.consume(&telegram());
+
+        let request = collector.join().unwrap();
+        assert!(request.starts_with("POST /v1/metrics HTTP/1.1"));
+        assert!(request.to_lowercase().contains("x-scope-orgid: home"));
+        assert!(request.contains("\"name\":\"dsmr.power_delivered\""));
+        assert!(consumer.health.snapshot()[&consumer.url].healthy());
+    }
+}
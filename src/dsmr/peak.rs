@@ -0,0 +1,424 @@
+use std::fs;
+
+use chrono::{DateTime, TimeZone};
+use chrono_tz::Europe::Amsterdam;
+use chrono_tz::Tz;
+
+use super::settings::PeakSettings;
+use super::telegram::Telegram;
+use super::value::{Decimal, Unit};
+use super::TelegramConsumer;
+
+const QUARTER: i64 = 900;
+const DELIVERED_TOTAL: &str = "1-0:1.8.0";
+const DELIVERED_TARIFF_PREFIX: &str = "1-0:1.8.";
+const ACTUAL_POWER: &str = "1-0:1.7.0";
+// Some meters (e.g. in Belgium) report the average demand of the running quarter hour themselves
+const AVERAGE_DEMAND: &str = "1-0:1.4.0";
+// Capacity tariffs are based on the average of the monthly peaks of the last year
+const HISTORY_MONTHS: usize = 12;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonthlyPeak {
+    pub month: String,
+    // Average demand in kW of the quarter hour with the highest demand
+    pub demand: Decimal,
+    pub at: DateTime<Tz>,
+}
+
+struct Quarter {
+    start: i64,
+    energy_at_start: Decimal,
+    energy: Decimal,
+    average_demand: Option<Decimal>,
+    // Whether the energy at the start of the quarter is known
+    complete: bool,
+    warned: bool,
+}
+
+// Tracks the average demand per quarter hour, which capacity tariffs use for billing
+// the highest quarter hour of each month.
+pub struct PeakDemandConsumer {
+    threshold: Option<Decimal>,
+    state_file: Option<String>,
+    quarter: Option<Quarter>,
+    history: Vec<MonthlyPeak>,
+}
+
+fn quantity_in(telegram: &Telegram, obis: &str, unit: Unit) -> Option<Decimal> {
+    telegram
+        .quantity(obis)
+        .and_then(|quantity| quantity.convert(unit).ok())
+        .map(|quantity| quantity.value)
+}
+
+// Energy delivered to the client in kWh, summed over all tariffs.
+fn delivered_energy(telegram: &Telegram) -> Option<Decimal> {
+    if let Some(total) = quantity_in(telegram, DELIVERED_TOTAL, Unit::KiloWattHour) {
+        return Some(total);
+    }
+    telegram
+        .objects
+        .iter()
+        .filter(|object| object.obis.starts_with(DELIVERED_TARIFF_PREFIX))
+        .filter_map(|object| object.quantity())
+        .filter_map(|quantity| quantity.convert(Unit::KiloWattHour).ok())
+        .map(|quantity| quantity.value)
+        .reduce(|a, b| a + b)
+}
+
+fn parse_peak(line: &str) -> Option<MonthlyPeak> {
+    let mut parts = line.split_whitespace();
+    let month = parts.next()?;
+    let demand = Decimal::parse(parts.next()?).ok()?;
+    let at = DateTime::parse_from_rfc3339(parts.next()?).ok()?;
+
+    Some(MonthlyPeak {
+        month: String::from(month),
+        demand,
+        at: at.with_timezone(&Amsterdam),
+    })
+}
+
+impl PeakDemandConsumer {
+    pub fn new(settings: &PeakSettings) -> Self {
+        let history = match &settings.state_file {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(content) => content.lines().filter_map(parse_peak).collect(),
+                Err(msg) => {
+                    log::info!("Not reading peak demand history from {}: {}", path, msg);
+                    Vec::new()
+                }
+            },
+            None => Vec::new(),
+        };
+
+        PeakDemandConsumer {
+            threshold: settings.threshold,
+            state_file: settings.state_file.clone(),
+            quarter: None,
+            history,
+        }
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.state_file {
+            let content: String = self
+                .history
+                .iter()
+                .map(|peak| format!("{} {} {}\n", peak.month, peak.demand, peak.at.to_rfc3339()))
+                .collect();
+            // Replace the file at once, so a power cut does not leave it half written
+            let temporary = format!("{}.tmp", path);
+            if let Err(msg) =
+                fs::write(&temporary, content).and_then(|_| fs::rename(&temporary, path))
+            {
+                log::warn!(
+                    "Could not save peak demand history to {} due to {}",
+                    path,
+                    msg
+                );
+            }
+        }
+    }
+
+    fn record(&mut self, start: i64, demand: Decimal) {
+        let at = Amsterdam.timestamp_opt(start, 0).unwrap();
+        let month = at.format("%Y-%m").to_string();
+
+        match self.history.last_mut() {
+            Some(peak) if peak.month == month => {
+                if demand <= peak.demand {
+                    return;
+                }
+                peak.demand = demand;
+                peak.at = at;
+            }
+            _ => {
+                self.history.push(MonthlyPeak { month, demand, at });
+                let excess = self.history.len().saturating_sub(HISTORY_MONTHS);
+                self.history.drain(..excess);
+            }
+        }
+
+        let sum = self
+            .history
+            .iter()
+            .map(|peak| peak.demand)
+            .fold(Decimal::new(0, 0), |a, b| a + b);
+        log::info!(
+            "New peak demand of {} kW in quarter hour starting at {}, average of last {} month(s) is {} kW",
+            demand,
+            at,
+            self.history.len(),
+            sum.divide(self.history.len() as i64)
+        );
+        self.save();
+    }
+
+    // Returns the average demand in kW of the quarter hour that closed with this telegram, if any.
+    fn update(&mut self, telegram: &Telegram) -> Option<Decimal> {
+        let timestamp = telegram.timestamp()?.timestamp();
+        let energy = delivered_energy(telegram)?;
+        let start = timestamp - timestamp.rem_euclid(QUARTER);
+
+        // The counter at the first telegram of a quarter is the end of the previous quarter
+        let mut closed = None;
+        match self.quarter.take() {
+            Some(quarter) if quarter.start == start => self.quarter = Some(quarter),
+            previous => {
+                let adjacent = match previous {
+                    Some(quarter) => {
+                        let adjacent = quarter.start + QUARTER == start;
+                        let demand = match quarter.average_demand {
+                            Some(demand) => Some(demand),
+                            None if quarter.complete && adjacent => {
+                                Some((energy - quarter.energy_at_start).times(4, 1))
+                            }
+                            None => None,
+                        };
+                        if let Some(demand) = demand {
+                            self.record(quarter.start, demand);
+                            closed = Some(demand);
+                        }
+                        adjacent
+                    }
+                    None => false,
+                };
+
+                self.quarter = Some(Quarter {
+                    start,
+                    energy_at_start: energy,
+                    energy,
+                    average_demand: None,
+                    complete: adjacent || timestamp == start,
+                    warned: false,
+                });
+            }
+        }
+
+        let quarter = self.quarter.as_mut().unwrap();
+        quarter.energy = energy;
+        quarter.average_demand = quantity_in(telegram, AVERAGE_DEMAND, Unit::KiloWatt);
+
+        if let (Some(threshold), Some(power)) = (
+            self.threshold,
+            quantity_in(telegram, ACTUAL_POWER, Unit::KiloWatt),
+        ) {
+            let elapsed = timestamp - start;
+            let remaining = QUARTER - elapsed;
+            let so_far = match quarter.average_demand {
+                Some(demand) => demand.times(elapsed, QUARTER),
+                None => (quarter.energy - quarter.energy_at_start).times(4, 1),
+            };
+            let projected = so_far + power.times(remaining, QUARTER);
+
+            if projected > threshold && !quarter.warned {
+                log::warn!(
+                    "Demand in quarter hour starting at {} is projected to be {} kW, exceeding {} kW",
+                    Amsterdam.timestamp_opt(start, 0).unwrap(),
+                    projected,
+                    threshold
+                );
+                quarter.warned = true;
+            }
+        }
+
+        closed
+    }
+}
+
+impl TelegramConsumer for PeakDemandConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        self.update(telegram);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn settings(state_file: Option<String>) -> PeakSettings {
+        PeakSettings {
+            threshold: Some(Decimal::parse("2.5").unwrap()),
+            state_file,
+        }
+    }
+
+    fn telegram(timestamp: &str, tariff1: &str, tariff2: &str, power: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n1-0:1.8.1({}*kWh)\r\n1-0:1.8.2({}*kWh)\r\n1-0:1.7.0({}*kW)\r\n!\r\n",
+            timestamp, tariff1, tariff2, power
+        ))
+    }
+
+    #[test]
+    fn delivered_energy_sums_tariffs() {
+        let result = delivered_energy(&telegram(
+            "231026204015S",
+            "000032.159",
+            "000002.167",
+            "00.302",
+        ));
+
+        assert_eq!(result, Some(Decimal::parse("34.326").unwrap()));
+    }
+
+    #[test]
+    fn average_demand_from_counter_deltas() {
+        let mut consumer = PeakDemandConsumer::new(&settings(None));
+
+        assert_eq!(
+            consumer.update(&telegram(
+                "231026203959S",
+                "000032.000",
+                "000002.000",
+                "01.000"
+            )),
+            None
+        );
+        assert_eq!(
+            consumer.update(&telegram(
+                "231026204500S",
+                "000032.250",
+                "000002.000",
+                "01.000"
+            )),
+            None
+        );
+        let result = consumer.update(&telegram(
+            "231026210000S",
+            "000032.500",
+            "000002.100",
+            "01.000",
+        ));
+
+        // 0.350 kWh in a quarter hour is an average demand of 1.4 kW
+        assert_eq!(result, Some(Decimal::parse("1.4").unwrap()));
+        assert_eq!(consumer.history.len(), 1);
+        assert_eq!(consumer.history[0].month, "2023-10");
+        assert_eq!(consumer.history[0].demand, Decimal::parse("1.4").unwrap());
+    }
+
+    #[test]
+    fn skip_incomplete_quarter() {
+        let mut consumer = PeakDemandConsumer::new(&settings(None));
+
+        consumer.update(&telegram(
+            "231026204015S",
+            "000032.000",
+            "000002.000",
+            "01.000",
+        ));
+        let result = consumer.update(&telegram(
+            "231026204500S",
+            "000032.250",
+            "000002.000",
+            "01.000",
+        ));
+
+        assert_eq!(result, None);
+        assert!(consumer.history.is_empty());
+    }
+
+    #[test]
+    fn average_demand_reported_by_meter() {
+        let mut consumer = PeakDemandConsumer::new(&settings(None));
+        let with_demand = |timestamp: &str, demand: &str| {
+            Telegram::parse(&format!(
+                "/FLU5\\253770234_A\r\n0-0:1.0.0({})\r\n1-0:1.8.1(000032.159*kWh)\r\n1-0:1.4.0({}*kW)\r\n!\r\n",
+                timestamp, demand
+            ))
+        };
+
+        consumer.update(&with_demand("231026204015S", "01.234"));
+        let result = consumer.update(&with_demand("231026204500S", "00.100"));
+
+        assert_eq!(result, Some(Decimal::parse("1.234").unwrap()));
+    }
+
+    #[test]
+    fn keep_highest_quarter_per_month() {
+        let mut consumer = PeakDemandConsumer::new(&settings(None));
+
+        consumer.update(&telegram(
+            "231031234500W",
+            "000010.000",
+            "000000.000",
+            "01.000",
+        ));
+        consumer.update(&telegram(
+            "231101000000W",
+            "000010.500",
+            "000000.000",
+            "01.000",
+        ));
+        consumer.update(&telegram(
+            "231101001500W",
+            "000010.750",
+            "000000.000",
+            "01.000",
+        ));
+        consumer.update(&telegram(
+            "231101003000W",
+            "000011.500",
+            "000000.000",
+            "01.000",
+        ));
+
+        assert_eq!(consumer.history.len(), 2);
+        assert_eq!(consumer.history[0].month, "2023-10");
+        assert_eq!(consumer.history[0].demand, Decimal::parse("2").unwrap());
+        assert_eq!(consumer.history[1].month, "2023-11");
+        assert_eq!(consumer.history[1].demand, Decimal::parse("3").unwrap());
+    }
+
+    #[test]
+    fn warn_once_when_projected_to_exceed_threshold() {
+        let mut consumer = PeakDemandConsumer::new(&settings(None));
+
+        consumer.update(&telegram(
+            "231026204500S",
+            "000032.000",
+            "000002.000",
+            "01.000",
+        ));
+        assert!(!consumer.quarter.as_ref().unwrap().warned);
+
+        // 0.5 kWh after five minutes, plus ten more minutes at 3 kW, makes an average of 4 kW
+        consumer.update(&telegram(
+            "231026205000S",
+            "000032.500",
+            "000002.000",
+            "03.000",
+        ));
+        assert!(consumer.quarter.as_ref().unwrap().warned);
+    }
+
+    #[test]
+    fn persist_history() {
+        let path = std::env::temp_dir().join(format!("dsmr-rs-peaks-{}.txt", std::process::id()));
+        let state_file = Some(String::from(path.to_str().unwrap()));
+
+        let mut consumer = PeakDemandConsumer::new(&settings(state_file.clone()));
+        consumer.update(&telegram(
+            "231026204500S",
+            "000032.000",
+            "000002.000",
+            "01.000",
+        ));
+        consumer.update(&telegram(
+            "231026210000S",
+            "000032.500",
+            "000002.000",
+            "01.000",
+        ));
+
+        let restarted = PeakDemandConsumer::new(&settings(state_file));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restarted.history, consumer.history);
+        assert_eq!(restarted.history[0].demand, Decimal::parse("2").unwrap());
+    }
+}
@@ -0,0 +1,346 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use super::settings::StageSetting;
+use super::telegram::{self, CosemObject, Telegram};
+use super::value::Quantity;
+use super::TelegramConsumer;
+
+// A step between the reader and a sink that can drop or transform telegrams.
+pub trait Stage {
+    // Returns the telegram to pass on, or nothing when it must be dropped.
+    fn process(&mut self, telegram: Telegram) -> Option<Telegram>;
+}
+
+struct FilterStage {
+    obis: String,
+}
+impl Stage for FilterStage {
+    fn process(&mut self, telegram: Telegram) -> Option<Telegram> {
+        if telegram.object(&self.obis).is_some() {
+            Some(telegram)
+        } else {
+            None
+        }
+    }
+}
+
+struct UnflaggedStage {}
+impl Stage for UnflaggedStage {
+    fn process(&mut self, telegram: Telegram) -> Option<Telegram> {
+        if telegram.flags.is_empty() {
+            Some(telegram)
+        } else {
+            None
+        }
+    }
+}
+
+// Only changes the parsed objects; the raw telegram is kept intact so its checksum stays valid.
+struct SelectStage {
+    obis: Vec<String>,
+}
+impl Stage for SelectStage {
+    fn process(&mut self, mut telegram: Telegram) -> Option<Telegram> {
+        telegram
+            .objects
+            .retain(|object| self.obis.contains(&object.obis));
+        Some(telegram)
+    }
+}
+
+struct ThrottleStage {
+    interval: Duration,
+    last_passed: Option<Instant>,
+}
+impl ThrottleStage {
+    fn process_at(&mut self, telegram: Telegram, now: Instant) -> Option<Telegram> {
+        match self.last_passed {
+            Some(last) if now.duration_since(last) < self.interval => None,
+            _ => {
+                self.last_passed = Some(now);
+                Some(telegram)
+            }
+        }
+    }
+}
+impl Stage for ThrottleStage {
+    fn process(&mut self, telegram: Telegram) -> Option<Telegram> {
+        self.process_at(telegram, Instant::now())
+    }
+}
+
+struct DedupeStage {
+    previous: Option<Vec<CosemObject>>,
+}
+impl Stage for DedupeStage {
+    fn process(&mut self, telegram: Telegram) -> Option<Telegram> {
+        let objects: Vec<CosemObject> = telegram
+            .objects
+            .iter()
+            .filter(|object| object.obis != telegram::TIMESTAMP)
+            .cloned()
+            .collect();
+
+        if self.previous.as_ref() == Some(&objects) {
+            return None;
+        }
+        self.previous = Some(objects);
+        Some(telegram)
+    }
+}
+
+struct ChangeStage {
+    obis: String,
+    threshold: Quantity,
+    previous: Option<Quantity>,
+}
+impl Stage for ChangeStage {
+    fn process(&mut self, telegram: Telegram) -> Option<Telegram> {
+        let current = match telegram
+            .quantity(&self.obis)
+            .map(|quantity| quantity.convert(self.threshold.unit))
+        {
+            Some(Ok(current)) => current,
+            // Without a comparable value there is nothing to decide on
+            _ => return Some(telegram),
+        };
+
+        if let Some(previous) = self.previous {
+            let change = current.value - previous.value;
+            if change <= self.threshold.value && -change <= self.threshold.value {
+                return None;
+            }
+        }
+        self.previous = Some(current);
+        Some(telegram)
+    }
+}
+
+struct TeeStage {
+    path: String,
+    file: Option<File>,
+}
+impl Stage for TeeStage {
+    fn process(&mut self, telegram: Telegram) -> Option<Telegram> {
+        if self.file.is_none() {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+            {
+                Ok(file) => self.file = Some(file),
+                Err(msg) => log::warn!("Could not open {} due to {}", self.path, msg),
+            }
+        }
+        if let Some(file) = &mut self.file {
+            if let Err(msg) = file.write_all(telegram.raw.as_bytes()) {
+                log::warn!("Could not write telegram to {} due to {}", self.path, msg);
+                // Try to re-open the file for the next telegram
+                self.file = None;
+            }
+        }
+        Some(telegram)
+    }
+}
+
+fn create_stage(setting: &StageSetting) -> Box<dyn Stage> {
+    match setting {
+        StageSetting::Filter(obis) => Box::new(FilterStage { obis: obis.clone() }),
+        StageSetting::Unflagged => Box::new(UnflaggedStage {}),
+        StageSetting::Select(obis) => Box::new(SelectStage { obis: obis.clone() }),
+        StageSetting::Throttle(seconds) => Box::new(ThrottleStage {
+            interval: Duration::from_secs(*seconds),
+            last_passed: None,
+        }),
+        StageSetting::Dedupe => Box::new(DedupeStage { previous: None }),
+        StageSetting::Change(obis, threshold) => Box::new(ChangeStage {
+            obis: obis.clone(),
+            threshold: *threshold,
+            previous: None,
+        }),
+        StageSetting::Tee(path) => Box::new(TeeStage {
+            path: path.clone(),
+            file: None,
+        }),
+    }
+}
+
+// Runs each telegram through a sequence of stages before handing it to a sink.
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+    sink: Box<dyn TelegramConsumer>,
+}
+impl Pipeline {
+    pub fn new(settings: &[StageSetting], sink: Box<dyn TelegramConsumer>) -> Self {
+        Pipeline {
+            stages: settings.iter().map(create_stage).collect(),
+            sink,
+        }
+    }
+}
+impl TelegramConsumer for Pipeline {
+    fn consume(&mut self, telegram: &Telegram) {
+        if self.stages.is_empty() {
+            self.sink.consume(telegram);
+            return;
+        }
+
+        let mut current = telegram.clone();
+        for stage in &mut self.stages {
+            match stage.process(current) {
+                Some(next) => current = next,
+                None => return,
+            }
+        }
+        self.sink.consume(&current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    struct CountingConsumer {
+        count: Rc<RefCell<u32>>,
+    }
+    impl TelegramConsumer for CountingConsumer {
+        fn consume(&mut self, _telegram: &Telegram) {
+            *self.count.borrow_mut() += 1;
+        }
+    }
+
+    fn telegram(timestamp: &str, power: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n1-0:1.8.1(000032.159*kWh)\r\n1-0:1.7.0({}*kW)\r\n!\r\n",
+            timestamp, power
+        ))
+    }
+
+    #[test]
+    fn filter_on_obis_code() {
+        let mut stage = FilterStage {
+            obis: String::from("1-0:1.7.0"),
+        };
+
+        assert!(stage.process(telegram("231026204015S", "00.302")).is_some());
+        assert!(stage
+            .process(Telegram::parse("/ISK5\\2M550T-1013\r\n!\r\n"))
+            .is_none());
+    }
+
+    #[test]
+    fn unflagged_drops_flagged_telegrams() {
+        let mut stage = UnflaggedStage {};
+        let mut flagged = telegram("231026204015S", "00.302");
+        flagged.flags.push(String::from("Something is off"));
+
+        assert!(stage.process(telegram("231026204015S", "00.302")).is_some());
+        assert!(stage.process(flagged).is_none());
+    }
+
+    #[test]
+    fn select_keeps_raw_telegram() {
+        let mut stage = SelectStage {
+            obis: vec![String::from("1-0:1.7.0")],
+        };
+        let input = telegram("231026204015S", "00.302");
+
+        let result = stage.process(input.clone()).unwrap();
+
+        assert_eq!(result.objects.len(), 1);
+        assert_eq!(result.objects[0].obis, "1-0:1.7.0");
+        assert_eq!(result.raw, input.raw);
+    }
+
+    #[test]
+    fn throttle_passes_one_telegram_per_interval() {
+        let mut stage = ThrottleStage {
+            interval: Duration::from_secs(60),
+            last_passed: None,
+        };
+        let start = Instant::now();
+
+        let first = stage.process_at(telegram("231026204015S", "00.302"), start);
+        let second = stage.process_at(
+            telegram("231026204025S", "00.302"),
+            start + Duration::from_secs(10),
+        );
+        let third = stage.process_at(
+            telegram("231026204115S", "00.302"),
+            start + Duration::from_secs(60),
+        );
+
+        assert!(first.is_some());
+        assert!(second.is_none());
+        assert!(third.is_some());
+    }
+
+    #[test]
+    fn dedupe_ignores_timestamp() {
+        let mut stage = DedupeStage { previous: None };
+
+        assert!(stage.process(telegram("231026204015S", "00.302")).is_some());
+        assert!(stage.process(telegram("231026204016S", "00.302")).is_none());
+        assert!(stage.process(telegram("231026204017S", "00.303")).is_some());
+    }
+
+    #[test]
+    fn change_passes_significant_changes() {
+        let mut stage = ChangeStage {
+            obis: String::from("1-0:1.7.0"),
+            threshold: Quantity::parse("50*W").unwrap(),
+            previous: None,
+        };
+
+        assert!(stage.process(telegram("231026204015S", "00.302")).is_some());
+        assert!(stage.process(telegram("231026204016S", "00.350")).is_none());
+        assert!(stage.process(telegram("231026204017S", "00.353")).is_some());
+        assert!(stage.process(telegram("231026204018S", "00.302")).is_some());
+    }
+
+    #[test]
+    fn tee_appends_raw_telegram() {
+        let path = std::env::temp_dir().join(format!("dsmr-rs-tee-{}.txt", std::process::id()));
+        let mut stage = TeeStage {
+            path: String::from(path.to_str().unwrap()),
+            file: None,
+        };
+        let input = telegram("231026204015S", "00.302");
+
+        assert!(stage.process(input.clone()).is_some());
+        assert!(stage.process(input.clone()).is_some());
+
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written, [input.raw.as_str(), input.raw.as_str()].concat());
+    }
+
+    #[test]
+    fn pipeline_runs_stages_in_order() {
+        let count = Rc::new(RefCell::new(0));
+        let sink = CountingConsumer {
+            count: count.clone(),
+        };
+        let mut pipeline = Pipeline::new(
+            &[
+                StageSetting::Filter(String::from("1-0:1.7.0")),
+                StageSetting::Dedupe,
+            ],
+            Box::new(sink),
+        );
+
+        pipeline.consume(&telegram("231026204015S", "00.302"));
+        pipeline.consume(&telegram("231026204016S", "00.302"));
+        pipeline.consume(&Telegram::parse("/ISK5\\2M550T-1013\r\n!\r\n"));
+        pipeline.consume(&telegram("231026204017S", "00.303"));
+
+        assert_eq!(*count.borrow(), 2);
+    }
+}
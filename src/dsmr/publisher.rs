@@ -0,0 +1,538 @@
+use std::collections::HashSet;
+
+use serde_json::json;
+
+use super::mqtt::MqttClient;
+use super::settings::{MqttSettings, MqttSinkSettings};
+use super::status::Health;
+use super::telegram::Telegram;
+use super::value::{Quantity, Unit};
+use super::TelegramConsumer;
+
+const EQUIPMENT_IDENTIFIER: &str = "0-0:96.1.1";
+// Seconds without packets after which the broker marks the meter as offline, which the client
+// pings within while a pipeline holds back telegrams
+const KEEP_ALIVE: u16 = 60;
+
+struct Sensor {
+    // OBIS code, where `*` matches the channel of an M-Bus device
+    obis: &'static str,
+    object_id: &'static str,
+    name: &'static str,
+    device_class: &'static str,
+    state_class: &'static str,
+    unit: Unit,
+}
+
+const fn energy(obis: &'static str, object_id: &'static str, name: &'static str) -> Sensor {
+    Sensor {
+        obis,
+        object_id,
+        name,
+        device_class: "energy",
+        state_class: "total_increasing",
+        unit: Unit::KiloWattHour,
+    }
+}
+
+const fn measurement(
+    obis: &'static str,
+    object_id: &'static str,
+    name: &'static str,
+    device_class: &'static str,
+    unit: Unit,
+) -> Sensor {
+    Sensor {
+        obis,
+        object_id,
+        name,
+        device_class,
+        state_class: "measurement",
+        unit,
+    }
+}
+
+// Returns the unit as Home Assistant expects it.
+fn unit_of_measurement(unit: Unit) -> &'static str {
+    match unit {
+        Unit::CubicMetre => "m³",
+        unit => unit.symbol(),
+    }
+}
+
+const SENSORS: [Sensor; 23] = [
+    energy(
+        "1-0:1.8.1",
+        "electricity_delivered_1",
+        "Energy delivered tariff 1",
+    ),
+    energy(
+        "1-0:1.8.2",
+        "electricity_delivered_2",
+        "Energy delivered tariff 2",
+    ),
+    energy(
+        "1-0:2.8.1",
+        "electricity_returned_1",
+        "Energy returned tariff 1",
+    ),
+    energy(
+        "1-0:2.8.2",
+        "electricity_returned_2",
+        "Energy returned tariff 2",
+    ),
+    measurement(
+        "1-0:1.7.0",
+        "power_delivered",
+        "Power delivered",
+        "power",
+        Unit::KiloWatt,
+    ),
+    measurement(
+        "1-0:2.7.0",
+        "power_returned",
+        "Power returned",
+        "power",
+        Unit::KiloWatt,
+    ),
+    measurement(
+        "1-0:21.7.0",
+        "power_delivered_l1",
+        "Power delivered L1",
+        "power",
+        Unit::KiloWatt,
+    ),
+    measurement(
+        "1-0:41.7.0",
+        "power_delivered_l2",
+        "Power delivered L2",
+        "power",
+        Unit::KiloWatt,
+    ),
+    measurement(
+        "1-0:61.7.0",
+        "power_delivered_l3",
+        "Power delivered L3",
+        "power",
+        Unit::KiloWatt,
+    ),
+    measurement(
+        "1-0:22.7.0",
+        "power_returned_l1",
+        "Power returned L1",
+        "power",
+        Unit::KiloWatt,
+    ),
+    measurement(
+        "1-0:42.7.0",
+        "power_returned_l2",
+        "Power returned L2",
+        "power",
+        Unit::KiloWatt,
+    ),
+    measurement(
+        "1-0:62.7.0",
+        "power_returned_l3",
+        "Power returned L3",
+        "power",
+        Unit::KiloWatt,
+    ),
+    measurement(
+        "1-0:32.7.0",
+        "voltage_l1",
+        "Voltage L1",
+        "voltage",
+        Unit::Volt,
+    ),
+    measurement(
+        "1-0:52.7.0",
+        "voltage_l2",
+        "Voltage L2",
+        "voltage",
+        Unit::Volt,
+    ),
+    measurement(
+        "1-0:72.7.0",
+        "voltage_l3",
+        "Voltage L3",
+        "voltage",
+        Unit::Volt,
+    ),
+    measurement(
+        "1-0:31.7.0",
+        "current_l1",
+        "Current L1",
+        "current",
+        Unit::Ampere,
+    ),
+    measurement(
+        "1-0:51.7.0",
+        "current_l2",
+        "Current L2",
+        "current",
+        Unit::Ampere,
+    ),
+    measurement(
+        "1-0:71.7.0",
+        "current_l3",
+        "Current L3",
+        "current",
+        Unit::Ampere,
+    ),
+    energy("1-0:1.8.0", "electricity_delivered", "Energy delivered"),
+    energy("1-0:2.8.0", "electricity_returned", "Energy returned"),
+    measurement(
+        "1-0:1.4.0",
+        "average_demand",
+        "Average demand",
+        "power",
+        Unit::KiloWatt,
+    ),
+    measurement(
+        "1-0:1.6.0",
+        "peak_demand",
+        "Peak demand this month",
+        "power",
+        Unit::KiloWatt,
+    ),
+    Sensor {
+        obis: "0-*:24.2.1",
+        object_id: "gas_delivered",
+        name: "Gas delivered",
+        device_class: "gas",
+        state_class: "total_increasing",
+        unit: Unit::CubicMetre,
+    },
+];
+
+fn matches(pattern: &str, obis: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            obis.len() > pattern.len() - 1 && obis.starts_with(prefix) && obis.ends_with(suffix)
+        }
+        None => pattern == obis,
+    }
+}
+
+// Returns the OBIS code and value of the sensor in the telegram, in the unit of the sensor.
+fn sensor_value<'a>(sensor: &Sensor, telegram: &'a Telegram) -> Option<(&'a str, Quantity)> {
+    telegram
+        .objects
+        .iter()
+        .filter(|object| matches(sensor.obis, &object.obis))
+        .find_map(|object| {
+            let quantity = object.quantity()?.convert(sensor.unit).ok()?;
+            Some((object.obis.as_str(), quantity))
+        })
+}
+
+pub struct SensorValue<'a> {
+    // Name of the sensor, such as `power_delivered`
+    pub name: &'static str,
+    pub obis: &'a str,
+    pub quantity: Quantity,
+}
+
+// Returns the value of each known sensor in the telegram, for sinks that name their metrics.
+pub fn sensor_values(telegram: &Telegram) -> Vec<SensorValue<'_>> {
+    SENSORS
+        .iter()
+        .filter_map(|sensor| {
+            let (obis, quantity) = sensor_value(sensor, telegram)?;
+            Some(SensorValue {
+                name: sensor.object_id,
+                obis,
+                quantity,
+            })
+        })
+        .collect()
+}
+
+// Meters report their equipment identifier as hexadecimal ASCII, such as `4530303334...`.
+pub fn equipment_identifier(telegram: &Telegram) -> Option<String> {
+    let value = telegram.value(EQUIPMENT_IDENTIFIER)?;
+    let decoded: Option<String> = (0..value.len())
+        .step_by(2)
+        .map(|index| {
+            let byte = u8::from_str_radix(value.get(index..index + 2)?, 16).ok()?;
+            Some(byte as char).filter(char::is_ascii_graphic)
+        })
+        .collect();
+    Some(decoded.unwrap_or_else(|| String::from(value)))
+}
+
+// Publishes the values of each telegram to an MQTT broker, one topic per sensor, together with
+// Home Assistant discovery messages for each sensor in the telegram.
+pub struct PublishingConsumer {
+    topic: String,
+    discovery_prefix: Option<String>,
+    client: MqttClient,
+    // Object ids of the sensors that were announced to Home Assistant
+    announced: HashSet<&'static str>,
+    failing: bool,
+    // Name of the sink in the health report
+    name: String,
+    health: Health,
+}
+impl PublishingConsumer {
+    pub fn new(settings: &MqttSinkSettings, mqtt: &MqttSettings, health: Health) -> Self {
+        let availability = format!("{}/status", settings.topic);
+        PublishingConsumer {
+            topic: settings.topic.clone(),
+            discovery_prefix: settings.discovery_prefix.clone(),
+            client: MqttClient::new(mqtt).with_availability(&availability, KEEP_ALIVE),
+            announced: HashSet::new(),
+            failing: false,
+            name: format!("mqtt://{}", mqtt.broker),
+            health,
+        }
+    }
+
+    fn discovery_config(&self, sensor: &Sensor, telegram: &Telegram) -> serde_json::Value {
+        let identifier = equipment_identifier(telegram).unwrap_or_else(|| String::from("meter"));
+        let node_id: String = identifier
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_lowercase();
+        let header = telegram.header.trim_start_matches('/');
+
+        json!({
+            "name": sensor.name,
+            "unique_id": format!("dsmr_{}_{}", node_id, sensor.object_id),
+            "state_topic": format!("{}/{}", self.topic, sensor.object_id),
+            "availability_topic": format!("{}/status", self.topic),
+            "device_class": sensor.device_class,
+            "state_class": sensor.state_class,
+            "unit_of_measurement": unit_of_measurement(sensor.unit),
+            "device": {
+                "identifiers": [format!("dsmr_{}", node_id)],
+                "name": format!("Smart meter {}", identifier),
+                "manufacturer": header.get(..3).unwrap_or(header),
+                "model": header,
+                "serial_number": identifier,
+            },
+        })
+    }
+
+    // Returns the messages to publish for the telegram, as topic, payload and whether to retain it.
+    fn messages(&mut self, telegram: &Telegram) -> Vec<(String, String, bool)> {
+        let mut messages = Vec::new();
+        for sensor in &SENSORS {
+            let value = match sensor_value(sensor, telegram) {
+                Some((_, value)) => value.value,
+                None => continue,
+            };
+
+            if let Some(prefix) = &self.discovery_prefix {
+                if !self.announced.contains(sensor.object_id) {
+                    let topic = format!(
+                        "{}/sensor/{}/{}/config",
+                        prefix,
+                        self.topic.replace('/', "_"),
+                        sensor.object_id
+                    );
+                    let config = self.discovery_config(sensor, telegram).to_string();
+                    messages.push((topic, config, true));
+                    self.announced.insert(sensor.object_id);
+                }
+            }
+            messages.push((
+                format!("{}/{}", self.topic, sensor.object_id),
+                value.to_string(),
+                false,
+            ));
+        }
+        messages
+    }
+}
+impl TelegramConsumer for PublishingConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        for (topic, payload, retain) in self.messages(telegram) {
+            match self.client.publish(&topic, payload.as_bytes(), retain) {
+                Ok(()) if self.failing => {
+                    log::info!("Publishing to MQTT broker again");
+                    self.failing = false;
+                }
+                Ok(()) => {}
+                Err(msg) => {
+                    if !self.failing {
+                        log::warn!("Could not publish telegram due to {}", msg);
+                        self.failing = true;
+                    }
+                    // Discovery messages that were not published must be sent again
+                    if retain {
+                        self.announced.clear();
+                    }
+                    self.health.failure(&self.name, &msg);
+                    return;
+                }
+            }
+        }
+        self.health.success(&self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::dsmr::pipeline::Pipeline;
+    use crate::dsmr::settings::StageSetting;
+
+    fn consumer(discovery_prefix: Option<&str>) -> PublishingConsumer {
+        let sink = MqttSinkSettings {
+            topic: String::from("dsmr"),
+            discovery_prefix: discovery_prefix.map(String::from),
+            pipeline: Vec::new(),
+        };
+        let mqtt = MqttSettings {
+            broker: String::from("localhost:1883"),
+            client_id: String::from("dsmr-rs"),
+            username: None,
+            password: None,
+        };
+        PublishingConsumer::new(&sink, &mqtt, Health::default())
+    }
+
+    fn telegram() -> Telegram {
+        Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:96.1.1(4530303334303036303436393733363134)\r\n\
+             1-0:1.8.1(000032.159*kWh)\r\n1-0:1.7.0(00.302*kW)\r\n\
+             0-1:24.2.1(231026204004S)(00004.381*m3)\r\n!\r\n",
+        )
+    }
+
+    #[test]
+    fn decode_equipment_identifier() {
+        assert_eq!(
+            equipment_identifier(&telegram()),
+            Some(String::from("E0034006046973614"))
+        );
+        let telegram = Telegram::parse("/ISK5\\2M550T-1013\r\n0-0:96.1.1(12ZZ)\r\n!\r\n");
+        assert_eq!(equipment_identifier(&telegram), Some(String::from("12ZZ")));
+    }
+
+    #[test]
+    fn match_obis_of_any_channel() {
+        assert!(matches("0-*:24.2.1", "0-1:24.2.1"));
+        assert!(matches("0-*:24.2.1", "0-4:24.2.1"));
+        assert!(!matches("0-*:24.2.1", "0-1:24.2.3"));
+        assert!(matches("1-0:1.8.1", "1-0:1.8.1"));
+    }
+
+    #[test]
+    fn values_of_sensors() {
+        let telegram = telegram();
+        let values = sensor_values(&telegram);
+
+        let names: Vec<&str> = values.iter().map(|value| value.name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "electricity_delivered_1",
+                "power_delivered",
+                "gas_delivered"
+            ]
+        );
+        assert_eq!(values[2].obis, "0-1:24.2.1");
+        assert_eq!(values[1].quantity.to_string(), "0.302 kW");
+    }
+
+    #[test]
+    fn publish_values_without_discovery() {
+        let mut consumer = consumer(None);
+
+        assert_eq!(
+            consumer.messages(&telegram()),
+            vec![
+                (
+                    String::from("dsmr/electricity_delivered_1"),
+                    String::from("32.159"),
+                    false
+                ),
+                (
+                    String::from("dsmr/power_delivered"),
+                    String::from("0.302"),
+                    false
+                ),
+                (
+                    String::from("dsmr/gas_delivered"),
+                    String::from("4.381"),
+                    false
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn announce_each_sensor_once() {
+        let mut consumer = consumer(Some("homeassistant"));
+
+        let messages = consumer.messages(&telegram());
+        assert_eq!(messages.len(), 6);
+        let (topic, config, retain) = &messages[4];
+        assert_eq!(topic, "homeassistant/sensor/dsmr/gas_delivered/config");
+        assert!(retain);
+
+        let config: serde_json::Value = serde_json::from_str(config).unwrap();
+        assert_eq!(config["unique_id"], "dsmr_e0034006046973614_gas_delivered");
+        assert_eq!(config["state_topic"], "dsmr/gas_delivered");
+        assert_eq!(config["availability_topic"], "dsmr/status");
+        assert_eq!(config["device_class"], "gas");
+        assert_eq!(config["state_class"], "total_increasing");
+        assert_eq!(config["unit_of_measurement"], "m³");
+        assert_eq!(config["device"]["manufacturer"], "ISK");
+        assert_eq!(config["device"]["serial_number"], "E0034006046973614");
+
+        assert_eq!(consumer.messages(&telegram()).len(), 3);
+    }
+
+    #[test]
+    fn stay_online_behind_throttled_pipeline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut headers = Vec::new();
+            while headers.last() != Some(&0xc0) {
+                let mut header = [0u8; 2];
+                stream.read_exact(&mut header).unwrap();
+                let mut body = vec![0u8; header[1] as usize];
+                stream.read_exact(&mut body).unwrap();
+                if header[0] == 0x10 {
+                    stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+                }
+                headers.push(header[0]);
+            }
+            headers
+        });
+
+        let mut consumer = consumer(None);
+        let mqtt = MqttSettings {
+            broker: address,
+            client_id: String::from("dsmr-rs"),
+            username: None,
+            password: None,
+        };
+        consumer.client = MqttClient::new(&mqtt).with_availability("dsmr/status", 2);
+        let mut pipeline = Pipeline::new(&[StageSetting::Throttle(3600)], Box::new(consumer));
+        pipeline.consume(&telegram());
+        pipeline.consume(&telegram());
+
+        // Connect, birth message, three values and a ping instead of the held back telegram
+        assert_eq!(
+            broker.join().unwrap(),
+            vec![0x10, 0x31, 0x30, 0x30, 0x30, 0xc0]
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use super::settings::PvoutputSettings;
+use super::status::Health;
+use super::telegram::Telegram;
+use super::value::Unit;
+use super::TelegramConsumer;
+
+const DELIVERED: [&str; 2] = ["1-0:1.8.1", "1-0:1.8.2"];
+const POWER_DELIVERED: &str = "1-0:1.7.0";
+const POWER_RETURNED: &str = "1-0:2.7.0";
+
+// Returns the value of an OBIS code in the given unit, rounded to a whole number.
+fn whole(telegram: &Telegram, obis: &str, unit: Unit) -> Option<String> {
+    telegram
+        .number(obis, Some(unit))
+        .map(|value| value.with_scale(0).to_string())
+}
+
+// Returns the parameters of the status at the end of an interval. Consumption is uploaded as the
+// energy delivered by the grid since the meter was installed (v3) and the mean power (v4). With
+// net data, the mean power that was exported (v2) and imported (v4) is uploaded instead.
+fn status(telegram: &Telegram, net: bool) -> Option<Vec<(&'static str, String)>> {
+    let time = match &telegram.summary {
+        Some(summary) => summary.end,
+        None => telegram.timestamp()?,
+    };
+    let mut params = vec![
+        ("d", time.format("%Y%m%d").to_string()),
+        ("t", time.format("%H:%M").to_string()),
+    ];
+
+    if net {
+        params.push(("v2", whole(telegram, POWER_RETURNED, Unit::Watt)?));
+        params.push(("v4", whole(telegram, POWER_DELIVERED, Unit::Watt)?));
+        params.push(("n", String::from("1")));
+        return Some(params);
+    }
+
+    let energy = DELIVERED
+        .iter()
+        .filter_map(|obis| telegram.number(obis, Some(Unit::WattHour)))
+        .reduce(|total, value| total + value);
+    if let Some(energy) = energy {
+        params.push(("v3", energy.with_scale(0).to_string()));
+    }
+    if let Some(power) = whole(telegram, POWER_DELIVERED, Unit::Watt) {
+        params.push(("v4", power));
+    }
+    if params.len() == 2 {
+        return None;
+    }
+    if energy.is_some() {
+        // Only the consumption energy is a lifetime total
+        params.push(("c1", String::from("3")));
+    }
+    Some(params)
+}
+
+// Uploads the consumption of each interval to the addstatus service of PVOutput, once per
+// interval. The telegrams are aggregated into intervals before they reach this consumer.
+pub struct PvoutputConsumer {
+    url: String,
+    apikey: String,
+    system_id: String,
+    net: bool,
+    client: reqwest::blocking::Client,
+    health: Health,
+}
+impl PvoutputConsumer {
+    pub fn new(settings: &PvoutputSettings, health: Health) -> Self {
+        PvoutputConsumer {
+            url: format!(
+                "{}/service/r2/addstatus.jsp",
+                settings.url.trim_end_matches('/')
+            ),
+            apikey: settings.apikey.clone(),
+            system_id: settings.system_id.clone(),
+            net: settings.net,
+            client: reqwest::blocking::Client::new(),
+            health,
+        }
+    }
+}
+impl TelegramConsumer for PvoutputConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let params = match status(telegram, self.net) {
+            Some(params) => params,
+            None => {
+                log::debug!("Not uploading telegram without consumption to PVOutput");
+                return;
+            }
+        };
+
+        log::trace!("- uploading status to {}", self.url);
+        let result = self
+            .client
+            .post(&self.url)
+            .header("X-Pvoutput-Apikey", &self.apikey)
+            .header("X-Pvoutput-SystemId", &self.system_id)
+            .form(&params)
+            .send()
+            .and_then(|response| Ok((response.status(), response.text()?)));
+
+        match result {
+            Ok((status, body)) if !status.is_success() => {
+                log::warn!("PVOutput did not accept status: {}", body.trim());
+                let error = format!("Response with status {}", status);
+                self.health.failure(&self.url, &error);
+            }
+            Ok((status, _)) => {
+                log::trace!("Got response with status {}", status);
+                self.health.success(&self.url);
+            }
+            Err(msg) => {
+                log::warn!("Could not upload status to PVOutput due to {}", msg);
+                self.health.failure(&self.url, &msg.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::dsmr::aggregate::AggregatingConsumer;
+
+    fn telegram(timestamp: &str, power: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n\
+             1-0:1.8.1(000032.159*kWh)\r\n1-0:1.8.2(000010.000*kWh)\r\n\
+             1-0:1.7.0({}*kW)\r\n1-0:2.7.0(00.000*kW)\r\n!\r\n",
+            timestamp, power
+        ))
+    }
+
+    fn settings(url: &str) -> PvoutputSettings {
+        PvoutputSettings {
+            url: String::from(url),
+            apikey: String::from("secret"),
+            system_id: String::from("12345"),
+            interval: 300,
+            net: false,
+            pipeline: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn status_of_interval() {
+        let telegram = telegram("231026204015S", "00.302");
+
+        assert_eq!(
+            status(&telegram, false),
+            Some(vec![
+                ("d", String::from("20231026")),
+                ("t", String::from("20:40")),
+                ("v3", String::from("42159")),
+                ("v4", String::from("302")),
+                ("c1", String::from("3")),
+            ])
+        );
+        assert_eq!(
+            status(&telegram, true),
+            Some(vec![
+                ("d", String::from("20231026")),
+                ("t", String::from("20:40")),
+                ("v2", String::from("0")),
+                ("v4", String::from("302")),
+                ("n", String::from("1")),
+            ])
+        );
+
+        let telegram = Telegram::parse("/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n!\r\n");
+        assert_eq!(status(&telegram, false), None);
+    }
+
+    #[test]
+    fn upload_mean_power_of_interval() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            // Read until the body is complete, as given by its length
+            loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_lowercase();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .and_then(|length| length.parse::<usize>().ok());
+                    if length.is_some_and(|length| body.len() >= length) {
+                        break;
+                    }
+                }
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 20\r\n\r\nOK 200: Added Status")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let health = Health::default();
+        let sink = PvoutputConsumer::new(&settings(&format!("http://{}", address)), health.clone());
+        let mut consumer = AggregatingConsumer::new(300, Box::new(sink));
+        consumer.consume(&telegram("231026203510S", "00.300"));
+        consumer.consume(&telegram("231026203955S", "00.500"));
+        consumer.consume(&telegram("231026204000S", "01.000"));
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /service/r2/addstatus.jsp HTTP/1.1"));
+        assert!(request.to_lowercase().contains("x-pvoutput-apikey: secret"));
+        assert!(request
+            .to_lowercase()
+            .contains("x-pvoutput-systemid: 12345"));
+        assert!(request.ends_with("d=20231026&t=20%3A40&v3=42159&v4=400&c1=3"));
+        assert!(
+            health.snapshot()[&format!("http://{}/service/r2/addstatus.jsp", address)].healthy()
+        );
+    }
+}
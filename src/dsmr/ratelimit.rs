@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::settings::Downsampling;
+use super::telegram::{self, Telegram};
+use super::value::{Decimal, Quantity};
+use super::TelegramConsumer;
+
+// Forwards telegrams to a sink at most once per interval, so a slow or remote sink can receive
+// fewer telegrams without slowing down how fast the serial port is read for the other sinks.
+pub struct RateLimitedConsumer {
+    interval: Duration,
+    downsampling: Downsampling,
+    last_forwarded: Option<Instant>,
+    // Sum and number of instantaneous values received since the last forwarded telegram
+    sums: HashMap<String, (Quantity, i64)>,
+    downstream: Box<dyn TelegramConsumer>,
+}
+impl RateLimitedConsumer {
+    pub fn new(
+        interval: u64,
+        downsampling: Downsampling,
+        downstream: Box<dyn TelegramConsumer>,
+    ) -> Self {
+        RateLimitedConsumer {
+            interval: Duration::from_secs(interval),
+            downsampling,
+            last_forwarded: None,
+            sums: HashMap::new(),
+            downstream,
+        }
+    }
+
+    fn accumulate(&mut self, telegram: &Telegram) {
+        for object in telegram
+            .objects
+            .iter()
+            .filter(|object| !telegram::is_cumulative(&object.obis))
+        {
+            if let Some(quantity) = object.quantity() {
+                let entry = self
+                    .sums
+                    .entry(object.obis.clone())
+                    .or_insert((Quantity::new(Decimal::new(0, 0), quantity.unit), 0));
+                if let Ok(quantity) = quantity.convert(entry.0.unit) {
+                    entry.0.value = entry.0.value + quantity.value;
+                    entry.1 += 1;
+                }
+            }
+        }
+    }
+
+    // Replaces the instantaneous values of the telegram with their mean over the interval.
+    fn mean(&mut self, telegram: &Telegram) -> Telegram {
+        let mut result = telegram.clone();
+        for object in &mut result.objects {
+            if let Some((sum, count)) = self.sums.get(&object.obis) {
+                object.set_quantity(Quantity::new(sum.value.divide(*count), sum.unit));
+            }
+        }
+        self.sums.clear();
+        result
+    }
+
+    fn consume_at(&mut self, telegram: &Telegram, now: Instant) {
+        if self.downsampling == Downsampling::Mean {
+            self.accumulate(telegram);
+        }
+
+        if let Some(last) = self.last_forwarded {
+            if now.duration_since(last) < self.interval {
+                return;
+            }
+        }
+        self.last_forwarded = Some(now);
+
+        match self.downsampling {
+            Downsampling::Latest => self.downstream.consume(telegram),
+            Downsampling::Mean => {
+                let mean = self.mean(telegram);
+                self.downstream.consume(&mean);
+            }
+        }
+    }
+}
+impl TelegramConsumer for RateLimitedConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        self.consume_at(telegram, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct RecordingConsumer {
+        telegrams: Rc<RefCell<Vec<Telegram>>>,
+    }
+    impl TelegramConsumer for RecordingConsumer {
+        fn consume(&mut self, telegram: &Telegram) {
+            self.telegrams.borrow_mut().push(telegram.clone());
+        }
+    }
+
+    fn consumer(downsampling: Downsampling) -> (RateLimitedConsumer, Rc<RefCell<Vec<Telegram>>>) {
+        let telegrams = Rc::new(RefCell::new(Vec::new()));
+        let sink = RecordingConsumer {
+            telegrams: telegrams.clone(),
+        };
+        (
+            RateLimitedConsumer::new(60, downsampling, Box::new(sink)),
+            telegrams,
+        )
+    }
+
+    fn telegram(delivered: &str, power: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n1-0:1.8.1({}*kWh)\r\n1-0:1.7.0({}*kW)\r\n!\r\n",
+            delivered, power
+        ))
+    }
+
+    #[test]
+    fn forward_latest_telegram_per_interval() {
+        let (mut consumer, telegrams) = consumer(Downsampling::Latest);
+        let start = Instant::now();
+
+        consumer.consume_at(&telegram("000032.159", "00.302"), start);
+        consumer.consume_at(
+            &telegram("000032.160", "00.400"),
+            start + Duration::from_secs(30),
+        );
+        consumer.consume_at(
+            &telegram("000032.161", "00.500"),
+            start + Duration::from_secs(60),
+        );
+
+        let telegrams = telegrams.borrow();
+        assert_eq!(telegrams.len(), 2);
+        assert_eq!(telegrams[1].value("1-0:1.8.1"), Some("000032.161*kWh"));
+        assert_eq!(telegrams[1].value("1-0:1.7.0"), Some("00.500*kW"));
+    }
+
+    #[test]
+    fn forward_mean_of_instantaneous_values() {
+        let (mut consumer, telegrams) = consumer(Downsampling::Mean);
+        let start = Instant::now();
+
+        consumer.consume_at(&telegram("000032.159", "00.302"), start);
+        consumer.consume_at(
+            &telegram("000032.160", "00.400"),
+            start + Duration::from_secs(30),
+        );
+        consumer.consume_at(
+            &telegram("000032.161", "00.500"),
+            start + Duration::from_secs(60),
+        );
+
+        let telegrams = telegrams.borrow();
+        assert_eq!(telegrams.len(), 2);
+        assert_eq!(telegrams[0].value("1-0:1.7.0"), Some("0.302*kW"));
+        // Counters are not averaged, but taken from the latest telegram
+        assert_eq!(telegrams[1].value("1-0:1.8.1"), Some("000032.161*kWh"));
+        assert_eq!(telegrams[1].value("1-0:1.7.0"), Some("0.450*kW"));
+    }
+}
@@ -1,5 +1,6 @@
 use super::settings;
 use super::settings::ParityBitSetting;
+use super::telegram::Telegram;
 
 use serialport::{Error, SerialPort};
 
@@ -81,8 +82,6 @@ pub fn read_from_serial_port(
 ) {
     let reader = &mut BufReader::new(port);
 
-    // let mut consumer = PrintConsumer::new();
-
     let mut buffer = String::new();
     loop {
         let result = reader.read_line(&mut buffer);
@@ -93,7 +92,7 @@ pub fn read_from_serial_port(
             // Just drop this telegram
             buffer.clear();
         } else if let Some(telegram) = extract_telegram(&buffer) {
-            consumer.consume(telegram);
+            consumer.consume(&Telegram::parse(telegram));
             return;
         }
     }
@@ -164,7 +163,7 @@ mod tests {
 
         let result = extract_telegram(&text);
 
-        assert_eq!(result.is_none(), true);
+        assert!(result.is_none());
     }
 
     #[test]
@@ -173,7 +172,7 @@ mod tests {
 
         let result = extract_telegram(&text);
 
-        assert_eq!(result.is_none(), true);
+        assert!(result.is_none());
     }
 
     #[test]
@@ -182,7 +181,7 @@ mod tests {
 
         let result = extract_telegram(&text);
 
-        assert_eq!(result.is_none(), true);
+        assert!(result.is_none());
     }
 
     #[test]
@@ -193,7 +192,7 @@ mod tests {
 
         let result = extract_telegram(&text);
 
-        assert_eq!(result.is_some(), true);
+        assert!(result.is_some());
         assert_eq!(
             result.unwrap(),
             "/ISk5\\2MT382-1000\r\n\r\n1-3:0.2.8(40)\r\n!522B\r\n"
@@ -207,7 +206,7 @@ mod tests {
 
         let result = extract_telegram(&text);
 
-        assert_eq!(result.is_some(), true);
+        assert!(result.is_some());
         assert_eq!(
             result.unwrap(),
             "/ISk5\\2MT382-1000\r\n\r\n1-3:0.2.8(40)\r\n!\r\n"
@@ -220,7 +219,7 @@ mod tests {
 
         let result = extract_telegram(&input);
 
-        assert_eq!(result.is_some(), true);
+        assert!(result.is_some());
         assert_eq!(result.unwrap(), read_test_resource("output1.txt".into()),);
     }
 
@@ -231,6 +230,6 @@ mod tests {
 
         let mut binding = fs::read_to_string(test_file).expect("Failed to read file");
         let text = binding.as_mut_str();
-        return String::from(text);
+        String::from(text)
     }
 }
@@ -0,0 +1,203 @@
+use std::io::Write;
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::settings::{AllowedNetwork, RelaySettings};
+use super::telegram::Telegram;
+use super::TelegramConsumer;
+
+// Clients that do not accept a telegram within this time are disconnected, so they cannot
+// hold up the reader.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Returns whether the address is in one of the networks, or whether there are no networks.
+pub fn allowed(networks: &[AllowedNetwork], address: IpAddr) -> bool {
+    networks.is_empty()
+        || networks
+            .iter()
+            .any(|network| match (network.address, address.to_canonical()) {
+                (IpAddr::V4(network_address), IpAddr::V4(address)) => {
+                    let mask = u32::MAX
+                        .checked_shl(32 - network.prefix as u32)
+                        .unwrap_or(0);
+                    u32::from(network_address) & mask == u32::from(address) & mask
+                }
+                (IpAddr::V6(network_address), IpAddr::V6(address)) => {
+                    let mask = u128::MAX
+                        .checked_shl(128 - network.prefix as u32)
+                        .unwrap_or(0);
+                    u128::from(network_address) & mask == u128::from(address) & mask
+                }
+                _ => false,
+            })
+}
+
+fn accept(
+    listener: TcpListener,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    max_clients: usize,
+    allow: Vec<AllowedNetwork>,
+) {
+    for connection in listener.incoming().flatten() {
+        let peer = match connection.peer_addr() {
+            Ok(peer) => peer,
+            Err(_) => continue,
+        };
+        if !allowed(&allow, peer.ip()) {
+            log::warn!("Refusing relay client {}, as it is not allowed", peer);
+            continue;
+        }
+        let mut clients = clients.lock().unwrap();
+        if clients.len() >= max_clients {
+            log::warn!(
+                "Refusing relay client {}, as there are already {} clients",
+                peer,
+                max_clients
+            );
+            continue;
+        }
+        if let Err(msg) = connection.set_write_timeout(Some(WRITE_TIMEOUT)) {
+            log::warn!("Could not accept relay client {} due to {}", peer, msg);
+            continue;
+        }
+        log::info!("Relaying telegrams to {}", peer);
+        clients.push(connection);
+    }
+}
+
+// Sends each telegram unchanged to all clients connected over TCP, like ser2net does for the
+// serial port, so other programs can read the meter too.
+pub struct RelayConsumer {
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+impl RelayConsumer {
+    pub fn start(settings: &RelaySettings) -> Result<Self, String> {
+        let listener = TcpListener::bind(&settings.address).map_err(|msg| {
+            format!(
+                "Could not relay telegrams on {} due to {}",
+                settings.address, msg
+            )
+        })?;
+        log::info!("Relaying telegrams on {}", settings.address);
+
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let shared = clients.clone();
+        let max_clients = settings.max_clients;
+        let allow = settings.allow.clone();
+        thread::spawn(move || accept(listener, shared, max_clients, allow));
+        Ok(RelayConsumer { clients })
+    }
+}
+impl TelegramConsumer for RelayConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain_mut(|client| match client.write_all(telegram.raw.as_bytes()) {
+            Ok(()) => true,
+            Err(msg) => {
+                match client.peer_addr() {
+                    Ok(peer) => log::info!("Stopped relaying to {} due to {}", peer, msg),
+                    Err(_) => log::info!("Stopped relaying to client due to {}", msg),
+                }
+                false
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::Read;
+    use std::time::Instant;
+
+    fn network(address: &str, prefix: u8) -> AllowedNetwork {
+        AllowedNetwork {
+            address: address.parse().unwrap(),
+            prefix,
+        }
+    }
+
+    fn start(max_clients: usize, allow: Vec<AllowedNetwork>) -> (RelayConsumer, String) {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let consumer = RelayConsumer::start(&RelaySettings {
+            address: address.clone(),
+            max_clients,
+            allow,
+            pipeline: Vec::new(),
+        })
+        .unwrap();
+        (consumer, address)
+    }
+
+    fn wait_for_clients(consumer: &RelayConsumer, count: usize) {
+        let start = Instant::now();
+        while consumer.clients.lock().unwrap().len() < count {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn allow_addresses_in_networks() {
+        let networks = vec![network("192.168.1.0", 24), network("fd00::1", 128)];
+
+        assert!(allowed(&networks, "192.168.1.42".parse().unwrap()));
+        assert!(allowed(&networks, "::ffff:192.168.1.42".parse().unwrap()));
+        assert!(allowed(&networks, "fd00::1".parse().unwrap()));
+        assert!(!allowed(&networks, "192.168.2.42".parse().unwrap()));
+        assert!(!allowed(&networks, "fd00::2".parse().unwrap()));
+        assert!(allowed(
+            &[network("0.0.0.0", 0)],
+            "10.0.0.1".parse().unwrap()
+        ));
+        assert!(allowed(&[], "10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn relay_telegram_to_all_clients() {
+        let raw = "/ISK5\\2M550T-1013\r\n1-0:1.7.0(00.302*kW)\r\n!E5A1\r\n";
+        let (mut consumer, address) = start(2, vec![network("127.0.0.0", 8)]);
+        let mut first = TcpStream::connect(&address).unwrap();
+        let mut second = TcpStream::connect(&address).unwrap();
+        wait_for_clients(&consumer, 2);
+
+        consumer.consume(&Telegram::parse(raw));
+
+        for client in [&mut first, &mut second] {
+            let mut received = vec![0u8; raw.len()];
+            client.read_exact(&mut received).unwrap();
+            assert_eq!(received, raw.as_bytes());
+        }
+    }
+
+    #[test]
+    fn refuse_clients_over_maximum() {
+        let (consumer, address) = start(1, Vec::new());
+        let _first = TcpStream::connect(&address).unwrap();
+        wait_for_clients(&consumer, 1);
+
+        let mut second = TcpStream::connect(&address).unwrap();
+        let mut buffer = [0u8; 16];
+
+        assert_eq!(second.read(&mut buffer).unwrap(), 0);
+        assert_eq!(consumer.clients.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn refuse_clients_not_allowed() {
+        let (consumer, address) = start(1, vec![network("10.0.0.0", 8)]);
+        let mut client = TcpStream::connect(&address).unwrap();
+        let mut buffer = [0u8; 16];
+
+        assert_eq!(client.read(&mut buffer).unwrap(), 0);
+        assert!(consumer.clients.lock().unwrap().is_empty());
+    }
+}
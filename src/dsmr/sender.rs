@@ -1,32 +1,140 @@
-use super::settings;
-
-use std::collections::HashMap;
+use super::settings::{self, UploadMode};
 
+use crate::dsmr::aggregate::AggregatingConsumer;
+use crate::dsmr::alert::AlertingConsumer;
+use crate::dsmr::api::ApiConsumer;
+use crate::dsmr::clock::ClockDriftConsumer;
+use crate::dsmr::csv::CsvConsumer;
+use crate::dsmr::emoncms::EmoncmsConsumer;
+use crate::dsmr::graphite::{CarbonConsumer, StatsdConsumer};
+use crate::dsmr::jsonl::JsonLinesConsumer;
 use crate::dsmr::logger::LoggingConsumer;
+use crate::dsmr::modbus::ModbusConsumer;
+use crate::dsmr::otlp::OtlpConsumer;
+use crate::dsmr::peak::PeakDemandConsumer;
+use crate::dsmr::pipeline::Pipeline;
+use crate::dsmr::publisher::PublishingConsumer;
+use crate::dsmr::pvoutput::PvoutputConsumer;
+use crate::dsmr::ratelimit::RateLimitedConsumer;
+use crate::dsmr::relay::RelayConsumer;
+use crate::dsmr::sqlite::StoringConsumer;
+use crate::dsmr::status::Health;
+use crate::dsmr::stream::StreamingConsumer;
+use crate::dsmr::telegram::Telegram;
+use crate::dsmr::timestamp::parse_timestamp;
+use crate::dsmr::value::Unit;
+use crate::dsmr::volkszaehler::VolkszaehlerConsumer;
+use crate::dsmr::webhook::WebhookConsumer;
 use crate::dsmr::TelegramConsumer;
 
+// Fields of the DSMR-reader v2 API, with the OBIS code and unit of their value.
+// The first six fields, and the timestamp, are mandatory.
+const READING_FIELDS: [(&str, &str, Unit); 18] = [
+    ("electricity_delivered_1", "1-0:1.8.1", Unit::KiloWattHour),
+    ("electricity_returned_1", "1-0:2.8.1", Unit::KiloWattHour),
+    ("electricity_delivered_2", "1-0:1.8.2", Unit::KiloWattHour),
+    ("electricity_returned_2", "1-0:2.8.2", Unit::KiloWattHour),
+    (
+        "electricity_currently_delivered",
+        "1-0:1.7.0",
+        Unit::KiloWatt,
+    ),
+    (
+        "electricity_currently_returned",
+        "1-0:2.7.0",
+        Unit::KiloWatt,
+    ),
+    ("phase_currently_delivered_l1", "1-0:21.7.0", Unit::KiloWatt),
+    ("phase_currently_delivered_l2", "1-0:41.7.0", Unit::KiloWatt),
+    ("phase_currently_delivered_l3", "1-0:61.7.0", Unit::KiloWatt),
+    ("phase_currently_returned_l1", "1-0:22.7.0", Unit::KiloWatt),
+    ("phase_currently_returned_l2", "1-0:42.7.0", Unit::KiloWatt),
+    ("phase_currently_returned_l3", "1-0:62.7.0", Unit::KiloWatt),
+    ("phase_voltage_l1", "1-0:32.7.0", Unit::Volt),
+    ("phase_voltage_l2", "1-0:52.7.0", Unit::Volt),
+    ("phase_voltage_l3", "1-0:72.7.0", Unit::Volt),
+    ("phase_power_current_l1", "1-0:31.7.0", Unit::Ampere),
+    ("phase_power_current_l2", "1-0:51.7.0", Unit::Ampere),
+    ("phase_power_current_l3", "1-0:71.7.0", Unit::Ampere),
+];
+const MANDATORY_FIELDS: usize = 6;
+
+// Returns the fields of a reading for the DSMR-reader v2 API.
+fn reading_fields(telegram: &Telegram) -> Result<Vec<(&'static str, String)>, String> {
+    let timestamp = telegram
+        .timestamp()
+        .ok_or_else(|| "Telegram has no timestamp".to_string())?;
+    let mut fields = vec![("timestamp", timestamp.to_rfc3339())];
+
+    for (index, (name, obis, unit)) in READING_FIELDS.iter().enumerate() {
+        match telegram
+            .quantity(obis)
+            .and_then(|quantity| quantity.convert(*unit).ok())
+        {
+            Some(quantity) => fields.push((name, quantity.value.to_string())),
+            None if index < MANDATORY_FIELDS => {
+                return Err(format!("Telegram has no value for {}", name))
+            }
+            None => {}
+        }
+    }
+
+    // The gas meter can be on any M-Bus channel, and reports when it was read
+    if let Some(object) = telegram
+        .objects
+        .iter()
+        .find(|object| object.obis.starts_with("0-") && object.obis.ends_with(":24.2.1"))
+    {
+        let captured = object.values.first().map(|value| parse_timestamp(value));
+        let delivered = object
+            .quantity()
+            .and_then(|quantity| quantity.convert(Unit::CubicMetre).ok());
+        if let (Some(Ok(captured)), Some(delivered)) = (captured, delivered) {
+            fields.push(("extra_device_timestamp", captured.to_rfc3339()));
+            fields.push(("extra_device_delivered", delivered.value.to_string()));
+        }
+    }
+
+    Ok(fields)
+}
+
 struct UploadConsumer {
     host: String,
     key: String,
+    mode: UploadMode,
     client: reqwest::blocking::Client,
+    health: Health,
 }
 impl UploadConsumer {
-    fn new(target: &settings::Host) -> Self {
+    fn new(target: &settings::Host, health: Health) -> Self {
         UploadConsumer {
             client: reqwest::blocking::Client::new(),
             host: String::from(&target.address),
             key: String::from(&target.key),
+            mode: target.mode,
+            health,
         }
     }
 }
 
 impl super::TelegramConsumer for UploadConsumer {
-    fn consume(&mut self, telegram: &str) {
+    fn consume(&mut self, telegram: &Telegram) {
         log::trace!("- uploading telegram to {}", self.host);
-        let url = [&self.host, "/api/v1/datalogger/dsmrreading"].join("");
-
-        let mut params = HashMap::new();
-        params.insert("telegram", telegram.to_string());
+        let (path, params) = match self.mode {
+            UploadMode::Raw => (
+                "/api/v1/datalogger/dsmrreading",
+                vec![("telegram", telegram.raw.clone())],
+            ),
+            UploadMode::Parsed => match reading_fields(telegram) {
+                Ok(fields) => ("/api/v2/datalogger/dsmrreading", fields),
+                Err(msg) => {
+                    log::warn!("Not uploading telegram to {}: {}", self.host, msg);
+                    self.health.failure(&self.host, &msg);
+                    return;
+                }
+            },
+        };
+        let url = [&self.host, path].join("");
 
         let result = self
             .client
@@ -36,11 +144,18 @@ impl super::TelegramConsumer for UploadConsumer {
             .send();
 
         match result {
+            Ok(response) if !response.status().is_success() => {
+                log::trace!("Got response with status {}", response.status());
+                let error = format!("Response with status {}", response.status());
+                self.health.failure(&self.host, &error);
+            }
             Ok(response) => {
                 log::trace!("Got response with status {}", response.status());
+                self.health.success(&self.host);
             }
             Err(msg) => {
                 log::warn!("Could not upload telegram due to {}", msg);
+                self.health.failure(&self.host, &msg.to_string());
             }
         }
     }
@@ -49,26 +164,288 @@ impl super::TelegramConsumer for UploadConsumer {
 pub struct DelegatingConsumer {
     delegates: Vec<Box<dyn TelegramConsumer>>,
     logger: LoggingConsumer,
+    // Consumers that watch the telegrams without sending them anywhere
+    monitors: Vec<Box<dyn TelegramConsumer>>,
 }
 impl DelegatingConsumer {
-    pub fn new(targets: &[settings::Host]) -> Self {
+    pub fn new(settings: &settings::Settings) -> Self {
+        let targets = &settings.api.hosts;
         let mut delegates: Vec<Box<dyn TelegramConsumer>> = Vec::with_capacity(targets.len() + 1);
 
         let logger: LoggingConsumer = LoggingConsumer::new(targets.len() as u32);
+        // Shared with the HTTP API, which reports the status of each sink
+        let health = Health::default();
 
         (0..targets.len())
-            .map(|index| UploadConsumer::new(&targets[index]))
+            .map(|index| {
+                let target = &targets[index];
+                let mut sink: Box<dyn TelegramConsumer> =
+                    Box::new(UploadConsumer::new(target, health.clone()));
+                if target.interval > 0 {
+                    sink = Box::new(RateLimitedConsumer::new(
+                        target.interval,
+                        target.downsampling,
+                        sink,
+                    ));
+                }
+                if let Some(window) = target.window {
+                    sink = Box::new(AggregatingConsumer::new(window, sink));
+                }
+                Pipeline::new(&target.pipeline, sink)
+            })
             .map(Box::new)
             .for_each(|b| delegates.push(b));
 
-        DelegatingConsumer { delegates, logger }
+        for webhook in &settings.webhook.webhooks {
+            let sink = Box::new(WebhookConsumer::new(webhook, health.clone()));
+            delegates.push(Box::new(Pipeline::new(&webhook.pipeline, sink)));
+        }
+
+        if let (Some(sink), Some(mqtt)) = (&settings.mqtt_sink, &settings.mqtt) {
+            let publisher = Box::new(PublishingConsumer::new(sink, mqtt, health.clone()));
+            delegates.push(Box::new(Pipeline::new(&sink.pipeline, publisher)));
+        }
+
+        let graphite = &settings.graphite;
+        if let Some((transport, address)) = &graphite.carbon {
+            let sink = CarbonConsumer::new(graphite, *transport, address, health.clone());
+            delegates.push(Box::new(Pipeline::new(&graphite.pipeline, Box::new(sink))));
+        }
+        if let Some(address) = &graphite.statsd {
+            let sink = StatsdConsumer::new(graphite, address, health.clone());
+            delegates.push(Box::new(Pipeline::new(&graphite.pipeline, Box::new(sink))));
+        }
+
+        if let Some(otlp) = &settings.otlp {
+            let sink = Box::new(OtlpConsumer::new(otlp, health.clone()));
+            delegates.push(Box::new(Pipeline::new(&otlp.pipeline, sink)));
+        }
+
+        if let Some(emoncms) = &settings.emoncms {
+            let sink = Box::new(EmoncmsConsumer::new(emoncms, health.clone()));
+            delegates.push(Box::new(Pipeline::new(&emoncms.pipeline, sink)));
+        }
+
+        if let Some(volkszaehler) = &settings.volkszaehler {
+            let sink = Box::new(VolkszaehlerConsumer::new(volkszaehler, health.clone()));
+            delegates.push(Box::new(Pipeline::new(&volkszaehler.pipeline, sink)));
+        }
+
+        if let Some(pvoutput) = &settings.pvoutput {
+            let sink = Box::new(PvoutputConsumer::new(pvoutput, health.clone()));
+            let aggregate = Box::new(AggregatingConsumer::new(pvoutput.interval, sink));
+            delegates.push(Box::new(Pipeline::new(&pvoutput.pipeline, aggregate)));
+        }
+
+        if let Some(sqlite) = &settings.sqlite {
+            match StoringConsumer::open(sqlite) {
+                Ok(storing) => {
+                    let sink = Box::new(storing);
+                    delegates.push(Box::new(Pipeline::new(&sqlite.pipeline, sink)));
+                }
+                Err(msg) => log::error!("{}", msg),
+            }
+        }
+
+        if let Some(csv) = &settings.csv {
+            let sink = Box::new(CsvConsumer::new(csv));
+            delegates.push(Box::new(Pipeline::new(&csv.pipeline, sink)));
+        }
+
+        if let Some(stream) = &settings.stream {
+            match StreamingConsumer::start(stream) {
+                Ok(streaming) => {
+                    let sink = Box::new(streaming);
+                    delegates.push(Box::new(Pipeline::new(&stream.pipeline, sink)));
+                }
+                Err(msg) => log::error!("{}", msg),
+            }
+        }
+
+        if let Some(jsonl) = &settings.jsonl {
+            match JsonLinesConsumer::start(jsonl) {
+                Ok(writing) => {
+                    let sink = Box::new(writing);
+                    delegates.push(Box::new(Pipeline::new(&jsonl.pipeline, sink)));
+                }
+                Err(msg) => log::error!("{}", msg),
+            }
+        }
+
+        if let Some(relay) = &settings.relay {
+            match RelayConsumer::start(relay) {
+                Ok(relaying) => {
+                    let sink = Box::new(relaying);
+                    delegates.push(Box::new(Pipeline::new(&relay.pipeline, sink)));
+                }
+                Err(msg) => log::error!("{}", msg),
+            }
+        }
+
+        if let Some(modbus) = &settings.modbus {
+            match ModbusConsumer::start(modbus) {
+                Ok(serving) => {
+                    let sink = Box::new(serving);
+                    delegates.push(Box::new(Pipeline::new(&modbus.pipeline, sink)));
+                }
+                Err(msg) => log::error!("{}", msg),
+            }
+        }
+
+        if let Some(http) = &settings.http {
+            match ApiConsumer::start(http, health) {
+                Ok(api) => delegates.push(Box::new(Pipeline::new(&http.pipeline, Box::new(api)))),
+                Err(msg) => log::error!("{}", msg),
+            }
+        }
+
+        let mut monitors: Vec<Box<dyn TelegramConsumer>> = vec![
+            Box::new(ClockDriftConsumer::new(&settings.clock)),
+            Box::new(PeakDemandConsumer::new(&settings.peak)),
+        ];
+        if !settings.alert.rules.is_empty() {
+            monitors.push(Box::new(AlertingConsumer::new(
+                &settings.alert,
+                settings.mqtt.as_ref(),
+            )));
+        }
+
+        DelegatingConsumer {
+            delegates,
+            logger,
+            monitors,
+        }
     }
 }
 impl super::TelegramConsumer for DelegatingConsumer {
-    fn consume(&mut self, telegram: &str) {
+    fn consume(&mut self, telegram: &Telegram) {
         for delegate in &mut self.delegates {
             delegate.consume(telegram)
         }
         self.logger.consume(telegram);
+        for monitor in &mut self.monitors {
+            monitor.consume(telegram)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::dsmr::settings::Downsampling;
+
+    #[test]
+    fn reading_fields_of_telegram() {
+        let telegram = Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n\
+             1-0:1.8.1(000032.159*kWh)\r\n1-0:1.8.2(000002.167*kWh)\r\n\
+             1-0:2.8.1(000002.376*kWh)\r\n1-0:2.8.2(000000.000*kWh)\r\n\
+             1-0:1.7.0(00.302*kW)\r\n1-0:2.7.0(00.000*kW)\r\n\
+             1-0:32.7.0(230.0*V)\r\n1-0:31.7.0(001*A)\r\n\
+             0-1:24.2.1(231026204004S)(00004.381*m3)\r\n!\r\n",
+        );
+
+        let result = reading_fields(&telegram).unwrap();
+
+        assert_eq!(
+            result,
+            vec![
+                ("timestamp", String::from("2023-10-26T20:40:15+02:00")),
+                ("electricity_delivered_1", String::from("32.159")),
+                ("electricity_returned_1", String::from("2.376")),
+                ("electricity_delivered_2", String::from("2.167")),
+                ("electricity_returned_2", String::from("0.000")),
+                ("electricity_currently_delivered", String::from("0.302")),
+                ("electricity_currently_returned", String::from("0.000")),
+                ("phase_voltage_l1", String::from("230.0")),
+                ("phase_power_current_l1", String::from("1")),
+                (
+                    "extra_device_timestamp",
+                    String::from("2023-10-26T20:40:04+02:00")
+                ),
+                ("extra_device_delivered", String::from("4.381")),
+            ]
+        );
+    }
+
+    #[test]
+    fn upload_mean_of_parsed_values() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                // Read until the body is complete, as given by its length
+                let body = loop {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .to_lowercase()
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length: ")?.parse().ok());
+                        if length.is_some_and(|length: usize| body.len() >= length) {
+                            break String::from(body);
+                        }
+                    }
+                };
+                stream
+                    .write_all(
+                        b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .unwrap();
+                bodies.push(body);
+            }
+            bodies
+        });
+        let target = settings::Host {
+            address: format!("http://{}", address),
+            key: String::from("secret"),
+            pipeline: Vec::new(),
+            interval: 1,
+            downsampling: Downsampling::Mean,
+            window: None,
+            mode: UploadMode::Parsed,
+        };
+        let upload = UploadConsumer::new(&target, Health::default());
+        let mut consumer = RateLimitedConsumer::new(1, Downsampling::Mean, Box::new(upload));
+        let telegram = |power: &str| {
+            Telegram::parse(&format!(
+                "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n\
+                 1-0:1.8.1(000032.159*kWh)\r\n1-0:1.8.2(000002.167*kWh)\r\n\
+                 1-0:2.8.1(000002.376*kWh)\r\n1-0:2.8.2(000000.000*kWh)\r\n\
+                 1-0:1.7.0({}*kW)\r\n1-0:2.7.0(00.000*kW)\r\n!\r\n",
+                power
+            ))
+        };
+
+        consumer.consume(&telegram("00.300"));
+        consumer.consume(&telegram("00.500"));
+        thread::sleep(Duration::from_millis(1100));
+        consumer.consume(&telegram("00.700"));
+
+        let bodies = server.join().unwrap();
+        assert!(bodies[0].contains("electricity_currently_delivered=0.300&"));
+        assert!(bodies[1].contains("electricity_currently_delivered=0.600&"));
+    }
+
+    #[test]
+    fn reading_fields_require_mandatory_values() {
+        let telegram = Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n1-0:1.8.1(000032.159*kWh)\r\n!\r\n",
+        );
+
+        assert!(reading_fields(&telegram).is_err());
     }
 }
@@ -1,6 +1,12 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::result::Result;
 
+use chrono::format::{Item, StrftimeItems};
+use chrono::NaiveDate;
+
+use super::value::{Decimal, Quantity, Unit};
+
 #[derive(Debug, PartialEq)]
 pub enum ParityBitSetting {
     None,
@@ -15,15 +21,373 @@ pub struct SerialSettings {
     pub byte_size: u8,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum StageSetting {
+    // Only pass telegrams that contain the given OBIS code
+    Filter(String),
+    // Only pass telegrams without validation flags
+    Unflagged,
+    // Keep only the given OBIS codes in the parsed telegram
+    Select(Vec<String>),
+    // Pass at most one telegram per number of seconds
+    Throttle(u64),
+    // Drop telegrams that are equal to the previous one, apart from their timestamp
+    Dedupe,
+    // Only pass telegrams when the value of an OBIS code changed more than a threshold
+    Change(String, Quantity),
+    // Append the raw telegram to a file
+    Tee(String),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Downsampling {
+    // Forward the most recent telegram
+    Latest,
+    // Forward the most recent telegram, with instantaneous values averaged over the interval.
+    // Only the parsed values are averaged; the raw telegram is the most recent one.
+    Mean,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UploadMode {
+    // Send the raw telegram to `/api/v1/datalogger/dsmrreading`
+    Raw,
+    // Send the parsed values as separate fields to `/api/v2/datalogger/dsmrreading`
+    Parsed,
+}
+
 pub struct Host {
     pub address: String,
     pub key: String,
+    pub pipeline: Vec<StageSetting>,
+    // Minimum number of seconds between two telegrams sent to this host
+    pub interval: u64,
+    pub downsampling: Downsampling,
+    // Length in seconds of the windows to aggregate telegrams in, if any
+    pub window: Option<u64>,
+    pub mode: UploadMode,
 }
 
 pub struct HostSettings {
     pub hosts: Vec<Host>,
 }
 
+pub struct ClockSettings {
+    // Maximum allowed difference in seconds between the meter clock and the host clock
+    pub drift_threshold: u32,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ValidationAction {
+    Drop,
+    Flag,
+    Pass,
+}
+
+pub struct ValueRange {
+    pub obis: String,
+    pub min: Decimal,
+    pub max: Decimal,
+}
+
+pub struct ValidationSettings {
+    pub monotonic: ValidationAction,
+    pub range: ValidationAction,
+    pub duplicate: ValidationAction,
+    pub mandatory: ValidationAction,
+    pub ranges: Vec<ValueRange>,
+    pub mandatory_fields: Vec<String>,
+}
+
+pub struct PeakSettings {
+    // Warn when the demand of the current quarter hour is projected to exceed this (in kW)
+    pub threshold: Option<Decimal>,
+    // File to keep the monthly peaks in, so they survive a restart
+    pub state_file: Option<String>,
+}
+
+// Prices that apply between two dates (both inclusive). Without a date, the period is open-ended.
+#[derive(Debug, PartialEq)]
+pub struct TariffPeriod {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    // Price per kWh delivered to the client, for tariff 1 and 2
+    pub import: [Decimal; 2],
+    // Price per kWh delivered by the client, for tariff 1 and 2
+    pub export: [Decimal; 2],
+    // Fixed costs per day
+    pub daily: Decimal,
+    // Price per m3 of gas
+    pub gas: Decimal,
+}
+
+pub struct TotalsSettings {
+    // File to keep the totals of the current day and month in, so they survive a restart
+    pub state_file: Option<String>,
+}
+
+pub struct CostSettings {
+    pub tariffs: Vec<TariffPeriod>,
+    // CSV file with hourly prices that override the import and export prices
+    pub dynamic_prices: Option<String>,
+    // File to keep the costs of the current day and month in, so they survive a restart
+    pub state_file: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum WebhookAuth {
+    None,
+    // `Authorization: Token <key>`, as used by DSMR-reader
+    Token(String),
+    // `Authorization: Bearer <token>`
+    Bearer(String),
+    // User name and password
+    Basic(String, String),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BodyFormat {
+    // `key={{placeholder}}&...`, with the values URL encoded
+    Form,
+    // A JSON document, with the placeholders escaped as JSON strings
+    Json,
+    // Plain text, with the placeholders as-is
+    Raw,
+}
+
+pub struct Webhook {
+    // May contain placeholders, such as `https://example.com/dsmr?power={{1-0:1.7.0}}`
+    pub url: String,
+    pub method: String,
+    pub headers: Vec<(String, String)>,
+    pub auth: WebhookAuth,
+    pub format: BodyFormat,
+    pub body: String,
+    pub pipeline: Vec<StageSetting>,
+}
+
+pub struct WebhookSettings {
+    pub webhooks: Vec<Webhook>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MqttSettings {
+    // Host and port of the broker, such as `localhost:1883`
+    pub broker: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+pub struct MqttSinkSettings {
+    // Prefix of the topics to publish values to, such as `dsmr` for `dsmr/power_delivered`
+    pub topic: String,
+    // Prefix of the Home Assistant discovery topics, if discovery is enabled
+    pub discovery_prefix: Option<String>,
+    pub pipeline: Vec<StageSetting>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum AlertCondition {
+    // The value of an OBIS code is above a limit
+    Above(String, Quantity),
+    // The value of an OBIS code is below a limit
+    Below(String, Quantity),
+    // The value of an OBIS code did not change, or is missing
+    Unchanged(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AlertRule {
+    pub name: String,
+    pub condition: AlertCondition,
+    // Number of seconds the condition must hold before the alert fires
+    pub duration: u64,
+    // How far the value must get back within the limit before the alert is resolved
+    pub hysteresis: Decimal,
+    // Minimum number of seconds between two times the alert fires
+    pub cooldown: u64,
+}
+
+pub struct AlertSettings {
+    pub rules: Vec<AlertRule>,
+    // URL to POST alerts to as JSON
+    pub webhook: Option<String>,
+    // Topic on the MQTT broker to publish alerts to as JSON
+    pub mqtt_topic: Option<String>,
+    // Command to run for each alert, with the alert in environment variables
+    pub command: Option<String>,
+}
+
+pub struct HttpSettings {
+    // Address to serve the HTTP API on, such as `0.0.0.0:8080`
+    pub address: String,
+    // Number of readings to keep for `/reading/history`
+    pub history: usize,
+    pub pipeline: Vec<StageSetting>,
+}
+
+pub struct StreamSettings {
+    // Address to serve the WebSocket and Server-Sent Events stream on, such as `0.0.0.0:8081`
+    pub address: String,
+    pub pipeline: Vec<StageSetting>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JsonLinesOutput {
+    Stdout,
+    // Path of a Unix domain socket that any number of subscribers can connect to
+    Socket(String),
+}
+
+pub struct JsonLinesSettings {
+    pub output: JsonLinesOutput,
+    pub pipeline: Vec<StageSetting>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AllowedNetwork {
+    pub address: IpAddr,
+    // Number of leading bits of the address that must match
+    pub prefix: u8,
+}
+
+pub struct RelaySettings {
+    // Address to relay telegrams on, such as `0.0.0.0:2001`
+    pub address: String,
+    pub max_clients: usize,
+    // Networks that clients may connect from; any client may connect when there are none
+    pub allow: Vec<AllowedNetwork>,
+    pub pipeline: Vec<StageSetting>,
+}
+
+pub struct ModbusSettings {
+    // Address to serve Modbus TCP on, such as `0.0.0.0:502`
+    pub address: String,
+    // Unit identifier that the server answers to
+    pub unit_id: u8,
+    // Seconds after the last telegram in which values are served, if they expire
+    pub timeout: Option<u64>,
+    // Largest number of clients that are served at the same time
+    pub max_clients: usize,
+    // Networks that clients may connect from; any client may connect when there are none
+    pub allow: Vec<AllowedNetwork>,
+    pub pipeline: Vec<StageSetting>,
+}
+
+pub struct SqliteSettings {
+    // Path of the database file
+    pub path: String,
+    // Seconds of readings to collect before writing them in one transaction
+    pub batch_interval: u64,
+    // Number of days after which readings are downsampled, if they are
+    pub downsample_after: Option<u64>,
+    // Length in seconds of the periods that old readings are downsampled to
+    pub downsample_window: u64,
+    // Number of days after which all data is deleted, if it is
+    pub retention: Option<u64>,
+    pub pipeline: Vec<StageSetting>,
+}
+
+pub struct CsvSettings {
+    // Path of the file, which can contain a date pattern such as `%Y-%m-%d`
+    pub path: String,
+    // `timestamp` or OBIS codes of the values to write
+    pub columns: Vec<String>,
+    pub delimiter: char,
+    pub decimal_separator: char,
+    pub pipeline: Vec<StageSetting>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+pub struct GraphiteSettings {
+    // Transport and address of the Carbon plaintext receiver, if metrics are sent to one
+    pub carbon: Option<(Transport, String)>,
+    // Address of the StatsD server, if gauges are sent to one
+    pub statsd: Option<String>,
+    // Path of the metrics, such as `energy.{equipment_id}.{obis_name}`
+    pub template: String,
+    // Number of telegrams to send to Carbon at once
+    pub batch_size: usize,
+    pub pipeline: Vec<StageSetting>,
+}
+
+pub struct OtlpSettings {
+    // Base URL of the OTLP/HTTP receiver, such as `http://localhost:4318`
+    pub endpoint: String,
+    pub headers: Vec<(String, String)>,
+    pub pipeline: Vec<StageSetting>,
+}
+
+// Maps the value of an OBIS code, optionally converted to a unit, to a name in a sink.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ValueMapping {
+    pub obis: String,
+    pub unit: Option<Unit>,
+    pub name: String,
+}
+
+pub struct EmoncmsSettings {
+    // Base URL of Emoncms, such as `https://emoncms.org`
+    pub url: String,
+    pub apikey: String,
+    pub node: String,
+    // Inputs to post, or the names of the known sensors when empty
+    pub inputs: Vec<ValueMapping>,
+    pub pipeline: Vec<StageSetting>,
+}
+
+pub struct VolkszaehlerSettings {
+    // Base URL of the middleware, such as `http://localhost/middleware.php`
+    pub url: String,
+    // Values to post, with the UUID of their channel as name
+    pub channels: Vec<ValueMapping>,
+    pub pipeline: Vec<StageSetting>,
+}
+
+pub struct PvoutputSettings {
+    // Base URL of the service, such as `https://pvoutput.org`
+    pub url: String,
+    pub apikey: String,
+    pub system_id: String,
+    // Length in seconds of the intervals to upload, which is the status interval of the system
+    pub interval: u64,
+    // Upload net import and export power instead of consumption
+    pub net: bool,
+    pub pipeline: Vec<StageSetting>,
+}
+
+pub struct Settings {
+    pub serial: SerialSettings,
+    pub api: HostSettings,
+    pub clock: ClockSettings,
+    pub validation: ValidationSettings,
+    pub peak: PeakSettings,
+    pub totals: TotalsSettings,
+    pub cost: CostSettings,
+    pub mqtt: Option<MqttSettings>,
+    pub mqtt_sink: Option<MqttSinkSettings>,
+    pub alert: AlertSettings,
+    pub webhook: WebhookSettings,
+    pub http: Option<HttpSettings>,
+    pub stream: Option<StreamSettings>,
+    pub jsonl: Option<JsonLinesSettings>,
+    pub relay: Option<RelaySettings>,
+    pub modbus: Option<ModbusSettings>,
+    pub sqlite: Option<SqliteSettings>,
+    pub csv: Option<CsvSettings>,
+    pub graphite: GraphiteSettings,
+    pub otlp: Option<OtlpSettings>,
+    pub emoncms: Option<EmoncmsSettings>,
+    pub volkszaehler: Option<VolkszaehlerSettings>,
+    pub pvoutput: Option<PvoutputSettings>,
+}
+
 fn read_serial_settings(settings: &HashMap<String, String>) -> Result<SerialSettings, String> {
     let serial_port = match settings.get("serial_port") {
         Some(value) => value,
@@ -60,6 +424,141 @@ fn read_serial_settings(settings: &HashMap<String, String>) -> Result<SerialSett
     })
 }
 
+// Parses a single stage, such as `throttle(60)` or `change(1-0:1.7.0,50*W)`
+fn read_stage(input: &str) -> Result<StageSetting, String> {
+    let invalid = || format!("Pipeline stage {} not valid", input);
+    let (name, args) = match input.split_once('(') {
+        Some((name, rest)) => (name, rest.strip_suffix(')').ok_or_else(invalid)?),
+        None => (input, ""),
+    };
+    let args: Vec<&str> = args
+        .split(',')
+        .map(str::trim)
+        .filter(|arg| !arg.is_empty())
+        .collect();
+
+    match (name.trim(), args.as_slice()) {
+        ("filter", [obis]) => Ok(StageSetting::Filter(String::from(*obis))),
+        ("unflagged", []) => Ok(StageSetting::Unflagged),
+        ("select", obis) if !obis.is_empty() => Ok(StageSetting::Select(
+            obis.iter().map(|obis| String::from(*obis)).collect(),
+        )),
+        ("throttle", [seconds]) => match seconds.parse::<u64>() {
+            Ok(seconds) => Ok(StageSetting::Throttle(seconds)),
+            Err(_) => Err(invalid()),
+        },
+        ("dedupe", []) => Ok(StageSetting::Dedupe),
+        ("change", [obis, threshold]) => match Quantity::parse(threshold) {
+            Ok(threshold) => Ok(StageSetting::Change(String::from(*obis), threshold)),
+            Err(_) => Err(invalid()),
+        },
+        ("tee", [path]) => Ok(StageSetting::Tee(String::from(*path))),
+        (_, _) => Err(invalid()),
+    }
+}
+
+// Parses a pipeline of stages separated by '|', such as `unflagged|throttle(60)`
+fn read_pipeline(input: &str) -> Result<Vec<StageSetting>, String> {
+    input
+        .split('|')
+        .map(str::trim)
+        .filter(|stage| !stage.is_empty())
+        .map(read_stage)
+        .collect()
+}
+
+// Whether the pipeline keeps only some values, which does not change the raw telegram
+fn selects(pipeline: &[StageSetting]) -> bool {
+    pipeline
+        .iter()
+        .any(|stage| matches!(stage, StageSetting::Select(_)))
+}
+
+// Parses a window length such as `15m` or `1h` into seconds.
+// Windows start at multiples of their length since the epoch, so they must divide an hour to
+// align to wall-clock boundaries in the time zone of the meter.
+fn read_window(input: &str) -> Result<Option<u64>, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+
+    let invalid = || format!("Window {} not valid", input);
+    let (amount, factor) = match (input.strip_suffix('m'), input.strip_suffix('h')) {
+        (Some(amount), _) => (amount, 60),
+        (_, Some(amount)) => (amount, 3_600),
+        _ => return Err(invalid()),
+    };
+    let seconds = amount
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(factor))
+        .ok_or_else(invalid)?;
+
+    if seconds == 0 || 3_600 % seconds != 0 {
+        return Err(invalid());
+    }
+    Ok(Some(seconds))
+}
+
+fn read_downsampling(input: &str) -> Result<Downsampling, String> {
+    match input.trim() {
+        "latest" => Ok(Downsampling::Latest),
+        "mean" => Ok(Downsampling::Mean),
+        _ => Err(format!("Value {} for api_downsampling not valid", input)),
+    }
+}
+
+fn read_upload_mode(input: &str) -> Result<UploadMode, String> {
+    match input.trim() {
+        "raw" => Ok(UploadMode::Raw),
+        "parsed" => Ok(UploadMode::Parsed),
+        _ => Err(format!("Value {} for api_modes not valid", input)),
+    }
+}
+
+// Reads an optional setting that has one item for each item of another setting, such as
+// each API host. When the setting is not defined, the result is empty.
+fn read_per_item<T>(
+    settings: &HashMap<String, String>,
+    list_key: &str,
+    key: &str,
+    separator: char,
+    count: usize,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+    let items = match settings.get(key) {
+        Some(value) => value
+            .split(separator)
+            .map(parse)
+            .collect::<Result<Vec<T>, String>>()?,
+        None => return Ok(Vec::new()),
+    };
+
+    if items.len() != count {
+        let msg = format!(
+            "Number of items in {} ({}) is not equal to number of items in {} ({})",
+            list_key,
+            count,
+            key,
+            items.len()
+        );
+        return Err(msg);
+    }
+
+    Ok(items)
+}
+
+fn read_per_host<T>(
+    settings: &HashMap<String, String>,
+    key: &str,
+    separator: char,
+    host_count: usize,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+    read_per_item(settings, "api_hosts", key, separator, host_count, parse)
+}
+
 fn read_host_settings(settings: &HashMap<String, String>) -> Result<HostSettings, String> {
     let hosts: Vec<&str> = match settings.get("api_hosts") {
         Some(value) => value.split(',').collect(),
@@ -79,158 +578,1154 @@ fn read_host_settings(settings: &HashMap<String, String>) -> Result<HostSettings
         return Err(msg);
     }
 
+    let mut pipelines = read_per_host(settings, "api_pipelines", ';', hosts.len(), read_pipeline)?;
+    let intervals = read_per_host(settings, "api_intervals", ',', hosts.len(), |value| {
+        value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| "Setting api_intervals can not be converted to numbers".to_string())
+    })?;
+    let downsampling = read_per_host(
+        settings,
+        "api_downsampling",
+        ',',
+        hosts.len(),
+        read_downsampling,
+    )?;
+    let windows = read_per_host(settings, "api_windows", ',', hosts.len(), read_window)?;
+    let modes = read_per_host(settings, "api_modes", ',', hosts.len(), read_upload_mode)?;
+
     let result = (0..hosts.len())
         .map(|x| Host {
             address: String::from(hosts[x]),
             key: String::from(keys[x]),
+            pipeline: pipelines.get_mut(x).map(std::mem::take).unwrap_or_default(),
+            interval: intervals.get(x).copied().unwrap_or(0),
+            downsampling: downsampling.get(x).copied().unwrap_or(Downsampling::Latest),
+            window: windows.get(x).copied().flatten(),
+            mode: modes.get(x).copied().unwrap_or(UploadMode::Raw),
         })
         .collect::<Vec<Host>>();
 
+    // The raw telegram is uploaded unchanged, so it can not hold mean values
+    if let Some(host) = result
+        .iter()
+        .find(|host| host.mode == UploadMode::Raw && host.downsampling == Downsampling::Mean)
+    {
+        return Err(format!(
+            "Setting api_downsampling mean requires api_modes parsed for {}",
+            host.address
+        ));
+    }
+    if let Some(host) = result
+        .iter()
+        .find(|host| host.mode == UploadMode::Raw && host.window.is_some())
+    {
+        return Err(format!(
+            "Setting api_windows requires api_modes parsed for {}",
+            host.address
+        ));
+    }
+    if let Some(host) = result
+        .iter()
+        .find(|host| host.mode == UploadMode::Raw && selects(&host.pipeline))
+    {
+        return Err(format!(
+            "Stage select in api_pipelines requires api_modes parsed for {}",
+            host.address
+        ));
+    }
+
     Ok(HostSettings { hosts: result })
 }
 
-pub fn settings(settings: config::Config) -> Result<(SerialSettings, HostSettings), String> {
-    let config_map = settings
-        .try_deserialize::<HashMap<String, String>>()
-        .map_err(|e| e.to_string())
-        .unwrap();
-
-    let serial_settings = read_serial_settings(&config_map);
-    let host_settings = read_host_settings(&config_map);
+fn read_clock_settings(settings: &HashMap<String, String>) -> Result<ClockSettings, String> {
+    let drift_threshold = match settings.get("clock_drift_threshold") {
+        Some(value) => match value.parse::<u32>() {
+            Ok(value) => value,
+            Err(_) => {
+                return Err(
+                    "Setting clock_drift_threshold can not be converted to a number".to_string(),
+                )
+            }
+        },
+        None => 30,
+    };
 
-    match (serial_settings, host_settings) {
-        (Ok(s), Ok(h)) => Ok((s, h)),
-        (Ok(_), Err(msg)) => Err(msg),
-        (Err(msg), Ok(_)) => Err(msg),
-        (Err(msg1), Err(msg2)) => Err([msg1, msg2].join(" + ")),
-    }
+    Ok(ClockSettings { drift_threshold })
 }
 
-#[cfg(test)]
-mod tests {
-    #[allow(unused_imports)]
-    use super::*;
-
-    #[test]
-    fn read_serial_settings_valid_settings() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
-        settings.insert(String::from("serial_baudrate"), String::from("9600"));
+fn read_peak_settings(settings: &HashMap<String, String>) -> Result<PeakSettings, String> {
+    let threshold = match settings.get("peak_threshold") {
+        Some(value) => match Decimal::parse(value) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                return Err("Setting peak_threshold can not be converted to a number".to_string())
+            }
+        },
+        None => None,
+    };
+    let state_file = settings.get("peak_state_file").cloned();
 
-        let result = read_serial_settings(&settings);
+    Ok(PeakSettings {
+        threshold,
+        state_file,
+    })
+}
 
-        assert_eq!(result.is_ok(), true);
-        let value = result.unwrap();
-        assert_eq!(value.port, "/dev/ttyUSB0");
-        assert_eq!(value.baud_rate, 9600);
+fn read_date(input: &str) -> Result<Option<NaiveDate>, String> {
+    match input.trim() {
+        "" => Ok(None),
+        date => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| format!("Date {} in cost_tariffs not valid", date)),
     }
+}
 
-    #[test]
-    fn read_serial_settings_without_serial_port() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("serial_baudrate"), String::from("9600"));
+// Parses a tariff period such as `2024-01-01..2024-12-31|import1=0.30|import2=0.28|daily=0.95`
+fn read_tariff_period(input: &str) -> Result<TariffPeriod, String> {
+    let mut parts = input.split('|');
+    let (from, to) = parts
+        .next()
+        .and_then(|dates| dates.split_once(".."))
+        .ok_or_else(|| format!("Tariff period {} has no dates", input))?;
 
-        let result = read_serial_settings(&settings);
+    let zero = Decimal::new(0, 0);
+    let mut period = TariffPeriod {
+        from: read_date(from)?,
+        to: read_date(to)?,
+        import: [zero, zero],
+        export: [zero, zero],
+        daily: zero,
+        gas: zero,
+    };
 
-        assert_eq!(result.is_err(), true);
+    for part in parts {
+        let invalid = || format!("Price {} in cost_tariffs not valid", part);
+        let (name, price) = part.split_once('=').ok_or_else(invalid)?;
+        let price = Decimal::parse(price.trim()).map_err(|_| invalid())?;
+        match name.trim() {
+            "import" => period.import = [price, price],
+            "import1" => period.import[0] = price,
+            "import2" => period.import[1] = price,
+            "export" => period.export = [price, price],
+            "export1" => period.export[0] = price,
+            "export2" => period.export[1] = price,
+            "daily" => period.daily = price,
+            "gas" => period.gas = price,
+            _ => return Err(invalid()),
+        }
     }
 
-    #[test]
-    fn read_serial_settings_without_serial_baudrate() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
+    Ok(period)
+}
 
-        let result = read_serial_settings(&settings);
+fn read_totals_settings(settings: &HashMap<String, String>) -> Result<TotalsSettings, String> {
+    let state_file = settings.get("totals_state_file").cloned();
 
-        assert_eq!(result.is_err(), true);
-    }
+    Ok(TotalsSettings { state_file })
+}
 
-    #[test]
-    fn read_serial_settings_with_invalid_serial_baudrate() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
-        settings.insert(String::from("serial_baudrate"), String::from("a"));
+fn read_cost_settings(settings: &HashMap<String, String>) -> Result<CostSettings, String> {
+    let tariffs = match settings.get("cost_tariffs") {
+        Some(value) => value
+            .split(';')
+            .filter(|period| !period.trim().is_empty())
+            .map(read_tariff_period)
+            .collect::<Result<Vec<TariffPeriod>, String>>()?,
+        None => Vec::new(),
+    };
+    let dynamic_prices = settings.get("cost_dynamic_prices").cloned();
+    let state_file = settings.get("cost_state_file").cloned();
 
-        let result = read_serial_settings(&settings);
+    Ok(CostSettings {
+        tariffs,
+        dynamic_prices,
+        state_file,
+    })
+}
 
-        assert_eq!(result.is_err(), true);
+fn read_validation_action(
+    settings: &HashMap<String, String>,
+    key: &str,
+    default: ValidationAction,
+) -> Result<ValidationAction, String> {
+    match settings.get(key).map(String::as_str) {
+        Some("drop") => Ok(ValidationAction::Drop),
+        Some("flag") => Ok(ValidationAction::Flag),
+        Some("pass") => Ok(ValidationAction::Pass),
+        Some(_) => Err(format!("Value for {} not valid", key)),
+        None => Ok(default),
     }
+}
 
-    #[test]
-    fn read_serial_settings_with_parity_bit_odd() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
-        settings.insert(String::from("serial_baudrate"), String::from("9600"));
-        settings.insert(String::from("serial_parity"), String::from("O"));
+// Parses a range such as `1-0:32.7.0=180..260`
+fn read_value_range(input: &str) -> Result<ValueRange, String> {
+    let invalid = || format!("Range {} in validation_ranges not valid", input);
+    let (obis, bounds) = input.split_once('=').ok_or_else(invalid)?;
+    let (min, max) = bounds.split_once("..").ok_or_else(invalid)?;
 
-        let result = read_serial_settings(&settings);
+    Ok(ValueRange {
+        obis: String::from(obis.trim()),
+        min: Decimal::parse(min.trim()).map_err(|_| invalid())?,
+        max: Decimal::parse(max.trim()).map_err(|_| invalid())?,
+    })
+}
 
-        assert_eq!(result.is_ok(), true);
-        let value = result.unwrap();
-        assert_eq!(value.parity_bit, ParityBitSetting::Odd);
-    }
+fn read_validation_settings(
+    settings: &HashMap<String, String>,
+) -> Result<ValidationSettings, String> {
+    let monotonic =
+        read_validation_action(settings, "validation_monotonic", ValidationAction::Drop)?;
+    let range = read_validation_action(settings, "validation_range", ValidationAction::Drop)?;
+    let duplicate =
+        read_validation_action(settings, "validation_duplicate", ValidationAction::Drop)?;
+    let mandatory =
+        read_validation_action(settings, "validation_mandatory", ValidationAction::Flag)?;
 
-    #[test]
-    fn read_serial_settings_with_parity_bit_even() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
-        settings.insert(String::from("serial_baudrate"), String::from("9600"));
-        settings.insert(String::from("serial_parity"), String::from("E"));
+    let ranges = match settings.get("validation_ranges") {
+        Some(value) => value
+            .split(',')
+            .filter(|range| !range.trim().is_empty())
+            .map(read_value_range)
+            .collect::<Result<Vec<ValueRange>, String>>()?,
+        None => Vec::new(),
+    };
+    let mandatory_fields = match settings.get("validation_mandatory_fields") {
+        Some(value) => value.split(',').map(|obis| obis.trim()).collect(),
+        None => vec!["1-0:1.8.1", "1-0:1.8.2", "1-0:2.8.1", "1-0:2.8.2"],
+    }
+    .into_iter()
+    .filter(|obis| !obis.is_empty())
+    .map(String::from)
+    .collect();
 
-        let result = read_serial_settings(&settings);
+    Ok(ValidationSettings {
+        monotonic,
+        range,
+        duplicate,
+        mandatory,
+        ranges,
+        mandatory_fields,
+    })
+}
 
-        assert_eq!(result.is_ok(), true);
-        let value = result.unwrap();
-        assert_eq!(value.parity_bit, ParityBitSetting::Even);
+fn collect_error<T>(result: Result<T, String>, errors: &mut Vec<String>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(msg) => {
+            errors.push(msg);
+            None
+        }
     }
+}
 
-    #[test]
-    fn read_serial_settings_with_byte_size_seven() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
-        settings.insert(String::from("serial_baudrate"), String::from("9600"));
-        settings.insert(String::from("serial_bytesize"), String::from("7"));
+fn read_webhook_method(input: &str) -> Result<String, String> {
+    let method = input.trim().to_uppercase();
+    match method.as_str() {
+        "" => Ok(String::from("POST")),
+        "GET" | "POST" | "PUT" | "PATCH" | "DELETE" => Ok(method),
+        _ => Err(format!("Method {} in webhook_methods not valid", input)),
+    }
+}
 
-        let result = read_serial_settings(&settings);
+// Parses headers separated by '|', such as `X-Source: dsmr-rs|X-Site: home`
+fn read_headers(input: &str, key: &str) -> Result<Vec<(String, String)>, String> {
+    input
+        .split('|')
+        .map(str::trim)
+        .filter(|header| !header.is_empty())
+        .map(|header| match header.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                Ok((String::from(name.trim()), String::from(value.trim())))
+            }
+            _ => Err(format!("Header {} in {} not valid", header, key)),
+        })
+        .collect()
+}
 
-        assert_eq!(result.is_ok(), true);
-        let value = result.unwrap();
-        assert_eq!(value.byte_size, 7);
+// Parses an authentication scheme such as `token:<key>`, `bearer:<token>` or `basic:<user>:<password>`
+fn read_webhook_auth(input: &str) -> Result<WebhookAuth, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(WebhookAuth::None);
     }
+    match input.split_once(':') {
+        Some(("token", key)) => Ok(WebhookAuth::Token(String::from(key))),
+        Some(("bearer", token)) => Ok(WebhookAuth::Bearer(String::from(token))),
+        Some(("basic", credentials)) => match credentials.split_once(':') {
+            Some((user, password)) => Ok(WebhookAuth::Basic(
+                String::from(user),
+                String::from(password),
+            )),
+            None => Ok(WebhookAuth::Basic(String::from(credentials), String::new())),
+        },
+        _ => Err(format!(
+            "Authentication {} in webhook_auth not valid",
+            input
+        )),
+    }
+}
 
-    #[test]
-    fn host_settings_single_pair() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("api_hosts"), String::from("localhost"));
-        settings.insert(String::from("api_keys"), String::from("this-is-not-secret"));
-
-        let result = read_host_settings(&settings);
+// Parses a body such as `json:{"telegram": "{{raw}}"}`, or `json:@/etc/dsmr-rs/body.json` to
+// read the template from a file.
+fn read_webhook_body(input: &str) -> Result<(BodyFormat, String), String> {
+    let input = input.trim();
+    let (format, template) = match input.split_once(':') {
+        Some(("form", template)) => (BodyFormat::Form, template),
+        Some(("json", template)) => (BodyFormat::Json, template),
+        Some(("raw", template)) => (BodyFormat::Raw, template),
+        _ => return Err(format!("Body {} in webhook_bodies not valid", input)),
+    };
+    let template = match template.strip_prefix('@') {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|msg| format!("Could not read body template {}: {}", path, msg))?,
+        None => String::from(template),
+    };
+    Ok((format, template))
+}
 
-        assert_eq!(result.is_ok(), true);
-        let value = result.unwrap();
-        assert_eq!(value.hosts.len(), 1);
-        assert_eq!(value.hosts[0].address, "localhost");
-        assert_eq!(value.hosts[0].key, "this-is-not-secret");
-    }
+fn read_webhook_settings(settings: &HashMap<String, String>) -> Result<WebhookSettings, String> {
+    let urls: Vec<&str> = match settings.get("webhook_urls") {
+        Some(value) => value.split(',').map(str::trim).collect(),
+        None => return Ok(WebhookSettings { webhooks: vec![] }),
+    };
+    let count = urls.len();
+    let read = |key: &str, separator: char| {
+        let parse = |value: &str| Ok(String::from(value));
+        read_per_item(settings, "webhook_urls", key, separator, count, parse)
+    };
 
-    #[test]
-    fn host_settings_no_api_hosts() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("api_hosts"), String::from("localhost"));
+    let methods = read("webhook_methods", ',')?
+        .iter()
+        .map(|method| read_webhook_method(method))
+        .collect::<Result<Vec<String>, String>>()?;
+    let headers = read("webhook_headers", ';')?
+        .iter()
+        .map(|headers| read_headers(headers, "webhook_headers"))
+        .collect::<Result<Vec<_>, String>>()?;
+    let auth = read("webhook_auth", ';')?
+        .iter()
+        .map(|auth| read_webhook_auth(auth))
+        .collect::<Result<Vec<WebhookAuth>, String>>()?;
+    let bodies = read("webhook_bodies", ';')?
+        .iter()
+        .map(|body| read_webhook_body(body))
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut pipelines = read("webhook_pipelines", ';')?
+        .iter()
+        .map(|pipeline| read_pipeline(pipeline))
+        .collect::<Result<Vec<_>, String>>()?;
 
-        let result = read_host_settings(&settings);
+    let webhooks = (0..count)
+        .map(|x| {
+            let (format, body) = bodies
+                .get(x)
+                .cloned()
+                .unwrap_or((BodyFormat::Form, String::from("telegram={{raw}}")));
+            Webhook {
+                url: String::from(urls[x]),
+                method: methods
+                    .get(x)
+                    .cloned()
+                    .unwrap_or_else(|| String::from("POST")),
+                headers: headers.get(x).cloned().unwrap_or_default(),
+                auth: auth.get(x).cloned().unwrap_or(WebhookAuth::None),
+                format,
+                body,
+                pipeline: pipelines.get_mut(x).map(std::mem::take).unwrap_or_default(),
+            }
+        })
+        .collect::<Vec<Webhook>>();
 
-        assert_eq!(result.is_ok(), false);
+    // The raw telegram is sent unchanged, so select can not remove values from it
+    if let Some(webhook) = webhooks.iter().find(|webhook| {
+        selects(&webhook.pipeline)
+            && (webhook.url.contains("{{raw}}") || webhook.body.contains("{{raw}}"))
+    }) {
+        return Err(format!(
+            "Stage select in webhook_pipelines can not be used with {{{{raw}}}} for {}",
+            webhook.url
+        ));
     }
 
-    #[test]
-    fn host_settings_no_api_keys() {
-        let mut settings = HashMap::new();
-        settings.insert(String::from("api_hosts"), String::from("localhost"));
-
-        let result = read_host_settings(&settings);
+    Ok(WebhookSettings { webhooks })
+}
 
-        assert_eq!(result.is_ok(), false);
+fn read_mqtt_settings(settings: &HashMap<String, String>) -> Result<Option<MqttSettings>, String> {
+    let broker = match settings.get("mqtt_broker") {
+        Some(broker) => broker.clone(),
+        None => return Ok(None),
+    };
+    let client_id = settings
+        .get("mqtt_client_id")
+        .cloned()
+        .unwrap_or_else(|| String::from("dsmr-rs"));
+    let username = settings.get("mqtt_username").cloned();
+    let password = settings.get("mqtt_password").cloned();
+    // MQTT 3.1.1 does not allow a password without a user name
+    if password.is_some() && username.is_none() {
+        return Err("Setting mqtt_password requires mqtt_username".to_string());
+    }
+
+    Ok(Some(MqttSettings {
+        broker,
+        client_id,
+        username,
+        password,
+    }))
+}
+
+fn read_mqtt_sink_settings(
+    settings: &HashMap<String, String>,
+) -> Result<Option<MqttSinkSettings>, String> {
+    let topic = match settings.get("mqtt_topic") {
+        Some(topic) => topic.trim_end_matches('/').to_string(),
+        None => return Ok(None),
+    };
+    if !settings.contains_key("mqtt_broker") {
+        return Err("Setting mqtt_topic requires mqtt_broker".to_string());
+    }
+    let discovery = match settings.get("mqtt_discovery").map(String::as_str) {
+        Some("true") | None => true,
+        Some("false") => false,
+        Some(_) => return Err("Setting mqtt_discovery must be true or false".to_string()),
+    };
+    let discovery_prefix = settings
+        .get("mqtt_discovery_prefix")
+        .cloned()
+        .unwrap_or_else(|| String::from("homeassistant"));
+    let pipeline = match settings.get("mqtt_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(MqttSinkSettings {
+        topic,
+        discovery_prefix: if discovery {
+            Some(discovery_prefix)
+        } else {
+            None
+        },
+        pipeline,
+    }))
+}
+
+// Parses a condition such as `1-0:31.7.0>25*A`, `1-0:32.7.0<207*V` or `unchanged(0-1:24.2.1)`
+fn read_alert_condition(input: &str) -> Option<AlertCondition> {
+    if let Some(obis) = input
+        .strip_prefix("unchanged(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        return Some(AlertCondition::Unchanged(String::from(obis.trim())));
+    }
+    if let Some((obis, limit)) = input.split_once('>') {
+        let limit = Quantity::parse(limit.trim()).ok()?;
+        return Some(AlertCondition::Above(String::from(obis.trim()), limit));
+    }
+    if let Some((obis, limit)) = input.split_once('<') {
+        let limit = Quantity::parse(limit.trim()).ok()?;
+        return Some(AlertCondition::Below(String::from(obis.trim()), limit));
+    }
+    None
+}
+
+// Parses a rule such as `main_fuse=1-0:31.7.0>25*A|for=60|hysteresis=1|cooldown=900`
+pub fn read_alert_rule(input: &str) -> Result<AlertRule, String> {
+    let invalid = || format!("Alert rule {} not valid", input);
+    let mut parts = input.split('|').map(str::trim);
+    let (name, condition) = parts
+        .next()
+        .and_then(|rule| rule.split_once('='))
+        .ok_or_else(invalid)?;
+    let condition = read_alert_condition(condition.trim()).ok_or_else(invalid)?;
+
+    let mut rule = AlertRule {
+        name: String::from(name.trim()),
+        condition,
+        duration: 0,
+        hysteresis: Decimal::new(0, 0),
+        cooldown: 0,
+    };
+    for part in parts {
+        match part.split_once('=') {
+            Some(("for", seconds)) => rule.duration = seconds.parse().map_err(|_| invalid())?,
+            Some(("hysteresis", value)) => {
+                rule.hysteresis = Decimal::parse(value).map_err(|_| invalid())?
+            }
+            Some(("cooldown", seconds)) => {
+                rule.cooldown = seconds.parse().map_err(|_| invalid())?
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(rule)
+}
+
+fn read_alert_settings(settings: &HashMap<String, String>) -> Result<AlertSettings, String> {
+    let rules = match settings.get("alert_rules") {
+        Some(value) => value
+            .split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(read_alert_rule)
+            .collect::<Result<Vec<AlertRule>, String>>()?,
+        None => Vec::new(),
+    };
+    let mqtt_topic = settings.get("alert_mqtt_topic").cloned();
+    if mqtt_topic.is_some() && !settings.contains_key("mqtt_broker") {
+        return Err("Setting alert_mqtt_topic requires mqtt_broker".to_string());
+    }
+
+    Ok(AlertSettings {
+        rules,
+        webhook: settings.get("alert_webhook").cloned(),
+        mqtt_topic,
+        command: settings.get("alert_command").cloned(),
+    })
+}
+
+fn read_http_settings(settings: &HashMap<String, String>) -> Result<Option<HttpSettings>, String> {
+    let address = match settings.get("http_address") {
+        Some(address) => address.clone(),
+        None => return Ok(None),
+    };
+    let history = match settings.get("http_history") {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| "Setting http_history is not a valid number".to_string())?,
+        None => 3600,
+    };
+    let pipeline = match settings.get("http_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(HttpSettings {
+        address,
+        history,
+        pipeline,
+    }))
+}
+
+fn read_stream_settings(
+    settings: &HashMap<String, String>,
+) -> Result<Option<StreamSettings>, String> {
+    let address = match settings.get("stream_address") {
+        Some(address) => address.clone(),
+        None => return Ok(None),
+    };
+    let pipeline = match settings.get("stream_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(StreamSettings { address, pipeline }))
+}
+
+fn read_jsonl_settings(
+    settings: &HashMap<String, String>,
+) -> Result<Option<JsonLinesSettings>, String> {
+    let output = match settings.get("jsonl_output").map(|value| value.trim()) {
+        Some("stdout") | Some("-") => JsonLinesOutput::Stdout,
+        Some("") => return Err("Setting jsonl_output can not be empty".to_string()),
+        Some(path) => JsonLinesOutput::Socket(String::from(path)),
+        None => return Ok(None),
+    };
+    let pipeline = match settings.get("jsonl_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(JsonLinesSettings { output, pipeline }))
+}
+
+// Parses an address such as `192.168.1.10`, or a network such as `192.168.1.0/24` or `fd00::/8`
+fn read_allowed_network(input: &str) -> Result<AllowedNetwork, String> {
+    let invalid = || format!("Network {} not valid", input);
+    let (address, prefix) = match input.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (input, None),
+    };
+    let address = address.parse::<IpAddr>().map_err(|_| invalid())?;
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => match prefix.parse::<u8>() {
+            Ok(prefix) if prefix <= bits => prefix,
+            _ => return Err(invalid()),
+        },
+        None => bits,
+    };
+    Ok(AllowedNetwork { address, prefix })
+}
+
+fn read_relay_settings(
+    settings: &HashMap<String, String>,
+) -> Result<Option<RelaySettings>, String> {
+    let address = match settings.get("relay_address") {
+        Some(address) => address.clone(),
+        None => return Ok(None),
+    };
+    let max_clients = match settings.get("relay_max_clients") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(max_clients) if max_clients > 0 => max_clients,
+            _ => return Err("Setting relay_max_clients is not a valid number".to_string()),
+        },
+        None => 5,
+    };
+    let allow = match settings.get("relay_allow") {
+        Some(value) => value
+            .split(',')
+            .map(|network| read_allowed_network(network.trim()))
+            .collect::<Result<Vec<AllowedNetwork>, String>>()?,
+        None => Vec::new(),
+    };
+    let pipeline = match settings.get("relay_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+    // Telegrams are relayed unchanged
+    if selects(&pipeline) {
+        return Err("Stage select can not be used in relay_pipeline".to_string());
+    }
+
+    Ok(Some(RelaySettings {
+        address,
+        max_clients,
+        allow,
+        pipeline,
+    }))
+}
+
+fn read_modbus_settings(
+    settings: &HashMap<String, String>,
+) -> Result<Option<ModbusSettings>, String> {
+    let address = match settings.get("modbus_address") {
+        Some(address) => address.clone(),
+        None => return Ok(None),
+    };
+    let unit_id = match settings.get("modbus_unit_id") {
+        Some(value) => value
+            .parse::<u8>()
+            .map_err(|_| "Setting modbus_unit_id can not be converted to a number".to_string())?,
+        None => 1,
+    };
+    let timeout = match settings
+        .get("modbus_timeout")
+        .map(|value| value.parse::<u64>())
+    {
+        Some(Ok(0)) => None,
+        Some(Ok(timeout)) => Some(timeout),
+        Some(Err(_)) => {
+            return Err("Setting modbus_timeout can not be converted to a number".to_string())
+        }
+        None => Some(30),
+    };
+    let max_clients = match settings.get("modbus_max_clients") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(max_clients) if max_clients > 0 => max_clients,
+            _ => return Err("Setting modbus_max_clients is not a valid number".to_string()),
+        },
+        None => 5,
+    };
+    let allow = match settings.get("modbus_allow") {
+        Some(value) => value
+            .split(',')
+            .map(|network| read_allowed_network(network.trim()))
+            .collect::<Result<Vec<AllowedNetwork>, String>>()?,
+        None => Vec::new(),
+    };
+    let pipeline = match settings.get("modbus_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(ModbusSettings {
+        address,
+        unit_id,
+        timeout,
+        max_clients,
+        allow,
+        pipeline,
+    }))
+}
+
+// Parses a number of days, where 0 means never.
+fn read_days(settings: &HashMap<String, String>, key: &str) -> Result<Option<u64>, String> {
+    match settings.get(key).map(|value| value.parse::<u64>()) {
+        Some(Ok(0)) => Ok(None),
+        Some(Ok(days)) => Ok(Some(days)),
+        Some(Err(_)) => Err(format!("Setting {} can not be converted to a number", key)),
+        None => Ok(None),
+    }
+}
+
+fn read_sqlite_settings(
+    settings: &HashMap<String, String>,
+) -> Result<Option<SqliteSettings>, String> {
+    let path = match settings.get("sqlite_path") {
+        Some(path) => path.clone(),
+        None => return Ok(None),
+    };
+    let batch_interval = match settings.get("sqlite_batch_interval") {
+        Some(value) => value.parse::<u64>().map_err(|_| {
+            "Setting sqlite_batch_interval can not be converted to a number".to_string()
+        })?,
+        None => 60,
+    };
+    let downsample_after = match settings.get("sqlite_downsample_after") {
+        Some(_) => read_days(settings, "sqlite_downsample_after")?,
+        None => Some(7),
+    };
+    let downsample_window = match settings.get("sqlite_downsample_window") {
+        Some(value) => read_window(value)?.unwrap_or(300),
+        None => 300,
+    };
+    let retention = read_days(settings, "sqlite_retention")?;
+    let pipeline = match settings.get("sqlite_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(SqliteSettings {
+        path,
+        batch_interval,
+        downsample_after,
+        downsample_window,
+        retention,
+        pipeline,
+    }))
+}
+
+fn read_csv_settings(settings: &HashMap<String, String>) -> Result<Option<CsvSettings>, String> {
+    let path = match settings.get("csv_path") {
+        Some(path) => path.clone(),
+        None => return Ok(None),
+    };
+    if StrftimeItems::new(&path).any(|item| item == Item::Error) {
+        return Err(format!(
+            "Setting csv_path has an invalid date pattern: {}",
+            path
+        ));
+    }
+    let columns = match settings.get("csv_columns") {
+        Some(value) => value
+            .split(',')
+            .map(|column| column.trim().to_string())
+            .filter(|column| !column.is_empty())
+            .collect(),
+        None => [
+            "timestamp",
+            "1-0:1.8.1",
+            "1-0:1.8.2",
+            "1-0:2.8.1",
+            "1-0:2.8.2",
+            "1-0:1.7.0",
+            "1-0:2.7.0",
+        ]
+        .iter()
+        .map(|column| column.to_string())
+        .collect(),
+    };
+    let delimiter = match settings.get("csv_delimiter").map(String::as_str) {
+        Some("tab") => '\t',
+        Some(value) if value.chars().count() == 1 => value.chars().next().unwrap(),
+        Some(_) => {
+            return Err("Setting csv_delimiter must be a single character or 'tab'".to_string())
+        }
+        None => ',',
+    };
+    let decimal_separator = match settings.get("csv_decimal_separator").map(String::as_str) {
+        Some(".") | None => '.',
+        Some(",") => ',',
+        Some(_) => return Err("Setting csv_decimal_separator must be '.' or ','".to_string()),
+    };
+    let pipeline = match settings.get("csv_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(CsvSettings {
+        path,
+        columns,
+        delimiter,
+        decimal_separator,
+        pipeline,
+    }))
+}
+
+fn read_graphite_settings(settings: &HashMap<String, String>) -> Result<GraphiteSettings, String> {
+    let carbon = settings.get("graphite_carbon").map(|value| {
+        if let Some(address) = value.strip_prefix("udp://") {
+            (Transport::Udp, String::from(address))
+        } else {
+            let address = value.strip_prefix("tcp://").unwrap_or(value);
+            (Transport::Tcp, String::from(address))
+        }
+    });
+    let template = settings
+        .get("graphite_template")
+        .cloned()
+        .unwrap_or_else(|| String::from("energy.{equipment_id}.{obis_name}"));
+    let batch_size = match settings.get("graphite_batch_size") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(batch_size) if batch_size > 0 => batch_size,
+            _ => {
+                return Err(
+                    "Setting graphite_batch_size can not be converted to a number".to_string(),
+                )
+            }
+        },
+        None => 1,
+    };
+    let pipeline = match settings.get("graphite_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(GraphiteSettings {
+        carbon,
+        statsd: settings.get("graphite_statsd").cloned(),
+        template,
+        batch_size,
+        pipeline,
+    })
+}
+
+fn read_otlp_settings(settings: &HashMap<String, String>) -> Result<Option<OtlpSettings>, String> {
+    let endpoint = match settings.get("otlp_endpoint") {
+        Some(endpoint) => endpoint.clone(),
+        None => return Ok(None),
+    };
+    let headers = match settings.get("otlp_headers") {
+        Some(value) => read_headers(value, "otlp_headers")?,
+        None => Vec::new(),
+    };
+    let pipeline = match settings.get("otlp_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(OtlpSettings {
+        endpoint,
+        headers,
+        pipeline,
+    }))
+}
+
+// Reads mappings such as `1-0:1.7.0*W=power,1-0:1.8.1=delivered_low`, where the unit is optional.
+fn read_value_mappings(input: &str, key: &str) -> Result<Vec<ValueMapping>, String> {
+    input
+        .split(',')
+        .map(|mapping| {
+            let (value, name) = mapping
+                .split_once('=')
+                .ok_or_else(|| format!("Setting {} has no name in {}", key, mapping))?;
+            let (obis, unit) = match value.split_once('*') {
+                Some((obis, unit)) => {
+                    let unit = Unit::parse(unit.trim())
+                        .ok_or_else(|| format!("Setting {} has unknown unit {}", key, unit))?;
+                    (obis, Some(unit))
+                }
+                None => (value, None),
+            };
+            if obis.trim().is_empty() || name.trim().is_empty() {
+                return Err(format!("Setting {} is invalid in {}", key, mapping));
+            }
+            Ok(ValueMapping {
+                obis: String::from(obis.trim()),
+                unit,
+                name: String::from(name.trim()),
+            })
+        })
+        .collect()
+}
+
+fn read_emoncms_settings(
+    settings: &HashMap<String, String>,
+) -> Result<Option<EmoncmsSettings>, String> {
+    let url = match settings.get("emoncms_url") {
+        Some(url) => url.clone(),
+        None => return Ok(None),
+    };
+    let apikey = settings
+        .get("emoncms_apikey")
+        .cloned()
+        .ok_or("Setting emoncms_apikey is required for Emoncms")?;
+    let inputs = match settings.get("emoncms_inputs") {
+        Some(value) => read_value_mappings(value, "emoncms_inputs")?,
+        None => Vec::new(),
+    };
+    let pipeline = match settings.get("emoncms_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(EmoncmsSettings {
+        url,
+        apikey,
+        node: settings
+            .get("emoncms_node")
+            .cloned()
+            .unwrap_or_else(|| String::from("dsmr")),
+        inputs,
+        pipeline,
+    }))
+}
+
+fn read_volkszaehler_settings(
+    settings: &HashMap<String, String>,
+) -> Result<Option<VolkszaehlerSettings>, String> {
+    let url = match settings.get("volkszaehler_url") {
+        Some(url) => url.clone(),
+        None => return Ok(None),
+    };
+    let channels = match settings.get("volkszaehler_channels") {
+        Some(value) => read_value_mappings(value, "volkszaehler_channels")?,
+        None => return Err("Setting volkszaehler_channels is required for Volkszähler".to_string()),
+    };
+    let pipeline = match settings.get("volkszaehler_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(VolkszaehlerSettings {
+        url,
+        channels,
+        pipeline,
+    }))
+}
+
+fn read_pvoutput_settings(
+    settings: &HashMap<String, String>,
+) -> Result<Option<PvoutputSettings>, String> {
+    let apikey = match settings.get("pvoutput_apikey") {
+        Some(apikey) => apikey.clone(),
+        None => return Ok(None),
+    };
+    let system_id = settings
+        .get("pvoutput_system_id")
+        .cloned()
+        .ok_or("Setting pvoutput_system_id is required for PVOutput")?;
+    // PVOutput accepts a status interval of 5, 10 or 15 minutes
+    let interval = match settings.get("pvoutput_interval") {
+        Some(value) => match read_window(value)? {
+            Some(interval @ (300 | 600 | 900)) => interval,
+            _ => return Err("Setting pvoutput_interval must be 5m, 10m or 15m".to_string()),
+        },
+        None => 300,
+    };
+    let net = match settings.get("pvoutput_net").map(|value| value.as_str()) {
+        Some("true") => true,
+        Some("false") | None => false,
+        Some(_) => return Err("Setting pvoutput_net must be true or false".to_string()),
+    };
+    let pipeline = match settings.get("pvoutput_pipeline") {
+        Some(value) => read_pipeline(value)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(PvoutputSettings {
+        url: settings
+            .get("pvoutput_url")
+            .cloned()
+            .unwrap_or_else(|| String::from("https://pvoutput.org")),
+        apikey,
+        system_id,
+        interval,
+        net,
+        pipeline,
+    }))
+}
+
+pub fn settings(settings: config::Config) -> Result<Settings, String> {
+    let config_map = settings
+        .try_deserialize::<HashMap<String, String>>()
+        .map_err(|e| e.to_string())
+        .unwrap();
+
+    let mut errors = Vec::new();
+    let serial = collect_error(read_serial_settings(&config_map), &mut errors);
+    let api = collect_error(read_host_settings(&config_map), &mut errors);
+    let clock = collect_error(read_clock_settings(&config_map), &mut errors);
+    let validation = collect_error(read_validation_settings(&config_map), &mut errors);
+    let peak = collect_error(read_peak_settings(&config_map), &mut errors);
+    let totals = collect_error(read_totals_settings(&config_map), &mut errors);
+    let cost = collect_error(read_cost_settings(&config_map), &mut errors);
+    let mqtt = collect_error(read_mqtt_settings(&config_map), &mut errors);
+    let mqtt_sink = collect_error(read_mqtt_sink_settings(&config_map), &mut errors);
+    let alert = collect_error(read_alert_settings(&config_map), &mut errors);
+    let webhook = collect_error(read_webhook_settings(&config_map), &mut errors);
+    let http = collect_error(read_http_settings(&config_map), &mut errors);
+    let stream = collect_error(read_stream_settings(&config_map), &mut errors);
+    let jsonl = collect_error(read_jsonl_settings(&config_map), &mut errors);
+    let relay = collect_error(read_relay_settings(&config_map), &mut errors);
+    let modbus = collect_error(read_modbus_settings(&config_map), &mut errors);
+    let sqlite = collect_error(read_sqlite_settings(&config_map), &mut errors);
+    let csv = collect_error(read_csv_settings(&config_map), &mut errors);
+    let graphite = collect_error(read_graphite_settings(&config_map), &mut errors);
+    let otlp = collect_error(read_otlp_settings(&config_map), &mut errors);
+    let emoncms = collect_error(read_emoncms_settings(&config_map), &mut errors);
+    let volkszaehler = collect_error(read_volkszaehler_settings(&config_map), &mut errors);
+    let pvoutput = collect_error(read_pvoutput_settings(&config_map), &mut errors);
+
+    if !errors.is_empty() {
+        return Err(errors.join(" + "));
+    }
+
+    // All settings are present when there are no errors
+    Ok(Settings {
+        serial: serial.unwrap(),
+        api: api.unwrap(),
+        clock: clock.unwrap(),
+        validation: validation.unwrap(),
+        peak: peak.unwrap(),
+        totals: totals.unwrap(),
+        cost: cost.unwrap(),
+        mqtt: mqtt.unwrap(),
+        mqtt_sink: mqtt_sink.unwrap(),
+        alert: alert.unwrap(),
+        webhook: webhook.unwrap(),
+        http: http.unwrap(),
+        stream: stream.unwrap(),
+        jsonl: jsonl.unwrap(),
+        relay: relay.unwrap(),
+        modbus: modbus.unwrap(),
+        sqlite: sqlite.unwrap(),
+        csv: csv.unwrap(),
+        graphite: graphite.unwrap(),
+        otlp: otlp.unwrap(),
+        emoncms: emoncms.unwrap(),
+        volkszaehler: volkszaehler.unwrap(),
+        pvoutput: pvoutput.unwrap(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn read_serial_settings_valid_settings() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
+        settings.insert(String::from("serial_baudrate"), String::from("9600"));
+
+        let result = read_serial_settings(&settings);
+
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        assert_eq!(value.port, "/dev/ttyUSB0");
+        assert_eq!(value.baud_rate, 9600);
+    }
+
+    #[test]
+    fn read_serial_settings_without_serial_port() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("serial_baudrate"), String::from("9600"));
+
+        let result = read_serial_settings(&settings);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn read_serial_settings_without_serial_baudrate() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
+
+        let result = read_serial_settings(&settings);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn read_serial_settings_with_invalid_serial_baudrate() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
+        settings.insert(String::from("serial_baudrate"), String::from("a"));
+
+        let result = read_serial_settings(&settings);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn read_serial_settings_with_parity_bit_odd() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
+        settings.insert(String::from("serial_baudrate"), String::from("9600"));
+        settings.insert(String::from("serial_parity"), String::from("O"));
+
+        let result = read_serial_settings(&settings);
+
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        assert_eq!(value.parity_bit, ParityBitSetting::Odd);
+    }
+
+    #[test]
+    fn read_serial_settings_with_parity_bit_even() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
+        settings.insert(String::from("serial_baudrate"), String::from("9600"));
+        settings.insert(String::from("serial_parity"), String::from("E"));
+
+        let result = read_serial_settings(&settings);
+
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        assert_eq!(value.parity_bit, ParityBitSetting::Even);
+    }
+
+    #[test]
+    fn read_serial_settings_with_byte_size_seven() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("serial_port"), String::from("/dev/ttyUSB0"));
+        settings.insert(String::from("serial_baudrate"), String::from("9600"));
+        settings.insert(String::from("serial_bytesize"), String::from("7"));
+
+        let result = read_serial_settings(&settings);
+
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        assert_eq!(value.byte_size, 7);
+    }
+
+    #[test]
+    fn host_settings_single_pair() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("api_hosts"), String::from("localhost"));
+        settings.insert(String::from("api_keys"), String::from("this-is-not-secret"));
+
+        let result = read_host_settings(&settings);
+
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        assert_eq!(value.hosts.len(), 1);
+        assert_eq!(value.hosts[0].address, "localhost");
+        assert_eq!(value.hosts[0].key, "this-is-not-secret");
+    }
+
+    #[test]
+    fn host_settings_no_api_hosts() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("api_hosts"), String::from("localhost"));
+
+        let result = read_host_settings(&settings);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn host_settings_no_api_keys() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("api_hosts"), String::from("localhost"));
+
+        let result = read_host_settings(&settings);
+
+        assert!(result.is_err());
     }
 
     #[test]
@@ -247,7 +1742,7 @@ mod tests {
 
         let result = read_host_settings(&settings);
 
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let value = result.unwrap();
         assert_eq!(value.hosts.len(), 2);
         assert_eq!(value.hosts[0].address, "localhost");
@@ -257,16 +1752,1000 @@ mod tests {
     }
 
     #[test]
-    fn host_settings_number_elements_mismatch() {
+    fn host_settings_with_pipelines() {
         let mut settings = HashMap::new();
         settings.insert(
             String::from("api_hosts"),
             String::from("localhost,remote-host"),
         );
-        settings.insert(String::from("api_keys"), String::from("this-is-not-secret"));
+        settings.insert(
+            String::from("api_keys"),
+            String::from("this-is-not-secret,this-better-be-secret"),
+        );
+        settings.insert(
+            String::from("api_pipelines"),
+            String::from(";unflagged|throttle(60)"),
+        );
+
+        let result = read_host_settings(&settings);
+
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        assert!(value.hosts[0].pipeline.is_empty());
+        assert_eq!(
+            value.hosts[1].pipeline,
+            vec![StageSetting::Unflagged, StageSetting::Throttle(60)]
+        );
+    }
+
+    #[test]
+    fn host_settings_select_requires_parsed() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            String::from("api_hosts"),
+            String::from("localhost,remote-host"),
+        );
+        settings.insert(
+            String::from("api_keys"),
+            String::from("this-is-not-secret,this-better-be-secret"),
+        );
+        settings.insert(
+            String::from("api_pipelines"),
+            String::from(";select(1-0:1.8.1)"),
+        );
+        assert!(read_host_settings(&settings).is_err());
+
+        settings.insert(String::from("api_modes"), String::from("raw,parsed"));
+        let value = read_host_settings(&settings).unwrap();
+
+        assert_eq!(
+            value.hosts[1].pipeline,
+            vec![StageSetting::Select(vec![String::from("1-0:1.8.1")])]
+        );
+    }
+
+    #[test]
+    fn host_settings_with_intervals() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            String::from("api_hosts"),
+            String::from("localhost,remote-host"),
+        );
+        settings.insert(
+            String::from("api_keys"),
+            String::from("this-is-not-secret,this-better-be-secret"),
+        );
+        settings.insert(String::from("api_intervals"), String::from("5,60"));
+        settings.insert(
+            String::from("api_downsampling"),
+            String::from("latest,mean"),
+        );
+        assert!(read_host_settings(&settings).is_err());
+
+        settings.insert(String::from("api_modes"), String::from("raw,parsed"));
+        let result = read_host_settings(&settings);
+
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        assert_eq!(value.hosts[0].interval, 5);
+        assert_eq!(value.hosts[0].downsampling, Downsampling::Latest);
+        assert_eq!(value.hosts[1].interval, 60);
+        assert_eq!(value.hosts[1].downsampling, Downsampling::Mean);
+    }
+
+    #[test]
+    fn host_settings_without_intervals() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("api_hosts"), String::from("localhost"));
+        settings.insert(String::from("api_keys"), String::from("this-is-not-secret"));
 
         let result = read_host_settings(&settings);
 
-        assert_eq!(result.is_ok(), false);
+        let value = result.unwrap();
+        assert_eq!(value.hosts[0].interval, 0);
+        assert_eq!(value.hosts[0].downsampling, Downsampling::Latest);
+        assert_eq!(value.hosts[0].mode, UploadMode::Raw);
+    }
+
+    #[test]
+    fn host_settings_with_modes() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            String::from("api_hosts"),
+            String::from("localhost,remote-host"),
+        );
+        settings.insert(
+            String::from("api_keys"),
+            String::from("this-is-not-secret,this-better-be-secret"),
+        );
+        settings.insert(String::from("api_modes"), String::from("raw, parsed"));
+
+        let value = read_host_settings(&settings).unwrap();
+        assert_eq!(value.hosts[0].mode, UploadMode::Raw);
+        assert_eq!(value.hosts[1].mode, UploadMode::Parsed);
+
+        settings.insert(String::from("api_modes"), String::from("raw,json"));
+        assert!(read_host_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn host_settings_invalid_intervals() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("api_hosts"), String::from("localhost"));
+        settings.insert(String::from("api_keys"), String::from("this-is-not-secret"));
+        settings.insert(String::from("api_intervals"), String::from("often"));
+
+        assert!(read_host_settings(&settings).is_err());
+
+        settings.insert(String::from("api_intervals"), String::from("5,60"));
+
+        assert!(read_host_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn host_settings_with_windows() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            String::from("api_hosts"),
+            String::from("localhost,remote-host"),
+        );
+        settings.insert(
+            String::from("api_keys"),
+            String::from("this-is-not-secret,this-better-be-secret"),
+        );
+        settings.insert(String::from("api_windows"), String::from(",15m"));
+        assert!(read_host_settings(&settings).is_err());
+
+        settings.insert(String::from("api_modes"), String::from("raw,parsed"));
+        let result = read_host_settings(&settings);
+
+        let value = result.unwrap();
+        assert_eq!(value.hosts[0].window, None);
+        assert_eq!(value.hosts[1].window, Some(900));
+    }
+
+    #[test]
+    fn window_lengths() {
+        assert_eq!(read_window("1m"), Ok(Some(60)));
+        assert_eq!(read_window("5m"), Ok(Some(300)));
+        assert_eq!(read_window("15m"), Ok(Some(900)));
+        assert_eq!(read_window("1h"), Ok(Some(3600)));
+        assert_eq!(read_window(""), Ok(None));
+        assert!(read_window("7m").is_err());
+        assert!(read_window("45m").is_err());
+        assert!(read_window("2h").is_err());
+        assert!(read_window("0m").is_err());
+        assert!(read_window("15s").is_err());
+        assert!(read_window("m").is_err());
+        assert!(read_window("5é").is_err());
+        assert!(read_window("18446744073709551615h").is_err());
+    }
+
+    #[test]
+    fn host_settings_pipelines_mismatch() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("api_hosts"), String::from("localhost"));
+        settings.insert(String::from("api_keys"), String::from("this-is-not-secret"));
+        settings.insert(String::from("api_pipelines"), String::from("dedupe;dedupe"));
+
+        let result = read_host_settings(&settings);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn pipeline_with_all_stages() {
+        let result = read_pipeline(
+            "filter(1-0:1.7.0) | unflagged | select(1-0:1.8.1, 1-0:1.8.2) | throttle(5) | dedupe | change(1-0:1.7.0,50*W) | tee(/tmp/telegrams.txt)",
+        );
+
+        assert_eq!(
+            result.unwrap(),
+            vec![
+                StageSetting::Filter(String::from("1-0:1.7.0")),
+                StageSetting::Unflagged,
+                StageSetting::Select(vec![String::from("1-0:1.8.1"), String::from("1-0:1.8.2")]),
+                StageSetting::Throttle(5),
+                StageSetting::Dedupe,
+                StageSetting::Change(String::from("1-0:1.7.0"), Quantity::parse("50*W").unwrap()),
+                StageSetting::Tee(String::from("/tmp/telegrams.txt")),
+            ]
+        );
+    }
+
+    #[test]
+    fn pipeline_with_invalid_stages() {
+        assert!(read_pipeline("throttle").is_err());
+        assert!(read_pipeline("throttle(soon)").is_err());
+        assert!(read_pipeline("change(1-0:1.7.0,50)").is_err());
+        assert!(read_pipeline("dedupe(1)").is_err());
+        assert!(read_pipeline("filter(1-0:1.7.0").is_err());
+        assert!(read_pipeline("reverse").is_err());
+    }
+
+    #[test]
+    fn host_settings_number_elements_mismatch() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            String::from("api_hosts"),
+            String::from("localhost,remote-host"),
+        );
+        settings.insert(String::from("api_keys"), String::from("this-is-not-secret"));
+
+        let result = read_host_settings(&settings);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn clock_settings_default_threshold() {
+        let settings = HashMap::new();
+
+        let result = read_clock_settings(&settings);
+
+        assert_eq!(result.unwrap().drift_threshold, 30);
+    }
+
+    #[test]
+    fn clock_settings_custom_threshold() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("clock_drift_threshold"), String::from("120"));
+
+        let result = read_clock_settings(&settings);
+
+        assert_eq!(result.unwrap().drift_threshold, 120);
+    }
+
+    #[test]
+    fn clock_settings_invalid_threshold() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("clock_drift_threshold"), String::from("soon"));
+
+        let result = read_clock_settings(&settings);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn peak_settings_defaults() {
+        let settings = HashMap::new();
+
+        let result = read_peak_settings(&settings).unwrap();
+
+        assert_eq!(result.threshold, None);
+        assert_eq!(result.state_file, None);
+    }
+
+    #[test]
+    fn peak_settings_custom() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("peak_threshold"), String::from("2.5"));
+        settings.insert(
+            String::from("peak_state_file"),
+            String::from("/var/lib/dsmr-rs/peaks.txt"),
+        );
+
+        let result = read_peak_settings(&settings).unwrap();
+
+        assert_eq!(result.threshold, Some(Decimal::parse("2.5").unwrap()));
+        assert_eq!(
+            result.state_file,
+            Some(String::from("/var/lib/dsmr-rs/peaks.txt"))
+        );
+    }
+
+    #[test]
+    fn peak_settings_invalid_threshold() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("peak_threshold"), String::from("high"));
+
+        assert!(read_peak_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn totals_settings() {
+        let mut settings = HashMap::new();
+        assert_eq!(read_totals_settings(&settings).unwrap().state_file, None);
+
+        settings.insert(
+            String::from("totals_state_file"),
+            String::from("/var/lib/dsmr-rs/totals.txt"),
+        );
+        assert_eq!(
+            read_totals_settings(&settings).unwrap().state_file,
+            Some(String::from("/var/lib/dsmr-rs/totals.txt"))
+        );
+    }
+
+    #[test]
+    fn cost_settings_defaults() {
+        let settings = HashMap::new();
+
+        let result = read_cost_settings(&settings).unwrap();
+
+        assert!(result.tariffs.is_empty());
+        assert_eq!(result.dynamic_prices, None);
+        assert_eq!(result.state_file, None);
+    }
+
+    #[test]
+    fn cost_settings_with_tariff_periods() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            String::from("cost_tariffs"),
+            String::from("..2023-12-31|import=0.40|export=0.09|gas=1.45;2024-01-01..|import1=0.30|import2=0.28|export1=0.10|export2=0.08|daily=0.95|gas=1.40"),
+        );
+        settings.insert(
+            String::from("cost_dynamic_prices"),
+            String::from("/etc/dsmr-rs/prices.csv"),
+        );
+        settings.insert(
+            String::from("cost_state_file"),
+            String::from("/var/lib/dsmr-rs/costs.txt"),
+        );
+
+        let result = read_cost_settings(&settings).unwrap();
+
+        let price = |value: &str| Decimal::parse(value).unwrap();
+        assert_eq!(
+            result.state_file,
+            Some(String::from("/var/lib/dsmr-rs/costs.txt"))
+        );
+        assert_eq!(result.tariffs.len(), 2);
+        assert_eq!(
+            result.tariffs[0],
+            TariffPeriod {
+                from: None,
+                to: NaiveDate::from_ymd_opt(2023, 12, 31),
+                import: [price("0.40"), price("0.40")],
+                export: [price("0.09"), price("0.09")],
+                daily: price("0"),
+                gas: price("1.45"),
+            }
+        );
+        assert_eq!(
+            result.tariffs[1],
+            TariffPeriod {
+                from: NaiveDate::from_ymd_opt(2024, 1, 1),
+                to: None,
+                import: [price("0.30"), price("0.28")],
+                export: [price("0.10"), price("0.08")],
+                daily: price("0.95"),
+                gas: price("1.40"),
+            }
+        );
+        assert_eq!(
+            result.dynamic_prices,
+            Some(String::from("/etc/dsmr-rs/prices.csv"))
+        );
+    }
+
+    #[test]
+    fn cost_settings_invalid_tariff_periods() {
+        let invalid = |value: &str| {
+            let mut settings = HashMap::new();
+            settings.insert(String::from("cost_tariffs"), String::from(value));
+            read_cost_settings(&settings).is_err()
+        };
+
+        assert!(invalid("import=0.30"));
+        assert!(invalid("2024-13-01..|import=0.30"));
+        assert!(invalid("2024-01-01..|import=cheap"));
+        assert!(invalid("2024-01-01..|water=1.00"));
+    }
+
+    #[test]
+    fn validation_settings_defaults() {
+        let settings = HashMap::new();
+
+        let result = read_validation_settings(&settings).unwrap();
+
+        assert_eq!(result.monotonic, ValidationAction::Drop);
+        assert_eq!(result.range, ValidationAction::Drop);
+        assert_eq!(result.duplicate, ValidationAction::Drop);
+        assert_eq!(result.mandatory, ValidationAction::Flag);
+        assert!(result.ranges.is_empty());
+        assert_eq!(result.mandatory_fields.len(), 4);
+    }
+
+    #[test]
+    fn validation_settings_custom() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("validation_monotonic"), String::from("flag"));
+        settings.insert(String::from("validation_mandatory"), String::from("pass"));
+        settings.insert(
+            String::from("validation_ranges"),
+            String::from("1-0:32.7.0=180..260,1-0:1.7.0=0..17.25"),
+        );
+        settings.insert(
+            String::from("validation_mandatory_fields"),
+            String::from("0-0:1.0.0,1-0:1.8.1"),
+        );
+
+        let result = read_validation_settings(&settings).unwrap();
+
+        assert_eq!(result.monotonic, ValidationAction::Flag);
+        assert_eq!(result.mandatory, ValidationAction::Pass);
+        assert_eq!(result.ranges.len(), 2);
+        assert_eq!(result.ranges[0].obis, "1-0:32.7.0");
+        assert_eq!(result.ranges[0].min, Decimal::parse("180").unwrap());
+        assert_eq!(result.ranges[1].max, Decimal::parse("17.25").unwrap());
+        assert_eq!(result.mandatory_fields, vec!["0-0:1.0.0", "1-0:1.8.1"]);
+    }
+
+    #[test]
+    fn validation_settings_invalid_action() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("validation_duplicate"), String::from("ignore"));
+
+        let result = read_validation_settings(&settings);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn validation_settings_invalid_range() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            String::from("validation_ranges"),
+            String::from("1-0:32.7.0=180"),
+        );
+
+        let result = read_validation_settings(&settings);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn mqtt_settings() {
+        let mut settings = HashMap::new();
+        assert_eq!(read_mqtt_settings(&settings).unwrap(), None);
+
+        settings.insert(String::from("mqtt_broker"), String::from("localhost:1883"));
+        settings.insert(String::from("mqtt_username"), String::from("dsmr"));
+        let result = read_mqtt_settings(&settings).unwrap().unwrap();
+
+        assert_eq!(result.broker, "localhost:1883");
+        assert_eq!(result.client_id, "dsmr-rs");
+        assert_eq!(result.username, Some(String::from("dsmr")));
+        assert_eq!(result.password, None);
+
+        settings.remove("mqtt_username");
+        settings.insert(String::from("mqtt_password"), String::from("secret"));
+        assert!(read_mqtt_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn mqtt_sink_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_mqtt_sink_settings(&settings).unwrap().is_none());
+
+        settings.insert(String::from("mqtt_topic"), String::from("dsmr/"));
+        assert!(read_mqtt_sink_settings(&settings).is_err());
+
+        settings.insert(String::from("mqtt_broker"), String::from("localhost:1883"));
+        let result = read_mqtt_sink_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.topic, "dsmr");
+        assert_eq!(result.discovery_prefix, Some(String::from("homeassistant")));
+        assert!(result.pipeline.is_empty());
+
+        settings.insert(String::from("mqtt_discovery"), String::from("false"));
+        settings.insert(String::from("mqtt_pipeline"), String::from("throttle(10)"));
+        let result = read_mqtt_sink_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.discovery_prefix, None);
+        assert_eq!(result.pipeline, vec![StageSetting::Throttle(10)]);
+
+        settings.insert(String::from("mqtt_discovery"), String::from("yes"));
+        assert!(read_mqtt_sink_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn alert_settings_with_rules() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            String::from("alert_rules"),
+            String::from(
+                "main_fuse=1-0:31.7.0>25*A|for=60|hysteresis=1|cooldown=900; \
+                 low_voltage=1-0:32.7.0<207*V;no_gas=unchanged(0-1:24.2.1)|for=7200",
+            ),
+        );
+        settings.insert(
+            String::from("alert_command"),
+            String::from("/usr/local/bin/notify"),
+        );
+
+        let result = read_alert_settings(&settings).unwrap();
+
+        assert_eq!(
+            result.rules,
+            vec![
+                AlertRule {
+                    name: String::from("main_fuse"),
+                    condition: AlertCondition::Above(
+                        String::from("1-0:31.7.0"),
+                        Quantity::parse("25*A").unwrap()
+                    ),
+                    duration: 60,
+                    hysteresis: Decimal::parse("1").unwrap(),
+                    cooldown: 900,
+                },
+                AlertRule {
+                    name: String::from("low_voltage"),
+                    condition: AlertCondition::Below(
+                        String::from("1-0:32.7.0"),
+                        Quantity::parse("207*V").unwrap()
+                    ),
+                    duration: 0,
+                    hysteresis: Decimal::new(0, 0),
+                    cooldown: 0,
+                },
+                AlertRule {
+                    name: String::from("no_gas"),
+                    condition: AlertCondition::Unchanged(String::from("0-1:24.2.1")),
+                    duration: 7200,
+                    hysteresis: Decimal::new(0, 0),
+                    cooldown: 0,
+                },
+            ]
+        );
+        assert_eq!(result.command, Some(String::from("/usr/local/bin/notify")));
+        assert_eq!(result.webhook, None);
+    }
+
+    #[test]
+    fn alert_settings_invalid() {
+        let invalid = |key: &str, value: &str| {
+            let mut settings = HashMap::new();
+            settings.insert(String::from(key), String::from(value));
+            read_alert_settings(&settings).is_err()
+        };
+
+        assert!(invalid("alert_rules", "1-0:31.7.0>25*A"));
+        assert!(invalid("alert_rules", "fuse=1-0:31.7.0>25"));
+        assert!(invalid("alert_rules", "fuse=1-0:31.7.0=25*A"));
+        assert!(invalid("alert_rules", "fuse=1-0:31.7.0>25*A|for=soon"));
+        assert!(invalid("alert_rules", "fuse=1-0:31.7.0>25*A|repeat=2"));
+        assert!(invalid("alert_mqtt_topic", "dsmr/alerts"));
+    }
+
+    #[test]
+    fn webhook_settings_defaults() {
+        let mut settings = HashMap::new();
+        assert!(read_webhook_settings(&settings)
+            .unwrap()
+            .webhooks
+            .is_empty());
+
+        settings.insert(
+            String::from("webhook_urls"),
+            String::from("http://localhost:1880/dsmr"),
+        );
+        let result = read_webhook_settings(&settings).unwrap();
+
+        assert_eq!(result.webhooks.len(), 1);
+        assert_eq!(result.webhooks[0].method, "POST");
+        assert_eq!(result.webhooks[0].auth, WebhookAuth::None);
+        assert_eq!(result.webhooks[0].format, BodyFormat::Form);
+        assert_eq!(result.webhooks[0].body, "telegram={{raw}}");
+    }
+
+    #[test]
+    fn webhook_settings_custom() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            String::from("webhook_urls"),
+            String::from("http://localhost:1880/dsmr,https://collector.example.com/"),
+        );
+        settings.insert(String::from("webhook_methods"), String::from(",put"));
+        settings.insert(
+            String::from("webhook_headers"),
+            String::from(";X-Source: dsmr-rs|X-Site: home"),
+        );
+        settings.insert(
+            String::from("webhook_auth"),
+            String::from("token:abc;basic:user:pass:word"),
+        );
+        settings.insert(
+            String::from("webhook_bodies"),
+            String::from("raw:{{raw}};json:{\"power\": {{1-0:1.7.0}}}"),
+        );
+        settings.insert(String::from("webhook_pipelines"), String::from(";dedupe"));
+
+        let result = read_webhook_settings(&settings).unwrap();
+
+        assert_eq!(result.webhooks[0].method, "POST");
+        assert_eq!(result.webhooks[1].method, "PUT");
+        assert!(result.webhooks[0].headers.is_empty());
+        assert_eq!(
+            result.webhooks[1].headers,
+            vec![
+                (String::from("X-Source"), String::from("dsmr-rs")),
+                (String::from("X-Site"), String::from("home"))
+            ]
+        );
+        assert_eq!(
+            result.webhooks[0].auth,
+            WebhookAuth::Token(String::from("abc"))
+        );
+        assert_eq!(
+            result.webhooks[1].auth,
+            WebhookAuth::Basic(String::from("user"), String::from("pass:word"))
+        );
+        assert_eq!(result.webhooks[0].format, BodyFormat::Raw);
+        assert_eq!(result.webhooks[1].format, BodyFormat::Json);
+        assert_eq!(result.webhooks[1].body, "{\"power\": {{1-0:1.7.0}}}");
+        assert_eq!(result.webhooks[1].pipeline, vec![StageSetting::Dedupe]);
+    }
+
+    #[test]
+    fn webhook_settings_invalid() {
+        let invalid = |key: &str, value: &str| {
+            let mut settings = HashMap::new();
+            settings.insert(
+                String::from("webhook_urls"),
+                String::from("http://a,http://b"),
+            );
+            settings.insert(String::from(key), String::from(value));
+            read_webhook_settings(&settings).is_err()
+        };
+
+        assert!(invalid("webhook_methods", "POST"));
+        assert!(invalid("webhook_methods", "POST,SEND"));
+        assert!(invalid("webhook_headers", "X-Source;dsmr"));
+        assert!(invalid("webhook_auth", ";digest:abc"));
+        assert!(invalid("webhook_bodies", "raw:{{raw}};xml:<a/>"));
+        assert!(invalid(
+            "webhook_bodies",
+            "raw:{{raw}};json:@/nonexistent/body.json"
+        ));
+        // The default body sends the raw telegram
+        assert!(invalid("webhook_pipelines", ";select(1-0:1.7.0)"));
+    }
+
+    #[test]
+    fn http_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_http_settings(&settings).unwrap().is_none());
+
+        settings.insert(String::from("http_address"), String::from("0.0.0.0:8080"));
+        let result = read_http_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.address, "0.0.0.0:8080");
+        assert_eq!(result.history, 3600);
+
+        settings.insert(String::from("http_history"), String::from("an hour"));
+        assert!(read_http_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn stream_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_stream_settings(&settings).unwrap().is_none());
+
+        settings.insert(String::from("stream_address"), String::from("0.0.0.0:8081"));
+        settings.insert(String::from("stream_pipeline"), String::from("throttle(5)"));
+        let result = read_stream_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.address, "0.0.0.0:8081");
+        assert_eq!(result.pipeline.len(), 1);
+
+        settings.insert(String::from("stream_pipeline"), String::from("sometimes"));
+        assert!(read_stream_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn relay_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_relay_settings(&settings).unwrap().is_none());
+
+        settings.insert(String::from("relay_address"), String::from("0.0.0.0:2001"));
+        settings.insert(
+            String::from("relay_allow"),
+            String::from("192.168.1.0/24, 10.0.0.5,fd00::/8"),
+        );
+        let result = read_relay_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.address, "0.0.0.0:2001");
+        assert_eq!(result.max_clients, 5);
+        assert_eq!(
+            result.allow,
+            vec![
+                AllowedNetwork {
+                    address: "192.168.1.0".parse().unwrap(),
+                    prefix: 24
+                },
+                AllowedNetwork {
+                    address: "10.0.0.5".parse().unwrap(),
+                    prefix: 32
+                },
+                AllowedNetwork {
+                    address: "fd00::".parse().unwrap(),
+                    prefix: 8
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn relay_settings_invalid() {
+        let invalid = |key: &str, value: &str| {
+            let mut settings = HashMap::new();
+            settings.insert(String::from("relay_address"), String::from("0.0.0.0:2001"));
+            settings.insert(String::from(key), String::from(value));
+            read_relay_settings(&settings).is_err()
+        };
+
+        assert!(invalid("relay_max_clients", "0"));
+        assert!(invalid("relay_allow", "192.168.1.0/33"));
+        assert!(invalid("relay_allow", "localhost"));
+        assert!(invalid("relay_pipeline", "select(1-0:1.7.0)"));
+    }
+
+    #[test]
+    fn sqlite_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_sqlite_settings(&settings).unwrap().is_none());
+
+        settings.insert(
+            String::from("sqlite_path"),
+            String::from("/var/lib/dsmr-rs/readings.db"),
+        );
+        let result = read_sqlite_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.batch_interval, 60);
+        assert_eq!(result.downsample_after, Some(7));
+        assert_eq!(result.downsample_window, 300);
+        assert_eq!(result.retention, None);
+
+        settings.insert(String::from("sqlite_downsample_after"), String::from("0"));
+        settings.insert(String::from("sqlite_downsample_window"), String::from("1h"));
+        settings.insert(String::from("sqlite_retention"), String::from("365"));
+        let result = read_sqlite_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.downsample_after, None);
+        assert_eq!(result.downsample_window, 3600);
+        assert_eq!(result.retention, Some(365));
+
+        settings.insert(String::from("sqlite_retention"), String::from("a year"));
+        assert!(read_sqlite_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn csv_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_csv_settings(&settings).unwrap().is_none());
+
+        settings.insert(
+            String::from("csv_path"),
+            String::from("/var/lib/dsmr-rs/%Y-%m-%d.csv"),
+        );
+        let result = read_csv_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.columns.len(), 7);
+        assert_eq!(result.delimiter, ',');
+        assert_eq!(result.decimal_separator, '.');
+
+        settings.insert(
+            String::from("csv_columns"),
+            String::from("timestamp, 1-0:1.7.0"),
+        );
+        settings.insert(String::from("csv_delimiter"), String::from("tab"));
+        settings.insert(String::from("csv_decimal_separator"), String::from(","));
+        let result = read_csv_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.columns, vec!["timestamp", "1-0:1.7.0"]);
+        assert_eq!(result.delimiter, '\t');
+        assert_eq!(result.decimal_separator, ',');
+    }
+
+    #[test]
+    fn csv_settings_invalid() {
+        let invalid = |key: &str, value: &str| {
+            let mut settings = HashMap::new();
+            settings.insert(String::from("csv_path"), String::from("readings.csv"));
+            settings.insert(String::from(key), String::from(value));
+            read_csv_settings(&settings).is_err()
+        };
+
+        assert!(invalid("csv_path", "readings-%Q.csv"));
+        assert!(invalid("csv_delimiter", ";;"));
+        assert!(invalid("csv_decimal_separator", "'"));
+    }
+
+    #[test]
+    fn graphite_settings() {
+        let mut settings = HashMap::new();
+        let result = read_graphite_settings(&settings).unwrap();
+        assert_eq!(result.carbon, None);
+        assert_eq!(result.template, "energy.{equipment_id}.{obis_name}");
+        assert_eq!(result.batch_size, 1);
+
+        settings.insert(
+            String::from("graphite_carbon"),
+            String::from("udp://graphite:2003"),
+        );
+        settings.insert(String::from("graphite_batch_size"), String::from("10"));
+        let result = read_graphite_settings(&settings).unwrap();
+        assert_eq!(
+            result.carbon,
+            Some((Transport::Udp, String::from("graphite:2003")))
+        );
+        assert_eq!(result.batch_size, 10);
+
+        settings.insert(
+            String::from("graphite_carbon"),
+            String::from("graphite:2003"),
+        );
+        let result = read_graphite_settings(&settings).unwrap();
+        assert_eq!(
+            result.carbon,
+            Some((Transport::Tcp, String::from("graphite:2003")))
+        );
+
+        settings.insert(String::from("graphite_batch_size"), String::from("0"));
+        assert!(read_graphite_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn otlp_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_otlp_settings(&settings).unwrap().is_none());
+
+        settings.insert(
+            String::from("otlp_endpoint"),
+            String::from("http://localhost:4318"),
+        );
+        settings.insert(
+            String::from("otlp_headers"),
+            String::from("Authorization: Bearer secret"),
+        );
+        let result = read_otlp_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.endpoint, "http://localhost:4318");
+        assert_eq!(
+            result.headers,
+            vec![(String::from("Authorization"), String::from("Bearer secret"))]
+        );
+
+        settings.insert(String::from("otlp_headers"), String::from("Bearer secret"));
+        assert!(read_otlp_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn value_mappings() {
+        let result = read_value_mappings("1-0:1.7.0*W=power, 0-0:96.7.21=failures", "key");
+
+        assert_eq!(
+            result,
+            Ok(vec![
+                ValueMapping {
+                    obis: String::from("1-0:1.7.0"),
+                    unit: Some(Unit::Watt),
+                    name: String::from("power"),
+                },
+                ValueMapping {
+                    obis: String::from("0-0:96.7.21"),
+                    unit: None,
+                    name: String::from("failures"),
+                },
+            ])
+        );
+        assert!(read_value_mappings("1-0:1.7.0", "key").is_err());
+        assert!(read_value_mappings("1-0:1.7.0*kWs=power", "key").is_err());
+        assert!(read_value_mappings("=power", "key").is_err());
+    }
+
+    #[test]
+    fn emoncms_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_emoncms_settings(&settings).unwrap().is_none());
+
+        settings.insert(
+            String::from("emoncms_url"),
+            String::from("https://emoncms.org"),
+        );
+        assert!(read_emoncms_settings(&settings).is_err());
+
+        settings.insert(String::from("emoncms_apikey"), String::from("secret"));
+        let result = read_emoncms_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.node, "dsmr");
+        assert!(result.inputs.is_empty());
+
+        settings.insert(
+            String::from("emoncms_inputs"),
+            String::from("1-0:1.7.0*W=power"),
+        );
+        let result = read_emoncms_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.inputs[0].name, "power");
+    }
+
+    #[test]
+    fn volkszaehler_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_volkszaehler_settings(&settings).unwrap().is_none());
+
+        settings.insert(
+            String::from("volkszaehler_url"),
+            String::from("http://localhost/middleware.php"),
+        );
+        assert!(read_volkszaehler_settings(&settings).is_err());
+
+        settings.insert(
+            String::from("volkszaehler_channels"),
+            String::from("1-0:1.7.0*W=12345678-1234-1234-1234-123456789012"),
+        );
+        let result = read_volkszaehler_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.channels[0].unit, Some(Unit::Watt));
+        assert_eq!(
+            result.channels[0].name,
+            "12345678-1234-1234-1234-123456789012"
+        );
+    }
+
+    #[test]
+    fn pvoutput_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_pvoutput_settings(&settings).unwrap().is_none());
+
+        settings.insert(String::from("pvoutput_apikey"), String::from("secret"));
+        assert!(read_pvoutput_settings(&settings).is_err());
+
+        settings.insert(String::from("pvoutput_system_id"), String::from("12345"));
+        let result = read_pvoutput_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.url, "https://pvoutput.org");
+        assert_eq!(result.interval, 300);
+        assert!(!result.net);
+
+        settings.insert(String::from("pvoutput_interval"), String::from("15m"));
+        settings.insert(String::from("pvoutput_net"), String::from("true"));
+        let result = read_pvoutput_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.interval, 900);
+        assert!(result.net);
+
+        settings.insert(String::from("pvoutput_interval"), String::from("1h"));
+        assert!(read_pvoutput_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn modbus_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_modbus_settings(&settings).unwrap().is_none());
+
+        settings.insert(String::from("modbus_address"), String::from("0.0.0.0:502"));
+        let result = read_modbus_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.unit_id, 1);
+        assert_eq!(result.timeout, Some(30));
+        assert_eq!(result.max_clients, 5);
+        assert!(result.allow.is_empty());
+
+        settings.insert(String::from("modbus_unit_id"), String::from("2"));
+        settings.insert(String::from("modbus_timeout"), String::from("0"));
+        settings.insert(String::from("modbus_allow"), String::from("192.168.1.0/24"));
+        let result = read_modbus_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.unit_id, 2);
+        assert_eq!(result.timeout, None);
+        assert_eq!(result.allow.len(), 1);
+
+        settings.insert(String::from("modbus_max_clients"), String::from("0"));
+        assert!(read_modbus_settings(&settings).is_err());
+
+        settings.insert(String::from("modbus_max_clients"), String::from("2"));
+        settings.insert(String::from("modbus_unit_id"), String::from("256"));
+        assert!(read_modbus_settings(&settings).is_err());
+    }
+
+    #[test]
+    fn jsonl_settings() {
+        let mut settings = HashMap::new();
+        assert!(read_jsonl_settings(&settings).unwrap().is_none());
+
+        settings.insert(String::from("jsonl_output"), String::from("stdout"));
+        let result = read_jsonl_settings(&settings).unwrap().unwrap();
+        assert_eq!(result.output, JsonLinesOutput::Stdout);
+
+        settings.insert(
+            String::from("jsonl_output"),
+            String::from("/run/dsmr-rs/readings.sock"),
+        );
+        let result = read_jsonl_settings(&settings).unwrap().unwrap();
+        assert_eq!(
+            result.output,
+            JsonLinesOutput::Socket(String::from("/run/dsmr-rs/readings.sock"))
+        );
+
+        settings.insert(String::from("jsonl_output"), String::from(" "));
+        assert!(read_jsonl_settings(&settings).is_err());
     }
 }
@@ -0,0 +1,540 @@
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+
+use super::settings::SqliteSettings;
+use super::telegram::Telegram;
+use super::timestamp::parse_timestamp;
+use super::value::{Decimal, Unit};
+use super::TelegramConsumer;
+
+// Version of the schema below, kept in `PRAGMA user_version`
+const SCHEMA_VERSION: i64 = 1;
+
+// How old readings are combined when they are downsampled
+#[derive(Clone, Copy)]
+enum Aggregate {
+    // Counters only increase, so the largest value is the last one
+    Maximum,
+    Average,
+}
+
+// Columns of the readings table, with the OBIS code and unit of their value.
+// Counters without a unit are stored as numbers too.
+const COLUMNS: [(&str, &str, Option<Unit>, Aggregate); 22] = [
+    (
+        "electricity_delivered_1",
+        "1-0:1.8.1",
+        Some(Unit::KiloWattHour),
+        Aggregate::Maximum,
+    ),
+    (
+        "electricity_delivered_2",
+        "1-0:1.8.2",
+        Some(Unit::KiloWattHour),
+        Aggregate::Maximum,
+    ),
+    (
+        "electricity_returned_1",
+        "1-0:2.8.1",
+        Some(Unit::KiloWattHour),
+        Aggregate::Maximum,
+    ),
+    (
+        "electricity_returned_2",
+        "1-0:2.8.2",
+        Some(Unit::KiloWattHour),
+        Aggregate::Maximum,
+    ),
+    (
+        "power_delivered",
+        "1-0:1.7.0",
+        Some(Unit::KiloWatt),
+        Aggregate::Average,
+    ),
+    (
+        "power_returned",
+        "1-0:2.7.0",
+        Some(Unit::KiloWatt),
+        Aggregate::Average,
+    ),
+    (
+        "power_delivered_l1",
+        "1-0:21.7.0",
+        Some(Unit::KiloWatt),
+        Aggregate::Average,
+    ),
+    (
+        "power_delivered_l2",
+        "1-0:41.7.0",
+        Some(Unit::KiloWatt),
+        Aggregate::Average,
+    ),
+    (
+        "power_delivered_l3",
+        "1-0:61.7.0",
+        Some(Unit::KiloWatt),
+        Aggregate::Average,
+    ),
+    (
+        "power_returned_l1",
+        "1-0:22.7.0",
+        Some(Unit::KiloWatt),
+        Aggregate::Average,
+    ),
+    (
+        "power_returned_l2",
+        "1-0:42.7.0",
+        Some(Unit::KiloWatt),
+        Aggregate::Average,
+    ),
+    (
+        "power_returned_l3",
+        "1-0:62.7.0",
+        Some(Unit::KiloWatt),
+        Aggregate::Average,
+    ),
+    (
+        "voltage_l1",
+        "1-0:32.7.0",
+        Some(Unit::Volt),
+        Aggregate::Average,
+    ),
+    (
+        "voltage_l2",
+        "1-0:52.7.0",
+        Some(Unit::Volt),
+        Aggregate::Average,
+    ),
+    (
+        "voltage_l3",
+        "1-0:72.7.0",
+        Some(Unit::Volt),
+        Aggregate::Average,
+    ),
+    (
+        "current_l1",
+        "1-0:31.7.0",
+        Some(Unit::Ampere),
+        Aggregate::Average,
+    ),
+    (
+        "current_l2",
+        "1-0:51.7.0",
+        Some(Unit::Ampere),
+        Aggregate::Average,
+    ),
+    (
+        "current_l3",
+        "1-0:71.7.0",
+        Some(Unit::Ampere),
+        Aggregate::Average,
+    ),
+    ("voltage_sags_l1", "1-0:32.32.0", None, Aggregate::Maximum),
+    ("voltage_sags_l2", "1-0:52.32.0", None, Aggregate::Maximum),
+    ("voltage_sags_l3", "1-0:72.32.0", None, Aggregate::Maximum),
+    ("power_failures", "0-0:96.7.21", None, Aggregate::Maximum),
+];
+
+// Seconds of meter time between two runs of downsampling and retention
+const MAINTENANCE_INTERVAL: i64 = 3_600;
+// Readings kept for the next batch when writing fails, about an hour of telegrams
+const MAX_PENDING_READINGS: usize = 3_600;
+
+fn create_schema(connection: &Connection) -> Result<(), String> {
+    let columns: Vec<String> = COLUMNS
+        .iter()
+        .map(|(name, _, _, _)| format!("{} REAL", name))
+        .collect();
+    let schema = format!(
+        "CREATE TABLE IF NOT EXISTS readings (
+            timestamp INTEGER NOT NULL,
+            resolution INTEGER NOT NULL,
+            {},
+            PRIMARY KEY (timestamp, resolution)
+        );
+        CREATE TABLE IF NOT EXISTS gas (
+            channel INTEGER NOT NULL,
+            captured INTEGER NOT NULL,
+            delivered REAL NOT NULL,
+            PRIMARY KEY (channel, captured)
+        );
+        CREATE TABLE IF NOT EXISTS events (
+            kind TEXT NOT NULL,
+            ended INTEGER NOT NULL,
+            duration INTEGER NOT NULL,
+            PRIMARY KEY (kind, ended)
+        );
+        PRAGMA user_version = {};",
+        columns.join(",\n            "),
+        SCHEMA_VERSION
+    );
+    connection.execute_batch(&schema).map_err(|e| e.to_string())
+}
+
+// Returns the end and duration in seconds of each power failure in the log, 1-0:99.97.0.
+fn power_failures(telegram: &Telegram) -> Vec<(i64, i64)> {
+    let values = match telegram.object("1-0:99.97.0") {
+        Some(object) => &object.values,
+        None => return Vec::new(),
+    };
+    // The count and OBIS code of the log are followed by pairs of end and duration
+    values
+        .get(2..)
+        .unwrap_or_default()
+        .chunks(2)
+        .filter_map(|event| match event {
+            [ended, duration] => {
+                let ended = parse_timestamp(ended).ok()?.timestamp();
+                let duration = duration.trim_end_matches("*s").parse::<i64>().ok()?;
+                Some((ended, duration))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Batch {
+    // Timestamp and the values of the columns
+    readings: Vec<Vec<Value>>,
+    gas: Vec<(i64, i64, f64)>,
+    events: Vec<(i64, i64)>,
+}
+impl Batch {
+    fn len(&self) -> usize {
+        self.readings.len() + self.gas.len() + self.events.len()
+    }
+}
+
+// Stores readings, gas meter readings and power failures in a SQLite database. Rows are
+// written in batches, to limit the number of writes to the SD card of a Raspberry Pi.
+pub struct StoringConsumer {
+    connection: Connection,
+    path: String,
+    batch_interval: i64,
+    downsample_after: Option<u64>,
+    downsample_window: u64,
+    retention: Option<u64>,
+    batch: Batch,
+    // Meter time of the first reading in the batch
+    batch_start: Option<i64>,
+    last_maintenance: Option<i64>,
+    last_gas: Vec<(i64, i64, f64)>,
+    last_failures: Vec<(i64, i64)>,
+}
+impl StoringConsumer {
+    pub fn open(settings: &SqliteSettings) -> Result<Self, String> {
+        let connection = Connection::open(&settings.path)
+            .map_err(|msg| format!("Could not open database {} due to {}", settings.path, msg))?;
+        create_schema(&connection).map_err(|msg| {
+            format!(
+                "Could not create tables in {} due to {}",
+                settings.path, msg
+            )
+        })?;
+
+        Ok(StoringConsumer {
+            connection,
+            path: settings.path.clone(),
+            batch_interval: settings.batch_interval as i64,
+            downsample_after: settings.downsample_after,
+            downsample_window: settings.downsample_window,
+            retention: settings.retention,
+            batch: Batch::default(),
+            batch_start: None,
+            last_maintenance: None,
+            last_gas: Vec::new(),
+            last_failures: Vec::new(),
+        })
+    }
+
+    fn write(&mut self) -> Result<(), rusqlite::Error> {
+        let names: Vec<&str> = COLUMNS.iter().map(|(name, _, _, _)| *name).collect();
+        let insert_reading = format!(
+            "INSERT OR REPLACE INTO readings (timestamp, resolution, {}) VALUES (?, 0{})",
+            names.join(", "),
+            ", ?".repeat(names.len())
+        );
+
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(&insert_reading)?;
+            for values in &self.batch.readings {
+                statement.execute(params_from_iter(values))?;
+            }
+            let mut statement = transaction.prepare_cached(
+                "INSERT OR IGNORE INTO gas (channel, captured, delivered) VALUES (?, ?, ?)",
+            )?;
+            for (channel, captured, delivered) in &self.batch.gas {
+                statement.execute(params![channel, captured, delivered])?;
+            }
+            let mut statement = transaction.prepare_cached(
+                "INSERT OR IGNORE INTO events (kind, ended, duration) VALUES ('power_failure', ?, ?)",
+            )?;
+            for (ended, duration) in &self.batch.events {
+                statement.execute(params![ended, duration])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    // Replaces readings older than the configured number of days by one reading per window,
+    // and deletes everything older than the retention period.
+    fn maintain(&mut self, now: i64) -> Result<(), rusqlite::Error> {
+        let transaction = self.connection.transaction()?;
+        if let Some(days) = self.downsample_after {
+            let window = self.downsample_window as i64;
+            let cutoff = (now - days as i64 * 86_400) / window * window;
+            let aggregates: Vec<String> = COLUMNS
+                .iter()
+                .map(|(name, _, _, aggregate)| match aggregate {
+                    Aggregate::Maximum => format!("MAX({})", name),
+                    Aggregate::Average => format!("AVG({})", name),
+                })
+                .collect();
+            let names: Vec<&str> = COLUMNS.iter().map(|(name, _, _, _)| *name).collect();
+            transaction.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO readings (timestamp, resolution, {})
+                     SELECT timestamp / ?1 * ?1, ?1, {} FROM readings
+                     WHERE resolution = 0 AND timestamp < ?2 GROUP BY timestamp / ?1",
+                    names.join(", "),
+                    aggregates.join(", ")
+                ),
+                params![window, cutoff],
+            )?;
+            transaction.execute(
+                "DELETE FROM readings WHERE resolution = 0 AND timestamp < ?",
+                params![cutoff],
+            )?;
+        }
+        if let Some(days) = self.retention {
+            let cutoff = now - days as i64 * 86_400;
+            transaction.execute("DELETE FROM readings WHERE timestamp < ?", params![cutoff])?;
+            transaction.execute("DELETE FROM gas WHERE captured < ?", params![cutoff])?;
+            transaction.execute("DELETE FROM events WHERE ended < ?", params![cutoff])?;
+        }
+        transaction.commit()
+    }
+
+    fn flush(&mut self, now: i64) {
+        match self.write() {
+            Ok(()) => self.batch = Batch::default(),
+            Err(msg) => {
+                log::warn!(
+                    "Could not store {} rows in {} due to {}",
+                    self.batch.len(),
+                    self.path,
+                    msg
+                );
+                // Keep the rows for the next batch, dropping the oldest readings
+                let excess = self
+                    .batch
+                    .readings
+                    .len()
+                    .saturating_sub(MAX_PENDING_READINGS);
+                self.batch.readings.drain(..excess);
+            }
+        }
+        self.batch_start = None;
+
+        let due = self
+            .last_maintenance
+            .is_none_or(|last| now - last >= MAINTENANCE_INTERVAL);
+        if due {
+            if let Err(msg) = self.maintain(now) {
+                log::warn!("Could not clean up {} due to {}", self.path, msg);
+            }
+            self.last_maintenance = Some(now);
+        }
+    }
+}
+impl TelegramConsumer for StoringConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let now = match telegram.timestamp() {
+            Some(timestamp) => timestamp.timestamp(),
+            None => {
+                log::debug!("Not storing telegram without timestamp");
+                return;
+            }
+        };
+
+        let mut values = vec![Value::Integer(now)];
+        values.extend(COLUMNS.iter().map(|(_, obis, unit, _)| {
+            let value = match unit {
+                Some(unit) => telegram
+                    .quantity(obis)
+                    .and_then(|quantity| quantity.convert(*unit).ok())
+                    .map(|quantity| quantity.value),
+                None => telegram
+                    .value(obis)
+                    .and_then(|value| Decimal::parse(value).ok()),
+            };
+            value.map_or(Value::Null, |value| Value::Real(value.to_f64()))
+        }));
+        self.batch.readings.push(values);
+
+        // The gas reading and the power failure log are repeated until they change
+        let gas: Vec<(i64, i64, f64)> = telegram
+            .gas_readings()
+            .into_iter()
+            .map(|(channel, captured, reading)| {
+                (i64::from(channel), captured.timestamp(), reading.to_f64())
+            })
+            .collect();
+        if gas != self.last_gas {
+            self.batch.gas.extend(gas.iter().copied());
+            self.last_gas = gas;
+        }
+        let failures = power_failures(telegram);
+        if failures != self.last_failures {
+            self.batch.events.extend(failures.iter().copied());
+            self.last_failures = failures;
+        }
+
+        let start = *self.batch_start.get_or_insert(now);
+        if now - start >= self.batch_interval {
+            self.flush(now);
+        }
+    }
+}
+impl Drop for StoringConsumer {
+    fn drop(&mut self) {
+        if self.batch.len() > 0 {
+            if let Err(msg) = self.write() {
+                log::warn!("Could not store rows in {} due to {}", self.path, msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn settings(downsample_after: Option<u64>, retention: Option<u64>) -> SqliteSettings {
+        SqliteSettings {
+            path: String::from(":memory:"),
+            batch_interval: 10,
+            downsample_after,
+            downsample_window: 300,
+            retention,
+            pipeline: Vec::new(),
+        }
+    }
+
+    fn telegram(timestamp: &str, delivered: &str, power: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n1-0:1.8.1({}*kWh)\r\n1-0:1.7.0({}*kW)\r\n\
+             0-0:96.7.21(00004)\r\n\
+             1-0:99.97.0(1)(0-0:96.7.19)(231020101215S)(0000000240*s)\r\n\
+             0-1:24.2.1(231026204004S)(00004.381*m3)\r\n!\r\n",
+            timestamp, delivered, power
+        ))
+    }
+
+    fn count(consumer: &StoringConsumer, table: &str) -> i64 {
+        consumer
+            .connection
+            .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn store_readings_in_batches() {
+        let mut consumer = StoringConsumer::open(&settings(None, None)).unwrap();
+
+        consumer.consume(&telegram("231026204010S", "000032.159", "00.302"));
+        consumer.consume(&telegram("231026204015S", "000032.160", "00.298"));
+        assert_eq!(count(&consumer, "readings"), 0);
+
+        consumer.consume(&telegram("231026204020S", "000032.160", "00.310"));
+        assert_eq!(count(&consumer, "readings"), 3);
+        assert_eq!(count(&consumer, "gas"), 1);
+        assert_eq!(count(&consumer, "events"), 1);
+
+        let (delivered, power, failures): (f64, f64, f64) = consumer
+            .connection
+            .query_row(
+                "SELECT electricity_delivered_1, power_delivered, power_failures FROM readings
+                 WHERE timestamp = 1698345615",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((delivered, power, failures), (32.16, 0.298, 4.0));
+
+        let event: (i64, i64) = consumer
+            .connection
+            .query_row("SELECT ended, duration FROM events", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(event, (1697789535, 240));
+    }
+
+    #[test]
+    fn retry_batch_after_failed_write() {
+        let mut consumer = StoringConsumer::open(&settings(None, None)).unwrap();
+        consumer.connection.execute_batch("DROP TABLE gas").unwrap();
+
+        consumer.consume(&telegram("231026204010S", "000032.159", "00.302"));
+        consumer.consume(&telegram("231026204020S", "000032.160", "00.310"));
+        assert_eq!(count(&consumer, "readings"), 0);
+
+        create_schema(&consumer.connection).unwrap();
+        consumer.consume(&telegram("231026204025S", "000032.160", "00.298"));
+        consumer.consume(&telegram("231026204035S", "000032.161", "00.305"));
+        assert_eq!(count(&consumer, "readings"), 4);
+        assert_eq!(count(&consumer, "gas"), 1);
+        assert_eq!(count(&consumer, "events"), 1);
+    }
+
+    #[test]
+    fn downsample_old_readings() {
+        let mut consumer = StoringConsumer::open(&settings(Some(1), None)).unwrap();
+        consumer.consume(&telegram("231024100000S", "000030.000", "01.000"));
+        consumer.consume(&telegram("231024100100S", "000030.010", "03.000"));
+        consumer.consume(&telegram("231024100500S", "000030.020", "02.000"));
+
+        consumer.consume(&telegram("231026100000S", "000032.000", "00.500"));
+
+        let rows: Vec<(i64, i64, f64, f64)> = consumer
+            .connection
+            .prepare(
+                "SELECT timestamp, resolution, electricity_delivered_1, power_delivered
+                 FROM readings ORDER BY timestamp",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (1698134400, 300, 30.01, 2.0),
+                (1698134700, 300, 30.02, 2.0),
+                (1698307200, 0, 32.0, 0.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn delete_readings_after_retention() {
+        let mut consumer = StoringConsumer::open(&settings(None, Some(1))).unwrap();
+        consumer.consume(&telegram("231024100000S", "000030.000", "01.000"));
+        consumer.consume(&telegram("231024100010S", "000030.000", "01.000"));
+        consumer.consume(&telegram("231026100000S", "000032.000", "00.500"));
+        consumer.consume(&telegram("231026100010S", "000032.000", "00.500"));
+
+        assert_eq!(count(&consumer, "readings"), 2);
+        assert_eq!(count(&consumer, "gas"), 1);
+        assert_eq!(count(&consumer, "events"), 0);
+    }
+}
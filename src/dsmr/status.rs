@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SinkStatus {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    // Number of telegrams that could not be sent since the last success
+    pub failures: u64,
+}
+impl SinkStatus {
+    pub fn healthy(&self) -> bool {
+        self.failures == 0
+    }
+}
+
+// Keeps track of whether each sink could send its latest telegram, so it can be reported
+// elsewhere, such as by the HTTP API. Clones share the same statuses.
+#[derive(Clone, Default)]
+pub struct Health {
+    sinks: Arc<Mutex<BTreeMap<String, SinkStatus>>>,
+}
+impl Health {
+    pub fn success(&self, sink: &str) {
+        let mut sinks = self.sinks.lock().unwrap();
+        let status = sinks.entry(String::from(sink)).or_default();
+        status.last_success = Some(Utc::now());
+        status.failures = 0;
+    }
+
+    pub fn failure(&self, sink: &str, error: &str) {
+        let mut sinks = self.sinks.lock().unwrap();
+        let status = sinks.entry(String::from(sink)).or_default();
+        status.last_failure = Some(Utc::now());
+        status.last_error = Some(String::from(error));
+        status.failures += 1;
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, SinkStatus> {
+        self.sinks.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn track_status_per_sink() {
+        let health = Health::default();
+        let shared = health.clone();
+
+        shared.failure("mqtt", "connection refused");
+        shared.failure("mqtt", "connection refused");
+        shared.success("http://localhost");
+
+        let sinks = health.snapshot();
+        assert_eq!(sinks["mqtt"].failures, 2);
+        assert!(!sinks["mqtt"].healthy());
+        assert_eq!(
+            sinks["mqtt"].last_error,
+            Some(String::from("connection refused"))
+        );
+        assert!(sinks["http://localhost"].healthy());
+
+        shared.success("mqtt");
+        assert!(health.snapshot()["mqtt"].healthy());
+    }
+}
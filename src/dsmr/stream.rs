@@ -0,0 +1,376 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use super::api::url_decode;
+use super::json;
+use super::settings::StreamSettings;
+use super::telegram::Telegram;
+use super::TelegramConsumer;
+
+// Clients that do not accept an update within this time are disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Keeps only the given fields of a reading: OBIS codes from its values, or other parts of the
+// reading such as `derived` or `totals`. The timestamp is always kept.
+fn select(reading: &Value, fields: &[String]) -> Value {
+    let mut selected = Map::new();
+    selected.insert(String::from("timestamp"), reading["timestamp"].clone());
+    let mut values = Map::new();
+    for field in fields {
+        if let Some(value) = reading["values"].get(field) {
+            values.insert(field.clone(), value.clone());
+        } else if let Some(value) = reading.get(field) {
+            selected.insert(field.clone(), value.clone());
+        }
+    }
+    selected.insert(String::from("values"), Value::Object(values));
+    Value::Object(selected)
+}
+
+#[derive(Debug, PartialEq)]
+enum Protocol {
+    WebSocket(String),
+    ServerSentEvents,
+}
+
+#[derive(Debug, PartialEq)]
+struct Subscription {
+    protocol: Protocol,
+    fields: Option<Vec<String>>,
+}
+
+// Reads the HTTP request that opens a stream: a WebSocket upgrade or a plain GET for
+// Server-Sent Events, on `/stream` with an optional `fields` parameter.
+fn read_request(reader: &mut impl BufRead) -> Result<Subscription, String> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    let target = match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["GET", target, _] => String::from(*target),
+        _ => return Err(format!("Unsupported request {}", line.trim())),
+    };
+
+    let mut key = None;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(|e| e.to_string())?;
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(String::from(value.trim()));
+            }
+        }
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    if path != "/stream" {
+        return Err(format!("Unknown path {}", path));
+    }
+    let fields = query
+        .split('&')
+        .filter_map(|pair| pair.strip_prefix("fields="))
+        .map(|value| {
+            value
+                .split(',')
+                .filter(|field| !field.is_empty())
+                .map(url_decode)
+                .collect()
+        })
+        .next();
+    let protocol = match key {
+        Some(key) => Protocol::WebSocket(key),
+        None => Protocol::ServerSentEvents,
+    };
+    Ok(Subscription { protocol, fields })
+}
+
+// The latest update for a client that was not written yet. A newer update replaces it, so slow
+// clients skip updates instead of holding up the reader.
+#[derive(Default)]
+struct Pending {
+    update: Option<String>,
+    closed: bool,
+}
+
+struct Client {
+    fields: Option<Vec<String>>,
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+impl Client {
+    fn offer(&self, update: String) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.update.replace(update).is_some() {
+            log::trace!("Dropped stale update for slow stream client");
+        }
+        self.ready.notify_one();
+        !pending.closed
+    }
+
+    fn next(&self) -> String {
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            if let Some(update) = pending.update.take() {
+                return update;
+            }
+            pending = self.ready.wait(pending).unwrap();
+        }
+    }
+
+    fn close(&self) {
+        self.pending.lock().unwrap().closed = true;
+    }
+}
+
+type Clients = Arc<Mutex<Vec<Arc<Client>>>>;
+
+fn stream(mut stream: TcpStream, clients: Clients) -> Result<(), String> {
+    stream
+        .set_write_timeout(Some(WRITE_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let subscription = match read_request(&mut reader) {
+        Ok(subscription) => subscription,
+        Err(msg) => {
+            let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+            return Err(msg);
+        }
+    };
+
+    let client = Arc::new(Client {
+        fields: subscription.fields,
+        pending: Mutex::new(Pending::default()),
+        ready: Condvar::new(),
+    });
+
+    let result = match subscription.protocol {
+        Protocol::WebSocket(key) => {
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                derive_accept_key(key.as_bytes())
+            );
+            stream
+                .write_all(response.as_bytes())
+                .map_err(|e| e.to_string())?;
+            clients.lock().unwrap().push(client.clone());
+            let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+            loop {
+                if let Err(msg) = socket.send(Message::text(client.next())) {
+                    break msg.to_string();
+                }
+            }
+        }
+        Protocol::ServerSentEvents => {
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                      Cache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+                )
+                .map_err(|e| e.to_string())?;
+            clients.lock().unwrap().push(client.clone());
+            loop {
+                let event = format!("data: {}\n\n", client.next());
+                if let Err(msg) = stream.write_all(event.as_bytes()) {
+                    break msg.to_string();
+                }
+            }
+        }
+    };
+    client.close();
+    Err(result)
+}
+
+// Pushes each telegram as JSON to clients of a WebSocket or Server-Sent Events stream.
+pub struct StreamingConsumer {
+    clients: Clients,
+}
+impl StreamingConsumer {
+    pub fn start(settings: &StreamSettings) -> Result<Self, String> {
+        let listener = TcpListener::bind(&settings.address).map_err(|msg| {
+            format!(
+                "Could not serve stream on {} due to {}",
+                settings.address, msg
+            )
+        })?;
+        log::info!("Serving stream on {}", settings.address);
+
+        let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+        let shared = clients.clone();
+        thread::spawn(move || {
+            for connection in listener.incoming().flatten() {
+                let clients = shared.clone();
+                thread::spawn(move || {
+                    if let Err(msg) = stream(connection, clients) {
+                        log::debug!("Stream client disconnected: {}", msg);
+                    }
+                });
+            }
+        });
+        Ok(StreamingConsumer { clients })
+    }
+}
+impl TelegramConsumer for StreamingConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let reading = json::reading(telegram);
+        clients.retain(|client| {
+            let update = match &client.fields {
+                Some(fields) => select(&reading, fields),
+                None => reading.clone(),
+            };
+            client.offer(update.to_string())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::Read;
+    use std::time::Instant;
+
+    fn telegram() -> Telegram {
+        Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n1-0:1.7.0(00.302*kW)\r\n\
+             1-0:2.7.0(00.000*kW)\r\n!\r\n",
+        )
+    }
+
+    fn start() -> (StreamingConsumer, String) {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let consumer = StreamingConsumer::start(&StreamSettings {
+            address: address.clone(),
+            pipeline: Vec::new(),
+        })
+        .unwrap();
+        (consumer, address)
+    }
+
+    fn wait_for_client(consumer: &StreamingConsumer) {
+        let start = Instant::now();
+        while consumer.clients.lock().unwrap().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn select_fields_of_reading() {
+        let reading = json::reading(&telegram());
+
+        let result = select(
+            &reading,
+            &[String::from("1-0:1.7.0"), String::from("flags")],
+        );
+
+        assert_eq!(
+            result,
+            serde_json::json!({
+                "timestamp": "2023-10-26T20:40:15+02:00",
+                "values": {"1-0:1.7.0": {"value": 0.302, "unit": "kW"}},
+                "flags": [],
+            })
+        );
+    }
+
+    #[test]
+    fn read_stream_requests() {
+        let mut request: &[u8] = b"GET /stream?fields=1-0%3A1.7.0,derived HTTP/1.1\r\n\
+            Host: localhost\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        assert_eq!(
+            read_request(&mut request).unwrap(),
+            Subscription {
+                protocol: Protocol::WebSocket(String::from("dGhlIHNhbXBsZSBub25jZQ==")),
+                fields: Some(vec![String::from("1-0:1.7.0"), String::from("derived")]),
+            }
+        );
+
+        let mut request: &[u8] = b"GET /stream HTTP/1.1\r\nAccept: text/event-stream\r\n\r\n";
+        assert_eq!(
+            read_request(&mut request).unwrap(),
+            Subscription {
+                protocol: Protocol::ServerSentEvents,
+                fields: None,
+            }
+        );
+
+        let mut request: &[u8] = b"GET /status HTTP/1.1\r\n\r\n";
+        assert!(read_request(&mut request).is_err());
+    }
+
+    #[test]
+    fn keep_latest_update_for_slow_client() {
+        let client = Client {
+            fields: None,
+            pending: Mutex::new(Pending::default()),
+            ready: Condvar::new(),
+        };
+
+        assert!(client.offer(String::from("first")));
+        assert!(client.offer(String::from("second")));
+        assert_eq!(client.next(), "second");
+
+        client.close();
+        assert!(!client.offer(String::from("third")));
+    }
+
+    #[test]
+    fn stream_server_sent_events() {
+        let (mut consumer, address) = start();
+        let mut connection = TcpStream::connect(address).unwrap();
+        connection
+            .write_all(b"GET /stream?fields=1-0:1.7.0 HTTP/1.1\r\n\r\n")
+            .unwrap();
+        wait_for_client(&consumer);
+
+        consumer.consume(&telegram());
+
+        let mut received = String::new();
+        let mut buffer = [0u8; 1024];
+        while !received.ends_with("\n\n") || !received.contains("data:") {
+            let read = connection.read(&mut buffer).unwrap();
+            received.push_str(&String::from_utf8_lossy(&buffer[..read]));
+        }
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(received.contains("Content-Type: text/event-stream"));
+        assert!(received.ends_with(
+            "data: {\"timestamp\":\"2023-10-26T20:40:15+02:00\",\
+             \"values\":{\"1-0:1.7.0\":{\"unit\":\"kW\",\"value\":0.302}}}\n\n"
+        ));
+    }
+
+    #[test]
+    fn stream_websocket() {
+        let (mut consumer, address) = start();
+        let connection = TcpStream::connect(&address).unwrap();
+        let url = format!("ws://{}/stream", address);
+        let (mut socket, _) = tungstenite::client(url, connection).unwrap();
+        wait_for_client(&consumer);
+
+        consumer.consume(&telegram());
+
+        let message = socket.read().unwrap();
+        let reading: Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(reading, json::reading(&telegram()));
+    }
+}
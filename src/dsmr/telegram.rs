@@ -0,0 +1,340 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use super::aggregate::Summary;
+use super::cost::Costs;
+use super::derived::Derived;
+use super::timestamp;
+use super::totals::Totals;
+use super::value::{Decimal, Quantity, Unit};
+
+pub const TIMESTAMP: &str = "0-0:1.0.0";
+
+// Counters that only ever increase: electricity delivered to and by the client per tariff,
+// and the last hourly reading of M-Bus devices such as gas meters.
+pub fn is_cumulative(obis: &str) -> bool {
+    obis.starts_with("1-0:1.8.")
+        || obis.starts_with("1-0:2.8.")
+        || (obis.starts_with("0-") && (obis.ends_with(":24.2.1") || obis.ends_with(":24.2.3")))
+}
+
+// A single line of a telegram, such as `1-0:1.8.1(000032.159*kWh)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CosemObject {
+    pub obis: String,
+    pub values: Vec<String>,
+}
+
+impl CosemObject {
+    // Returns the value that carries a unit, which is the last one for objects that
+    // also report a capture time such as `0-1:24.2.1(231026204004S)(00004.381*m3)`.
+    pub fn quantity(&self) -> Option<Quantity> {
+        self.values
+            .iter()
+            .rev()
+            .find(|value| value.contains('*'))
+            .and_then(|value| match Quantity::parse(value) {
+                Ok(quantity) => Some(quantity),
+                Err(msg) => {
+                    log::debug!("Ignoring value of {}: {}", self.obis, msg);
+                    None
+                }
+            })
+    }
+
+    // Replaces the value that carries a unit.
+    pub fn set_quantity(&mut self, quantity: Quantity) {
+        if let Some(value) = self
+            .values
+            .iter_mut()
+            .rev()
+            .find(|value| value.contains('*'))
+        {
+            *value = format!("{}*{}", quantity.value, quantity.unit);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Telegram {
+    pub raw: String,
+    pub header: String,
+    pub objects: Vec<CosemObject>,
+    // Problems found while validating the telegram that did not lead to dropping it
+    pub flags: Vec<String>,
+    // Statistics of the window this telegram represents, when it is the result of aggregation
+    pub summary: Option<Summary>,
+    // Running costs of the day and month this telegram was read in
+    pub costs: Option<Costs>,
+    // Energy and gas used so far in the day and month this telegram was read in
+    pub totals: Option<Totals>,
+    // Net power, per-phase values and gas flow calculated from the values of the telegram
+    pub derived: Option<Derived>,
+}
+
+fn parse_line(line: &str) -> Option<CosemObject> {
+    let open = line.find('(')?;
+    let obis = line[..open].trim();
+    if obis.is_empty() {
+        return None;
+    }
+
+    let values = line[open..]
+        .trim_end()
+        .split(')')
+        .filter_map(|part| part.strip_prefix('('))
+        .map(String::from)
+        .collect();
+
+    Some(CosemObject {
+        obis: String::from(obis),
+        values,
+    })
+}
+
+impl Telegram {
+    pub fn parse(telegram: &str) -> Self {
+        let header = telegram
+            .lines()
+            .find(|line| line.starts_with('/'))
+            .map(|line| String::from(line.trim_end()))
+            .unwrap_or_default();
+
+        let objects = telegram.lines().filter_map(parse_line).collect();
+
+        Telegram {
+            raw: String::from(telegram),
+            header,
+            objects,
+            flags: Vec::new(),
+            summary: None,
+            costs: None,
+            totals: None,
+            derived: None,
+        }
+    }
+
+    pub fn object(&self, obis: &str) -> Option<&CosemObject> {
+        self.objects.iter().find(|object| object.obis == obis)
+    }
+
+    // Returns the first value of the given OBIS code, which is the only one for most objects.
+    pub fn value(&self, obis: &str) -> Option<&str> {
+        self.object(obis)
+            .and_then(|object| object.values.first())
+            .map(String::as_str)
+    }
+
+    pub fn quantity(&self, obis: &str) -> Option<Quantity> {
+        self.object(obis).and_then(CosemObject::quantity)
+    }
+
+    // Returns the value of the given OBIS code as a number, converted to the unit if one is given.
+    // Values without a unit, such as the number of power failures, are numbers too.
+    pub fn number(&self, obis: &str, unit: Option<Unit>) -> Option<Decimal> {
+        match (self.quantity(obis), unit) {
+            (Some(quantity), Some(unit)) => quantity.convert(unit).ok().map(|q| q.value),
+            (Some(quantity), None) => Some(quantity.value),
+            (None, None) => self
+                .value(obis)
+                .and_then(|value| Decimal::parse(value).ok()),
+            (None, Some(_)) => None,
+        }
+    }
+
+    // Returns the channel, capture time and reading in m3 of each gas meter, which M-Bus devices
+    // report on any channel.
+    pub fn gas_readings(&self) -> Vec<(u8, DateTime<Tz>, Decimal)> {
+        self.objects
+            .iter()
+            .filter(|object| object.obis.starts_with("0-") && object.obis.ends_with(":24.2.1"))
+            .filter_map(|object| {
+                let channel = object.obis[2..].split(':').next()?.parse::<u8>().ok()?;
+                let captured = timestamp::parse_timestamp(object.values.first()?).ok()?;
+                let reading = object.quantity()?.convert(Unit::CubicMetre).ok()?;
+                Some((channel, captured, reading.value))
+            })
+            .collect()
+    }
+
+    // Returns the capture time and reading in m3 of the first gas meter.
+    pub fn gas_reading(&self) -> Option<(DateTime<Tz>, Decimal)> {
+        self.gas_readings()
+            .first()
+            .map(|(_, captured, reading)| (*captured, *reading))
+    }
+
+    pub fn timestamp(&self) -> Option<DateTime<Tz>> {
+        match self.value(TIMESTAMP).map(timestamp::parse_timestamp) {
+            Some(Ok(instant)) => Some(instant),
+            Some(Err(msg)) => {
+                log::debug!("Ignoring telegram timestamp: {}", msg);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    use crate::dsmr::value::{Decimal, Unit};
+
+    #[test]
+    fn number_of_object() {
+        let telegram = Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n1-0:1.7.0(00.302*kW)\r\n0-0:96.7.21(00004)\r\n!\r\n",
+        );
+
+        assert_eq!(
+            telegram.number("1-0:1.7.0", None),
+            Some(Decimal::new(302, 3))
+        );
+        assert_eq!(
+            telegram.number("1-0:1.7.0", Some(Unit::Watt)),
+            Some(Decimal::new(302, 0))
+        );
+        assert_eq!(telegram.number("1-0:1.7.0", Some(Unit::Volt)), None);
+        assert_eq!(
+            telegram.number("0-0:96.7.21", None),
+            Some(Decimal::new(4, 0))
+        );
+        assert_eq!(telegram.number("0-0:96.7.21", Some(Unit::Watt)), None);
+    }
+
+    #[test]
+    fn parse_line_with_single_value() {
+        let result = parse_line("1-0:1.8.1(000032.159*kWh)");
+
+        assert_eq!(
+            result,
+            Some(CosemObject {
+                obis: String::from("1-0:1.8.1"),
+                values: vec![String::from("000032.159*kWh")],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_line_with_multiple_values() {
+        let result = parse_line("0-1:24.2.1(231026204004S)(00004.381*m3)\r").unwrap();
+
+        assert_eq!(result.obis, "0-1:24.2.1");
+        assert_eq!(result.values, vec!["231026204004S", "00004.381*m3"]);
+    }
+
+    #[test]
+    fn parse_line_with_empty_value() {
+        let result = parse_line("0-0:96.13.0()").unwrap();
+
+        assert_eq!(result.values, vec![""]);
+    }
+
+    #[test]
+    fn parse_line_without_values() {
+        assert_eq!(parse_line("/ISK5\\2M550T-1013"), None);
+        assert_eq!(parse_line("!3812"), None);
+        assert_eq!(parse_line(""), None);
+    }
+
+    #[test]
+    fn parse_complete_telegram() {
+        let telegram = Telegram::parse(&read_test_resource("output1.txt".into()));
+
+        assert_eq!(telegram.header, "/ISK5\\2M550T-1013");
+        assert_eq!(telegram.objects.len(), 35);
+        assert_eq!(telegram.value("1-0:1.8.1"), Some("000032.159*kWh"));
+        assert_eq!(telegram.value("1-0:99.97.0"), Some("1"));
+        assert_eq!(telegram.value("1-0:1.8.3"), None);
+    }
+
+    #[test]
+    fn object_quantity() {
+        let object = parse_line("0-1:24.2.1(231026204004S)(00004.381*m3)").unwrap();
+
+        let result = object.quantity().unwrap();
+        assert_eq!(result.value, Decimal::new(4381, 3));
+        assert_eq!(result.unit, Unit::CubicMetre);
+    }
+
+    #[test]
+    fn object_set_quantity() {
+        let mut object = parse_line("0-1:24.2.1(231026204004S)(00004.381*m3)").unwrap();
+
+        object.set_quantity(Quantity::parse("4.5*m3").unwrap());
+
+        assert_eq!(object.values, vec!["231026204004S", "4.5*m3"]);
+    }
+
+    #[test]
+    fn object_without_quantity() {
+        let object = parse_line("0-0:96.7.21(00005)").unwrap();
+
+        assert_eq!(object.quantity(), None);
+    }
+
+    #[test]
+    fn telegram_quantities() {
+        let telegram = Telegram::parse(&read_test_resource("output1.txt".into()));
+
+        let power = telegram.quantity("1-0:1.7.0").unwrap();
+        assert_eq!(
+            power.convert(Unit::Watt).unwrap().value,
+            Decimal::new(302, 0)
+        );
+        assert_eq!(
+            telegram.quantity("1-0:32.7.0").unwrap().to_string(),
+            "234.3 V"
+        );
+        assert_eq!(telegram.quantity("0-0:96.1.1"), None);
+    }
+
+    #[test]
+    fn cumulative_obis_codes() {
+        assert!(is_cumulative("1-0:1.8.1"));
+        assert!(is_cumulative("1-0:2.8.2"));
+        assert!(is_cumulative("0-1:24.2.1"));
+        assert!(is_cumulative("0-2:24.2.3"));
+        assert!(!is_cumulative("1-0:1.7.0"));
+        assert!(!is_cumulative("0-1:24.1.0"));
+    }
+
+    #[test]
+    fn gas_readings_on_any_channel() {
+        let telegram = Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-1:24.2.1(231026204004S)(00004.381*m3)\r\n\
+             0-2:24.2.1(231026200000S)(00012.000*m3)\r\n0-3:24.2.1(invalid)(00001.000*m3)\r\n!\r\n",
+        );
+
+        let readings = telegram.gas_readings();
+
+        assert_eq!(readings.len(), 2);
+        assert_eq!(readings[1].0, 2);
+        assert_eq!(readings[1].2, Decimal::new(12000, 3));
+        let (captured, reading) = telegram.gas_reading().unwrap();
+        assert_eq!(captured.to_rfc3339(), "2023-10-26T20:40:04+02:00");
+        assert_eq!(reading, Decimal::new(4381, 3));
+    }
+
+    #[test]
+    fn telegram_timestamp() {
+        let telegram = Telegram::parse(&read_test_resource("output1.txt".into()));
+
+        let expected = timestamp::parse_timestamp("231026204015S").unwrap();
+        assert_eq!(telegram.timestamp(), Some(expected));
+    }
+
+    fn read_test_resource(path: PathBuf) -> String {
+        let mut test_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_file.push("resources/test/");
+        test_file.push(path);
+
+        fs::read_to_string(test_file).expect("Failed to read file")
+    }
+}
@@ -0,0 +1,102 @@
+use chrono::{DateTime, LocalResult, NaiveDate, TimeZone};
+use chrono_tz::Europe::Amsterdam;
+use chrono_tz::Tz;
+
+// Converts a DSMR timestamp (YYMMDDhhmmssX) into an instant in the Europe/Amsterdam timezone.
+// The trailing X is 'S' for summer time or 'W' for winter time; it is used to tell apart the
+// two occurrences of the same wall-clock time during the autumn DST transition.
+pub fn parse_timestamp(input: &str) -> Result<DateTime<Tz>, String> {
+    let (digits, suffix) = match input.char_indices().nth(12) {
+        Some((index, _)) => input.split_at(index),
+        None => (input, ""),
+    };
+
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!(
+            "Timestamp {} is not formatted as YYMMDDhhmmss",
+            input
+        ));
+    }
+
+    let field = |from: usize| digits[from..(from + 2)].parse::<u32>().unwrap();
+    let naive = NaiveDate::from_ymd_opt(2000 + field(0) as i32, field(2), field(4))
+        .and_then(|date| date.and_hms_opt(field(6), field(8), field(10)))
+        .ok_or_else(|| format!("Timestamp {} is not a valid date and time", input))?;
+
+    match (Amsterdam.from_local_datetime(&naive), suffix) {
+        (LocalResult::Single(instant), "S") | (LocalResult::Single(instant), "W") => Ok(instant),
+        (LocalResult::Single(instant), "") => Ok(instant),
+        (LocalResult::Ambiguous(summer, _), "S") | (LocalResult::Ambiguous(summer, _), "") => {
+            Ok(summer)
+        }
+        (LocalResult::Ambiguous(_, winter), "W") => Ok(winter),
+        (LocalResult::None, _) => Err(format!("Timestamp {} does not exist in local time", input)),
+        (_, _) => Err(format!("Timestamp {} has an unknown DST suffix", input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use chrono::Utc;
+
+    #[test]
+    fn parse_summer_timestamp() {
+        let result = parse_timestamp("231026204015S");
+
+        assert!(result.is_ok());
+        let expected = Utc.with_ymd_and_hms(2023, 10, 26, 18, 40, 15).unwrap();
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_winter_timestamp() {
+        let result = parse_timestamp("230114121128W");
+
+        assert!(result.is_ok());
+        let expected = Utc.with_ymd_and_hms(2023, 1, 14, 11, 11, 28).unwrap();
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_ambiguous_timestamp_in_summer_time() {
+        let result = parse_timestamp("231029023000S");
+
+        let expected = Utc.with_ymd_and_hms(2023, 10, 29, 0, 30, 0).unwrap();
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_ambiguous_timestamp_in_winter_time() {
+        let result = parse_timestamp("231029023000W");
+
+        let expected = Utc.with_ymd_and_hms(2023, 10, 29, 1, 30, 0).unwrap();
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_timestamp_without_suffix() {
+        let result = parse_timestamp("231026204015");
+
+        let expected = Utc.with_ymd_and_hms(2023, 10, 26, 18, 40, 15).unwrap();
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_timestamp_in_spring_gap() {
+        let result = parse_timestamp("230326023000W");
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_invalid_timestamps() {
+        assert!(parse_timestamp("").is_err());
+        assert!(parse_timestamp("2310262040S").is_err());
+        assert!(parse_timestamp("231326204015S").is_err());
+        assert!(parse_timestamp("23102620401aS").is_err());
+        assert!(parse_timestamp("231026204015X").is_err());
+    }
+}
@@ -0,0 +1,522 @@
+use std::fs;
+
+use super::settings::TotalsSettings;
+use super::telegram::Telegram;
+use super::value::{Decimal, Unit};
+use super::TelegramConsumer;
+
+const IMPORT_TOTAL: &str = "1-0:1.8.0";
+const IMPORT_TARIFF_PREFIX: &str = "1-0:1.8.";
+const EXPORT_TOTAL: &str = "1-0:2.8.0";
+const EXPORT_TARIFF_PREFIX: &str = "1-0:2.8.";
+// The state is saved at every day boundary, and otherwise at most once per this many seconds
+const SAVE_INTERVAL: i64 = 300;
+
+// Energy in kWh and gas in m3 used in a day or month.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodTotals {
+    // The day (2023-10-26) or month (2023-10) these totals are for
+    pub period: String,
+    // Energy delivered to the client
+    pub import: Decimal,
+    // Energy delivered by the client
+    pub export: Decimal,
+    pub gas: Decimal,
+}
+impl PeriodTotals {
+    fn new(period: String) -> Self {
+        let zero = Decimal::new(0, 0);
+        PeriodTotals {
+            period,
+            import: zero,
+            export: zero,
+            gas: zero,
+        }
+    }
+
+    fn add(&mut self, counters: &Counters) {
+        let zero = Decimal::new(0, 0);
+        self.import = self.import + counters.import.unwrap_or(zero);
+        self.export = self.export + counters.export.unwrap_or(zero);
+        self.gas = self.gas + counters.gas.unwrap_or(zero);
+    }
+}
+
+// Energy and gas used so far today and this month.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Totals {
+    pub day: PeriodTotals,
+    pub month: PeriodTotals,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Counters {
+    import: Option<Decimal>,
+    export: Option<Decimal>,
+    gas: Option<Decimal>,
+}
+
+// Returns the counter in kWh, summed over all tariffs when the meter does not report a total.
+fn energy(telegram: &Telegram, total: &str, tariff_prefix: &str) -> Option<Decimal> {
+    if let Some(Ok(total)) = telegram
+        .quantity(total)
+        .map(|quantity| quantity.convert(Unit::KiloWattHour))
+    {
+        return Some(total.value);
+    }
+    telegram
+        .objects
+        .iter()
+        .filter(|object| object.obis.starts_with(tariff_prefix))
+        .filter_map(|object| object.quantity())
+        .filter_map(|quantity| quantity.convert(Unit::KiloWattHour).ok())
+        .map(|quantity| quantity.value)
+        .reduce(|a, b| a + b)
+}
+
+// Returns how much a counter increased. A counter that went back is taken as the start of a
+// replaced meter, of which the usage before this telegram is unknown.
+fn delta(name: &str, previous: Option<Decimal>, current: Option<Decimal>) -> Option<Decimal> {
+    match (previous, current) {
+        (Some(previous), Some(current)) if current >= previous => Some(current - previous),
+        (Some(previous), Some(current)) => {
+            log::warn!(
+                "Counter of {} went back from {} to {}, assuming the meter was replaced",
+                name,
+                previous,
+                current
+            );
+            None
+        }
+        _ => None,
+    }
+}
+
+impl Counters {
+    fn from(telegram: &Telegram) -> Self {
+        Counters {
+            import: energy(telegram, IMPORT_TOTAL, IMPORT_TARIFF_PREFIX),
+            export: energy(telegram, EXPORT_TOTAL, EXPORT_TARIFF_PREFIX),
+            gas: telegram.gas_reading().map(|(_, reading)| reading),
+        }
+    }
+
+    // Keeps the previous reading of counters that are missing from this telegram.
+    fn or(self, previous: &Counters) -> Self {
+        Counters {
+            import: self.import.or(previous.import),
+            export: self.export.or(previous.export),
+            gas: self.gas.or(previous.gas),
+        }
+    }
+
+    fn since(&self, previous: &Counters) -> Counters {
+        Counters {
+            import: delta("energy delivered to client", previous.import, self.import),
+            export: delta("energy delivered by client", previous.export, self.export),
+            gas: delta("gas", previous.gas, self.gas),
+        }
+    }
+}
+
+fn format_value(value: Option<Decimal>) -> String {
+    value.map_or_else(|| String::from("-"), |value| value.to_string())
+}
+
+fn parse_value(value: &str) -> Option<Decimal> {
+    Decimal::parse(value).ok()
+}
+
+// The state file has a line for the day, the month and the last counters, such as
+// `day 2023-10-26 1.234 0.500 0.300`, `month 2023-10 12.345 5.000 3.000` and
+// `counters 32.159 2.376 4.381`, with `-` for counters the meter does not report.
+fn parse_state(content: &str) -> (Option<PeriodTotals>, Option<PeriodTotals>, Option<Counters>) {
+    let (mut day, mut month, mut counters) = (None, None, None);
+    for line in content.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["day" | "month", period, import, export, gas] => {
+                let totals = match (parse_value(import), parse_value(export), parse_value(gas)) {
+                    (Some(import), Some(export), Some(gas)) => PeriodTotals {
+                        period: String::from(*period),
+                        import,
+                        export,
+                        gas,
+                    },
+                    _ => continue,
+                };
+                if parts[0] == "day" {
+                    day = Some(totals);
+                } else {
+                    month = Some(totals);
+                }
+            }
+            ["counters", import, export, gas] => {
+                counters = Some(Counters {
+                    import: parse_value(import),
+                    export: parse_value(export),
+                    gas: parse_value(gas),
+                })
+            }
+            _ => log::debug!("Ignoring line of totals state: {}", line),
+        }
+    }
+    (day, month, counters)
+}
+
+// Keeps the energy and gas used in the current day and month based on the meter timestamp,
+// and attaches them to each telegram. With a state file the totals survive a restart.
+pub struct TotalsConsumer {
+    state_file: Option<String>,
+    previous: Option<Counters>,
+    day: Option<PeriodTotals>,
+    month: Option<PeriodTotals>,
+    saved_at: Option<i64>,
+    // Whether the counters were read from the state file and no telegram was handled yet
+    restored: bool,
+    downstream: Box<dyn TelegramConsumer>,
+}
+impl TotalsConsumer {
+    pub fn new(settings: TotalsSettings, downstream: Box<dyn TelegramConsumer>) -> Self {
+        let (day, month, previous) = match &settings.state_file {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(content) => parse_state(&content),
+                Err(msg) => {
+                    log::info!("Not reading totals from {}: {}", path, msg);
+                    (None, None, None)
+                }
+            },
+            None => (None, None, None),
+        };
+
+        TotalsConsumer {
+            state_file: settings.state_file,
+            restored: previous.is_some(),
+            previous,
+            day,
+            month,
+            saved_at: None,
+            downstream,
+        }
+    }
+
+    fn save(&self) {
+        let path = match &self.state_file {
+            Some(path) => path,
+            None => return,
+        };
+        let mut content = String::new();
+        for (name, totals) in [("day", &self.day), ("month", &self.month)] {
+            if let Some(totals) = totals {
+                content.push_str(&format!(
+                    "{} {} {} {} {}\n",
+                    name, totals.period, totals.import, totals.export, totals.gas
+                ));
+            }
+        }
+        if let Some(counters) = &self.previous {
+            content.push_str(&format!(
+                "counters {} {} {}\n",
+                format_value(counters.import),
+                format_value(counters.export),
+                format_value(counters.gas)
+            ));
+        }
+        // Replace the file at once, so it is never left half written
+        let temporary = format!("{}.tmp", path);
+        if let Err(msg) = fs::write(&temporary, content).and_then(|_| fs::rename(&temporary, path))
+        {
+            log::warn!("Could not save totals to {} due to {}", path, msg);
+        }
+    }
+
+    fn update(&mut self, telegram: &Telegram) -> Option<Totals> {
+        let timestamp = telegram.timestamp()?;
+        let date = timestamp.date_naive();
+        let day = date.format("%Y-%m-%d").to_string();
+        let month = date.format("%Y-%m").to_string();
+
+        // After a restart into another day or month, the usage since the saved counters can
+        // not be split over the periods, so it is not counted for the new period
+        let restored = std::mem::take(&mut self.restored);
+        let mut changed = false;
+        let mut count_month = true;
+        let mut count_day = true;
+        if self.month.as_ref().map(|totals| &totals.period) != Some(&month) {
+            self.month = Some(PeriodTotals::new(month));
+            changed = true;
+            count_month = !restored;
+        }
+        if self.day.as_ref().map(|totals| &totals.period) != Some(&day) {
+            if let Some(previous) = &self.day {
+                log::info!(
+                    "Totals for {}: {} kWh delivered to client, {} kWh delivered by client, {} m3 gas",
+                    previous.period,
+                    previous.import,
+                    previous.export,
+                    previous.gas
+                );
+            }
+            self.day = Some(PeriodTotals::new(day));
+            changed = true;
+            count_day = !restored;
+        }
+
+        let current = Counters::from(telegram);
+        let current = match &self.previous {
+            Some(previous) => {
+                let current = current.or(previous);
+                let usage = current.since(previous);
+                for (totals, count) in [(&mut self.day, count_day), (&mut self.month, count_month)]
+                {
+                    if let (Some(totals), true) = (totals.as_mut(), count) {
+                        totals.add(&usage);
+                    }
+                }
+                current
+            }
+            None => current,
+        };
+        self.previous = Some(current);
+
+        let timestamp = timestamp.timestamp();
+        if changed
+            || self
+                .saved_at
+                .is_none_or(|saved_at| timestamp - saved_at >= SAVE_INTERVAL)
+        {
+            self.save();
+            self.saved_at = Some(timestamp);
+        }
+
+        match (&self.day, &self.month) {
+            (Some(day), Some(month)) => Some(Totals {
+                day: day.clone(),
+                month: month.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl TelegramConsumer for TotalsConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        match self.update(telegram) {
+            Some(totals) => {
+                let mut result = telegram.clone();
+                result.totals = Some(totals);
+                self.downstream.consume(&result);
+            }
+            None => self.downstream.consume(telegram),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::path::Path;
+
+    struct NoopConsumer {}
+    impl TelegramConsumer for NoopConsumer {
+        fn consume(&mut self, _telegram: &Telegram) {}
+    }
+
+    fn consumer(state_file: Option<String>) -> TotalsConsumer {
+        TotalsConsumer::new(TotalsSettings { state_file }, Box::new(NoopConsumer {}))
+    }
+
+    fn value(value: &str) -> Decimal {
+        Decimal::parse(value).unwrap()
+    }
+
+    fn telegram(timestamp: &str, import1: &str, export1: &str, gas: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0({})\r\n1-0:1.8.1({}*kWh)\r\n1-0:1.8.2(000002.167*kWh)\r\n1-0:2.8.1({}*kWh)\r\n1-0:2.8.2(000000.000*kWh)\r\n0-1:24.2.1(231026204004S)({}*m3)\r\n!\r\n",
+            timestamp, import1, export1, gas
+        ))
+    }
+
+    #[test]
+    fn totals_of_day_and_month() {
+        let mut consumer = consumer(None);
+
+        let first = consumer
+            .update(&telegram(
+                "231031234500W",
+                "000032.159",
+                "000002.376",
+                "00004.381",
+            ))
+            .unwrap();
+        assert_eq!(first.day, PeriodTotals::new(String::from("2023-10-31")));
+
+        let second = consumer
+            .update(&telegram(
+                "231031235959W",
+                "000033.159",
+                "000002.876",
+                "00004.681",
+            ))
+            .unwrap();
+        assert_eq!(second.day.import, value("1.000"));
+        assert_eq!(second.day.export, value("0.500"));
+        assert_eq!(second.day.gas, value("0.300"));
+        assert_eq!(second.month.import, value("1.000"));
+
+        // Usage since the last telegram of the previous day counts for the new day
+        let third = consumer
+            .update(&telegram(
+                "231101000009W",
+                "000033.259",
+                "000002.876",
+                "00004.681",
+            ))
+            .unwrap();
+        assert_eq!(third.day.period, "2023-11-01");
+        assert_eq!(third.day.import, value("0.100"));
+        assert_eq!(third.month.period, "2023-11");
+        assert_eq!(third.month.import, value("0.100"));
+    }
+
+    #[test]
+    fn counters_that_go_back_are_a_replaced_meter() {
+        let mut consumer = consumer(None);
+
+        consumer.update(&telegram(
+            "231026204015S",
+            "000032.159",
+            "000002.376",
+            "00004.381",
+        ));
+        consumer.update(&telegram(
+            "231026204025S",
+            "000000.100",
+            "000000.000",
+            "00004.381",
+        ));
+        let result = consumer
+            .update(&telegram(
+                "231026204035S",
+                "000000.300",
+                "000000.000",
+                "00004.381",
+            ))
+            .unwrap();
+
+        assert_eq!(result.day.import, value("0.200"));
+        assert_eq!(result.day.export, value("0.000"));
+    }
+
+    #[test]
+    fn totals_survive_restart() {
+        let path = std::env::temp_dir().join(format!("dsmr-rs-totals-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        let mut first = consumer(Some(path.clone()));
+        first.update(&telegram(
+            "231026204015S",
+            "000032.159",
+            "000002.376",
+            "00004.381",
+        ));
+        first.update(&telegram(
+            "231026204025S",
+            "000032.659",
+            "000002.376",
+            "00004.381",
+        ));
+        first.save();
+
+        let mut second = consumer(Some(path.clone()));
+        let result = second
+            .update(&telegram(
+                "231026210000S",
+                "000033.159",
+                "000002.376",
+                "00004.481",
+            ))
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.day.period, "2023-10-26");
+        assert_eq!(result.day.import, value("1.000"));
+        assert_eq!(result.day.gas, value("0.100"));
+        assert_eq!(result.month.import, value("1.000"));
+    }
+
+    #[test]
+    fn restart_into_new_day_starts_from_counters() {
+        let path =
+            std::env::temp_dir().join(format!("dsmr-rs-totals-day-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        let mut first = consumer(Some(path.clone()));
+        first.update(&telegram(
+            "231026204015S",
+            "000032.159",
+            "000002.376",
+            "00004.381",
+        ));
+        first.update(&telegram(
+            "231026204025S",
+            "000032.659",
+            "000002.376",
+            "00004.381",
+        ));
+        first.save();
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        let mut second = consumer(Some(path.clone()));
+        let restarted = second
+            .update(&telegram(
+                "231027080000S",
+                "000040.659",
+                "000002.376",
+                "00005.381",
+            ))
+            .unwrap();
+        let next = second
+            .update(&telegram(
+                "231027080010S",
+                "000040.759",
+                "000002.376",
+                "00005.381",
+            ))
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Usage while the logger was down is not booked to the new day, but is to the month
+        assert_eq!(restarted.day.period, "2023-10-27");
+        assert_eq!(restarted.day.import, value("0"));
+        assert_eq!(restarted.month.import, value("8.500"));
+        assert_eq!(next.day.import, value("0.100"));
+    }
+
+    #[test]
+    fn parse_state_with_unknown_counters() {
+        let (day, month, counters) =
+            parse_state("day 2023-10-26 1.234 0.500 0.300\ncounters 32.159 - -\nunknown\n");
+
+        assert_eq!(
+            day,
+            Some(PeriodTotals {
+                period: String::from("2023-10-26"),
+                import: value("1.234"),
+                export: value("0.500"),
+                gas: value("0.300"),
+            })
+        );
+        assert_eq!(month, None);
+        assert_eq!(
+            counters,
+            Some(Counters {
+                import: Some(value("32.159")),
+                export: None,
+                gas: None,
+            })
+        );
+    }
+}
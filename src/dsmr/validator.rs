@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use super::settings::{ValidationAction, ValidationSettings};
+use super::telegram::{self, Telegram};
+use super::value::Quantity;
+use super::TelegramConsumer;
+
+// Counters that go back in this many telegrams in a row are taken as a new reference, as the
+// meter was replaced or its counters were reset rather than a single reading being wrong.
+const RESET_AFTER: u32 = 10;
+
+// Checks telegrams that passed the checksum for values that are still wrong, such as counters
+// that go backwards, voltages of 0 or timestamps that are repeated after a meter reboot.
+// Depending on the configured action, a telegram that violates a rule is dropped or flagged.
+pub struct ValidatingConsumer {
+    settings: ValidationSettings,
+    counters: HashMap<String, Quantity>,
+    last_timestamp: Option<DateTime<Tz>>,
+    // Number of telegrams in a row in which a counter went back
+    counters_back: u32,
+    downstream: Box<dyn TelegramConsumer>,
+}
+
+struct Violation {
+    action: ValidationAction,
+    message: String,
+}
+
+impl ValidatingConsumer {
+    pub fn new(settings: ValidationSettings, downstream: Box<dyn TelegramConsumer>) -> Self {
+        ValidatingConsumer {
+            settings,
+            counters: HashMap::new(),
+            last_timestamp: None,
+            counters_back: 0,
+            downstream,
+        }
+    }
+
+    fn check_monotonic(&self, telegram: &Telegram, violations: &mut Vec<Violation>) {
+        for object in telegram
+            .objects
+            .iter()
+            .filter(|object| telegram::is_cumulative(&object.obis))
+        {
+            let current = object.quantity();
+            let previous = self.counters.get(&object.obis);
+            if let (Some(current), Some(previous)) = (current, previous) {
+                let current = current.convert(previous.unit).unwrap_or(current);
+                if current.value < previous.value {
+                    violations.push(Violation {
+                        action: self.settings.monotonic,
+                        message: format!(
+                            "Counter {} went back from {} to {}",
+                            object.obis, previous, current
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    fn check_ranges(&self, telegram: &Telegram, violations: &mut Vec<Violation>) {
+        for range in &self.settings.ranges {
+            if let Some(quantity) = telegram.quantity(&range.obis) {
+                if quantity.value < range.min || quantity.value > range.max {
+                    violations.push(Violation {
+                        action: self.settings.range,
+                        message: format!(
+                            "Value {} of {} is outside range {}..{}",
+                            quantity, range.obis, range.min, range.max
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    fn check_duplicate(&self, telegram: &Telegram, violations: &mut Vec<Violation>) {
+        if let (Some(current), Some(previous)) = (telegram.timestamp(), self.last_timestamp) {
+            if current == previous {
+                violations.push(Violation {
+                    action: self.settings.duplicate,
+                    message: format!("Timestamp {} was already seen", current),
+                });
+            }
+        }
+    }
+
+    fn check_mandatory(&self, telegram: &Telegram, violations: &mut Vec<Violation>) {
+        for obis in &self.settings.mandatory_fields {
+            if telegram.object(obis).is_none() {
+                violations.push(Violation {
+                    action: self.settings.mandatory,
+                    message: format!("Mandatory field {} is missing", obis),
+                });
+            }
+        }
+    }
+
+    // Returns the telegram to pass on, or nothing when it must be dropped.
+    fn validate(&mut self, telegram: &Telegram) -> Option<Telegram> {
+        let mut violations = Vec::new();
+        self.check_monotonic(telegram, &mut violations);
+        if violations.is_empty() {
+            self.counters_back = 0;
+        } else {
+            self.counters_back += 1;
+            if self.counters_back >= RESET_AFTER {
+                log::warn!(
+                    "Counters went back in {} telegrams in a row, taking them as a reset",
+                    self.counters_back
+                );
+                violations.clear();
+                self.counters_back = 0;
+            }
+        }
+        self.check_ranges(telegram, &mut violations);
+        self.check_duplicate(telegram, &mut violations);
+        self.check_mandatory(telegram, &mut violations);
+
+        let mut flags = Vec::new();
+        let mut dropped = false;
+        for violation in violations {
+            match violation.action {
+                ValidationAction::Drop => {
+                    log::warn!("Dropping telegram: {}", violation.message);
+                    dropped = true;
+                }
+                ValidationAction::Flag => {
+                    log::debug!("Flagging telegram: {}", violation.message);
+                    flags.push(violation.message);
+                }
+                ValidationAction::Pass => {
+                    log::trace!("Ignoring violation: {}", violation.message);
+                }
+            }
+        }
+        if dropped {
+            return None;
+        }
+
+        // Only accepted telegrams serve as a reference for the next one
+        for object in &telegram.objects {
+            if let (true, Some(quantity)) =
+                (telegram::is_cumulative(&object.obis), object.quantity())
+            {
+                self.counters.insert(object.obis.clone(), quantity);
+            }
+        }
+        if let Some(timestamp) = telegram.timestamp() {
+            self.last_timestamp = Some(timestamp);
+        }
+
+        let mut accepted = telegram.clone();
+        accepted.flags.extend(flags);
+        Some(accepted)
+    }
+}
+
+impl TelegramConsumer for ValidatingConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        if let Some(accepted) = self.validate(telegram) {
+            self.downstream.consume(&accepted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use crate::dsmr::settings::ValueRange;
+    use crate::dsmr::value::Decimal;
+
+    struct NoopConsumer {}
+    impl TelegramConsumer for NoopConsumer {
+        fn consume(&mut self, _telegram: &Telegram) {}
+    }
+
+    fn validator(settings: ValidationSettings) -> ValidatingConsumer {
+        ValidatingConsumer::new(settings, Box::new(NoopConsumer {}))
+    }
+
+    fn settings(action: ValidationAction) -> ValidationSettings {
+        ValidationSettings {
+            monotonic: action,
+            range: action,
+            duplicate: action,
+            mandatory: action,
+            ranges: vec![ValueRange {
+                obis: String::from("1-0:32.7.0"),
+                min: Decimal::parse("180").unwrap(),
+                max: Decimal::parse("260").unwrap(),
+            }],
+            mandatory_fields: vec![String::from("1-0:1.8.1")],
+        }
+    }
+
+    fn telegram(timestamp: &str, delivered: &str, voltage: &str) -> Telegram {
+        Telegram::parse(&format!(
+            "/ISK5\\2M550T-1013\r\n\r\n0-0:1.0.0({})\r\n1-0:1.8.1({}*kWh)\r\n1-0:32.7.0({}*V)\r\n!\r\n",
+            timestamp, delivered, voltage
+        ))
+    }
+
+    #[test]
+    fn accept_valid_telegrams() {
+        let mut validator = validator(settings(ValidationAction::Drop));
+
+        let first = validator.validate(&telegram("231026204015S", "000032.159", "234.3"));
+        let second = validator.validate(&telegram("231026204016S", "000032.160", "234.1"));
+
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert!(second.unwrap().flags.is_empty());
+    }
+
+    #[test]
+    fn drop_counter_going_back() {
+        let mut validator = validator(settings(ValidationAction::Drop));
+
+        validator.validate(&telegram("231026204015S", "000032.159", "234.3"));
+        let result = validator.validate(&telegram("231026204016S", "000032.158", "234.3"));
+
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn compare_counters_in_same_unit() {
+        let mut validator = validator(settings(ValidationAction::Drop));
+
+        validator.validate(&telegram("231026204015S", "000032.159", "234.3"));
+        let wh = Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204016S)\r\n1-0:1.8.1(32160*Wh)\r\n!\r\n",
+        );
+
+        assert!(validator.validate(&wh).is_some());
+    }
+
+    #[test]
+    fn keep_reference_when_dropping() {
+        let mut validator = validator(settings(ValidationAction::Drop));
+
+        validator.validate(&telegram("231026204015S", "000032.159", "234.3"));
+        validator.validate(&telegram("231026204016S", "000000.000", "234.3"));
+        let result = validator.validate(&telegram("231026204017S", "000032.159", "234.3"));
+
+        assert!(result.is_some());
+    }
+
+    #[test]
+    fn accept_reset_counters_again() {
+        let mut validator = validator(settings(ValidationAction::Drop));
+
+        validator.validate(&telegram("231026204000S", "000032.159", "234.3"));
+        for second in 1..RESET_AFTER {
+            let timestamp = format!("2310262040{:02}S", second);
+            let result = validator.validate(&telegram(&timestamp, "000000.001", "234.3"));
+            assert!(result.is_none());
+        }
+        let reset = validator.validate(&telegram("231026204030S", "000000.002", "234.3"));
+        let next = validator.validate(&telegram("231026204031S", "000000.003", "234.3"));
+
+        assert!(reset.is_some());
+        assert!(next.is_some());
+    }
+
+    #[test]
+    fn flag_value_out_of_range() {
+        let mut validator = validator(settings(ValidationAction::Flag));
+
+        let result = validator.validate(&telegram("231026204015S", "000032.159", "000.0"));
+
+        assert!(result.is_some());
+        let flags = result.unwrap().flags;
+        assert_eq!(flags.len(), 1);
+        assert!(flags[0].contains("1-0:32.7.0"));
+    }
+
+    #[test]
+    fn pass_value_out_of_range() {
+        let mut validator = validator(settings(ValidationAction::Pass));
+
+        let result = validator.validate(&telegram("231026204015S", "000032.159", "000.0"));
+
+        assert!(result.unwrap().flags.is_empty());
+    }
+
+    #[test]
+    fn drop_duplicate_timestamp() {
+        let mut validator = validator(settings(ValidationAction::Drop));
+
+        validator.validate(&telegram("231026204015S", "000032.159", "234.3"));
+        let result = validator.validate(&telegram("231026204015S", "000032.159", "234.3"));
+
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn flag_missing_mandatory_field() {
+        let mut validator = validator(settings(ValidationAction::Flag));
+
+        let result = validator.validate(&Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n!\r\n",
+        ));
+
+        let flags = result.unwrap().flags;
+        assert_eq!(flags, vec!["Mandatory field 1-0:1.8.1 is missing"]);
+    }
+}
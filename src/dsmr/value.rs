@@ -0,0 +1,517 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// A fixed-point decimal number, such as `000032.159`. The value is `digits / 10^scale`.
+// Meters report cumulative counters with a fixed number of decimals; keeping them as integers
+// avoids the rounding errors that floating point numbers introduce over time.
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    digits: i64,
+    scale: u32,
+}
+
+const MAX_SCALE: u32 = 12;
+
+impl Decimal {
+    pub fn new(digits: i64, scale: u32) -> Self {
+        Decimal { digits, scale }
+    }
+
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (negative, unsigned) = match input.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, input.strip_prefix('+').unwrap_or(input)),
+        };
+        let (integral, fraction) = match unsigned.split_once('.') {
+            Some((integral, fraction)) => (integral, fraction),
+            None => (unsigned, ""),
+        };
+
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if integral.is_empty() || !is_digits(integral) || !is_digits(fraction) {
+            return Err(format!("Value {} is not a decimal number", input));
+        }
+        if fraction.len() as u32 > MAX_SCALE {
+            return Err(format!("Value {} has too many decimals", input));
+        }
+
+        let digits = [integral, fraction]
+            .concat()
+            .parse::<i64>()
+            .map_err(|_| format!("Value {} is out of range", input))?;
+
+        Ok(Decimal {
+            digits: if negative { -digits } else { digits },
+            scale: fraction.len() as u32,
+        })
+    }
+
+    // Divides the value, rounding the result to the same number of decimals.
+    pub fn divide(&self, divisor: i64) -> Self {
+        self.multiply(1, divisor as i128, self.scale)
+    }
+
+    // Multiplies the value by num / den, rounding the result to the same number of decimals.
+    pub fn times(&self, num: i64, den: i64) -> Self {
+        self.multiply(num as i128, den as i128, self.scale)
+    }
+
+    // Divides by another decimal, giving a result with the given number of decimals.
+    pub fn divided_by(&self, divisor: Decimal, scale: u32) -> Option<Self> {
+        if divisor.digits == 0 {
+            return None;
+        }
+        let numerator = self.digits as i128 * 10i128.pow(scale + divisor.scale);
+        let denominator = divisor.digits as i128 * 10i128.pow(self.scale);
+        Some(Decimal::new(
+            divide_rounded(numerator, denominator) as i64,
+            scale,
+        ))
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    // Rounds (half away from zero) or pads the value to the given number of decimals.
+    pub fn with_scale(&self, scale: u32) -> Self {
+        Decimal::new(
+            scale_digits(self.digits as i128, self.scale, scale) as i64,
+            scale,
+        )
+    }
+
+    // Returns the nearest floating point number, for storage and protocols that need one.
+    pub fn to_f64(self) -> f64 {
+        self.digits as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    // Multiplies the value by num / den, rounding the result to the given number of decimals.
+    fn multiply(&self, num: i128, den: i128, scale: u32) -> Self {
+        let numerator = self.digits as i128 * num * 10i128.pow(scale);
+        let denominator = den * 10i128.pow(self.scale);
+        Decimal::new(divide_rounded(numerator, denominator) as i64, scale)
+    }
+}
+
+fn divide_rounded(numerator: i128, denominator: i128) -> i128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    if remainder.abs() * 2 >= denominator.abs() {
+        quotient + numerator.signum() * denominator.signum()
+    } else {
+        quotient
+    }
+}
+
+fn scale_digits(digits: i128, from: u32, to: u32) -> i128 {
+    match from.cmp(&to) {
+        Ordering::Less => digits * 10i128.pow(to - from),
+        Ordering::Equal => digits,
+        Ordering::Greater => divide_rounded(digits, 10i128.pow(from - to)),
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        let left = scale_digits(self.digits as i128, self.scale, scale);
+        let right = scale_digits(other.digits as i128, other.scale, scale);
+        left.cmp(&right)
+    }
+}
+
+impl Add for Decimal {
+    type Output = Decimal;
+    fn add(self, other: Decimal) -> Decimal {
+        let scale = self.scale.max(other.scale);
+        let left = scale_digits(self.digits as i128, self.scale, scale);
+        let right = scale_digits(other.digits as i128, other.scale, scale);
+        Decimal::new((left + right) as i64, scale)
+    }
+}
+
+impl Sub for Decimal {
+    type Output = Decimal;
+    fn sub(self, other: Decimal) -> Decimal {
+        self + (-other)
+    }
+}
+
+// The result has the decimals of both values, up to a maximum of 12.
+impl Mul for Decimal {
+    type Output = Decimal;
+    fn mul(self, other: Decimal) -> Decimal {
+        let scale = self.scale + other.scale;
+        let digits = scale_digits(
+            self.digits as i128 * other.digits as i128,
+            scale,
+            scale.min(MAX_SCALE),
+        );
+        Decimal::new(digits as i64, scale.min(MAX_SCALE))
+    }
+}
+
+impl Neg for Decimal {
+    type Output = Decimal;
+    fn neg(self) -> Decimal {
+        Decimal::new(-self.digits, self.scale)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.digits < 0 { "-" } else { "" };
+        let magnitude = self.digits.unsigned_abs();
+        if self.scale == 0 {
+            return write!(f, "{}{}", sign, magnitude);
+        }
+        let divisor = 10u64.pow(self.scale);
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            magnitude / divisor,
+            magnitude % divisor,
+            width = self.scale as usize
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Unit {
+    WattHour,
+    KiloWattHour,
+    MegaWattHour,
+    MegaJoule,
+    GigaJoule,
+    Watt,
+    KiloWatt,
+    Volt,
+    Ampere,
+    CubicMetre,
+    CubicMetrePerHour,
+    Second,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Energy,
+    Power,
+    Voltage,
+    Current,
+    Volume,
+    Flow,
+    Time,
+}
+
+impl Unit {
+    pub fn parse(input: &str) -> Option<Unit> {
+        match input {
+            "Wh" => Some(Unit::WattHour),
+            "kWh" => Some(Unit::KiloWattHour),
+            "MWh" => Some(Unit::MegaWattHour),
+            "MJ" => Some(Unit::MegaJoule),
+            "GJ" => Some(Unit::GigaJoule),
+            "W" => Some(Unit::Watt),
+            "kW" => Some(Unit::KiloWatt),
+            "V" => Some(Unit::Volt),
+            "A" => Some(Unit::Ampere),
+            "m3" => Some(Unit::CubicMetre),
+            "m3/h" => Some(Unit::CubicMetrePerHour),
+            "s" => Some(Unit::Second),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::WattHour => "Wh",
+            Unit::KiloWattHour => "kWh",
+            Unit::MegaWattHour => "MWh",
+            Unit::MegaJoule => "MJ",
+            Unit::GigaJoule => "GJ",
+            Unit::Watt => "W",
+            Unit::KiloWatt => "kW",
+            Unit::Volt => "V",
+            Unit::Ampere => "A",
+            Unit::CubicMetre => "m3",
+            Unit::CubicMetrePerHour => "m3/h",
+            Unit::Second => "s",
+        }
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        match self {
+            Unit::WattHour
+            | Unit::KiloWattHour
+            | Unit::MegaWattHour
+            | Unit::MegaJoule
+            | Unit::GigaJoule => Dimension::Energy,
+            Unit::Watt | Unit::KiloWatt => Dimension::Power,
+            Unit::Volt => Dimension::Voltage,
+            Unit::Ampere => Dimension::Current,
+            Unit::CubicMetre => Dimension::Volume,
+            Unit::CubicMetrePerHour => Dimension::Flow,
+            Unit::Second => Dimension::Time,
+        }
+    }
+
+    // The size of this unit, as a fraction of the base unit of its dimension (Wh for energy, W for power).
+    fn factor(&self) -> (i128, i128) {
+        match self {
+            Unit::KiloWattHour | Unit::KiloWatt => (1_000, 1),
+            Unit::MegaWattHour => (1_000_000, 1),
+            // 1 MJ = 1,000,000 J = 1,000,000 / 3,600 Wh
+            Unit::MegaJoule => (2_500, 9),
+            Unit::GigaJoule => (2_500_000, 9),
+            _ => (1, 1),
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quantity {
+    pub value: Decimal,
+    pub unit: Unit,
+}
+
+impl Quantity {
+    pub fn new(value: Decimal, unit: Unit) -> Self {
+        Quantity { value, unit }
+    }
+
+    // Parses a value such as `000032.159*kWh`.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (value, unit) = input
+            .split_once('*')
+            .ok_or_else(|| format!("Value {} has no unit", input))?;
+        let unit = Unit::parse(unit).ok_or_else(|| format!("Unit {} is not supported", unit))?;
+
+        Ok(Quantity::new(Decimal::parse(value)?, unit))
+    }
+
+    // Converts the quantity to another unit of the same dimension.
+    // The result has as many decimals as needed to keep the resolution of the original value,
+    // so 000032.159 kWh becomes 32159 Wh and 1234 Wh becomes 1.234 kWh.
+    pub fn convert(&self, unit: Unit) -> Result<Quantity, String> {
+        if self.unit.dimension() != unit.dimension() {
+            return Err(format!("Can not convert {} to {}", self.unit, unit));
+        }
+        if self.unit == unit {
+            return Ok(*self);
+        }
+
+        let (from_num, from_den) = self.unit.factor();
+        let (to_num, to_den) = unit.factor();
+        let (num, den) = (from_num * to_den, from_den * to_num);
+
+        // Smallest number of decimals for which one step of the original value is still visible
+        let one_step = 10i128.pow(self.value.scale);
+        let scale = (0..=MAX_SCALE)
+            .find(|&scale| num * 10i128.pow(scale) >= den * one_step)
+            .unwrap_or(MAX_SCALE);
+
+        Ok(Quantity::new(self.value.multiply(num, den, scale), unit))
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value, self.unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn parse_decimal() {
+        let result = Decimal::parse("000032.159").unwrap();
+
+        assert_eq!(result.digits, 32159);
+        assert_eq!(result.scale, 3);
+        assert_eq!(result.to_string(), "32.159");
+    }
+
+    #[test]
+    fn parse_decimal_without_fraction() {
+        let result = Decimal::parse("00005").unwrap();
+
+        assert_eq!(result.digits, 5);
+        assert_eq!(result.scale, 0);
+        assert_eq!(result.to_string(), "5");
+    }
+
+    #[test]
+    fn parse_negative_decimal() {
+        let result = Decimal::parse("-0.050").unwrap();
+
+        assert_eq!(result.digits, -50);
+        assert_eq!(result.to_string(), "-0.050");
+    }
+
+    #[test]
+    fn parse_invalid_decimals() {
+        assert!(Decimal::parse("").is_err());
+        assert!(Decimal::parse(".5").is_err());
+        assert!(Decimal::parse("1.2.3").is_err());
+        assert!(Decimal::parse("12a").is_err());
+        assert!(Decimal::parse("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn decimal_arithmetic_aligns_scale() {
+        let a = Decimal::parse("32.159").unwrap();
+        let b = Decimal::parse("2.1").unwrap();
+
+        assert_eq!((a + b).to_string(), "34.259");
+        assert_eq!((a - b).to_string(), "30.059");
+        assert_eq!((b - a).to_string(), "-30.059");
+    }
+
+    #[test]
+    fn decimal_multiplication_keeps_decimals() {
+        let energy = Decimal::parse("0.125").unwrap();
+        let price = Decimal::parse("0.28795").unwrap();
+
+        assert_eq!((energy * price).to_string(), "0.03599375");
+    }
+
+    #[test]
+    fn decimal_with_scale_rounds() {
+        let value = Decimal::parse("1.2345").unwrap();
+
+        assert_eq!(value.with_scale(2).to_string(), "1.23");
+        assert_eq!(value.with_scale(3).to_string(), "1.235");
+        assert_eq!((-value).with_scale(3).to_string(), "-1.235");
+        assert_eq!(value.with_scale(5).to_string(), "1.23450");
+    }
+
+    #[test]
+    fn decimal_comparison_ignores_scale() {
+        assert_eq!(
+            Decimal::parse("1.50").unwrap(),
+            Decimal::parse("1.5").unwrap()
+        );
+        assert!(Decimal::parse("1.49").unwrap() < Decimal::parse("1.5").unwrap());
+    }
+
+    #[test]
+    fn decimal_division_rounds() {
+        let value = Decimal::parse("0.302").unwrap();
+
+        assert_eq!(value.divide(2).to_string(), "0.151");
+        assert_eq!(value.divide(4).to_string(), "0.076");
+        assert_eq!((-value).divide(4).to_string(), "-0.076");
+    }
+
+    #[test]
+    fn decimal_multiplication_rounds() {
+        let value = Decimal::parse("0.302").unwrap();
+
+        assert_eq!(value.times(4, 1).to_string(), "1.208");
+        assert_eq!(value.times(600, 3600).to_string(), "0.050");
+    }
+
+    #[test]
+    fn decimal_divided_by_decimal() {
+        let power = Decimal::parse("1234").unwrap();
+        let voltage = Decimal::parse("230.1").unwrap();
+
+        assert_eq!(power.divided_by(voltage, 3).unwrap().to_string(), "5.363");
+        assert_eq!((-power).divided_by(voltage, 1).unwrap().to_string(), "-5.4");
+        assert!(power.divided_by(Decimal::new(0, 1), 3).is_none());
+    }
+
+    #[test]
+    fn parse_quantity() {
+        let result = Quantity::parse("000032.159*kWh").unwrap();
+
+        assert_eq!(result.value, Decimal::new(32159, 3));
+        assert_eq!(result.unit, Unit::KiloWattHour);
+        assert_eq!(result.to_string(), "32.159 kWh");
+    }
+
+    #[test]
+    fn parse_invalid_quantities() {
+        assert!(Quantity::parse("000032.159").is_err());
+        assert!(Quantity::parse("000032.159*kvarh").is_err());
+        assert!(Quantity::parse("abc*kWh").is_err());
+    }
+
+    #[test]
+    fn convert_kwh_to_wh() {
+        let result = Quantity::parse("000032.159*kWh")
+            .unwrap()
+            .convert(Unit::WattHour)
+            .unwrap();
+
+        assert_eq!(result.to_string(), "32159 Wh");
+    }
+
+    #[test]
+    fn convert_wh_to_kwh() {
+        let result = Quantity::parse("1234*Wh")
+            .unwrap()
+            .convert(Unit::KiloWattHour)
+            .unwrap();
+
+        assert_eq!(result.to_string(), "1.234 kWh");
+    }
+
+    #[test]
+    fn convert_kw_to_w() {
+        let result = Quantity::parse("00.302*kW")
+            .unwrap()
+            .convert(Unit::Watt)
+            .unwrap();
+
+        assert_eq!(result.to_string(), "302 W");
+    }
+
+    #[test]
+    fn convert_gj_to_kwh() {
+        let result = Quantity::parse("00012.345*GJ")
+            .unwrap()
+            .convert(Unit::KiloWattHour)
+            .unwrap();
+
+        assert_eq!(result.to_string(), "3429.2 kWh");
+    }
+
+    #[test]
+    fn convert_to_same_unit() {
+        let quantity = Quantity::parse("234.3*V").unwrap();
+
+        assert_eq!(quantity.convert(Unit::Volt).unwrap(), quantity);
+    }
+
+    #[test]
+    fn convert_between_dimensions() {
+        let result = Quantity::parse("234.3*V").unwrap().convert(Unit::Ampere);
+
+        assert!(result.is_err());
+    }
+}
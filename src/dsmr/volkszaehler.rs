@@ -0,0 +1,186 @@
+use chrono::Utc;
+
+use super::settings::{ValueMapping, VolkszaehlerSettings};
+use super::status::Health;
+use super::telegram::Telegram;
+use super::TelegramConsumer;
+
+// Posts the values of each telegram to the channels of a Volkszähler middleware, once per
+// telegram. A channel is skipped when its value is not in the telegram.
+pub struct VolkszaehlerConsumer {
+    url: String,
+    channels: Vec<ValueMapping>,
+    client: reqwest::blocking::Client,
+    health: Health,
+}
+impl VolkszaehlerConsumer {
+    pub fn new(settings: &VolkszaehlerSettings, health: Health) -> Self {
+        VolkszaehlerConsumer {
+            url: String::from(settings.url.trim_end_matches('/')),
+            channels: settings.channels.clone(),
+            client: reqwest::blocking::Client::new(),
+            health,
+        }
+    }
+
+    fn post(&self, uuid: &str, timestamp: i64, value: &str) -> Result<(), String> {
+        let url = format!("{}/data/{}.json", self.url, uuid);
+        log::trace!("- posting value to {}", url);
+        let response = self
+            .client
+            .post(&url)
+            .query(&[
+                ("ts", timestamp.to_string()),
+                ("value", String::from(value)),
+            ])
+            .send()
+            .map_err(|msg| msg.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Response with status {}", response.status()));
+        }
+        Ok(())
+    }
+}
+impl TelegramConsumer for VolkszaehlerConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        // The middleware expects timestamps in milliseconds
+        let timestamp = telegram.timestamp().map_or_else(
+            || Utc::now().timestamp_millis(),
+            |timestamp| timestamp.timestamp_millis(),
+        );
+
+        let mut errors = Vec::new();
+        for channel in &self.channels {
+            let value = match telegram.number(&channel.obis, channel.unit) {
+                Some(value) => value,
+                None => continue,
+            };
+            if let Err(msg) = self.post(&channel.name, timestamp, &value.to_string()) {
+                log::warn!(
+                    "Could not post value to Volkszähler channel {} due to {}",
+                    channel.name,
+                    msg
+                );
+                errors.push(msg);
+            }
+        }
+
+        match errors.last() {
+            Some(error) => self.health.failure(&self.url, error),
+            None => self.health.success(&self.url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::dsmr::value::Unit;
+
+    const POWER: &str = "12345678-1234-1234-1234-123456789012";
+    const DELIVERED: &str = "87654321-4321-4321-4321-210987654321";
+
+    fn telegram() -> Telegram {
+        Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n\
+             1-0:1.8.1(000032.159*kWh)\r\n1-0:1.7.0(00.302*kW)\r\n!\r\n",
+        )
+    }
+
+    fn settings(url: &str) -> VolkszaehlerSettings {
+        VolkszaehlerSettings {
+            url: format!("{}/middleware.php/", url),
+            channels: vec![
+                ValueMapping {
+                    obis: String::from("1-0:1.7.0"),
+                    unit: Some(Unit::Watt),
+                    name: String::from(POWER),
+                },
+                ValueMapping {
+                    obis: String::from("1-0:2.7.0"),
+                    unit: Some(Unit::Watt),
+                    name: String::from("00000000-0000-0000-0000-000000000000"),
+                },
+                ValueMapping {
+                    obis: String::from("1-0:1.8.1"),
+                    unit: Some(Unit::WattHour),
+                    name: String::from(DELIVERED),
+                },
+            ],
+            pipeline: Vec::new(),
+        }
+    }
+
+    // Answers the given number of requests with the given status, and returns their request lines.
+    fn server(requests: usize, status: &'static str) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut lines = Vec::new();
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                // Skip the headers, as the request has no body
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}",
+                    status
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+                lines.push(String::from(line.trim_end()));
+            }
+            lines
+        });
+        (format!("http://{}", address), server)
+    }
+
+    #[test]
+    fn post_values_to_channels() {
+        let (url, server) = server(2, "200 OK");
+
+        let mut consumer = VolkszaehlerConsumer::new(&settings(&url), Health::default());
+        consumer.consume(&telegram());
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                format!(
+                    "POST /middleware.php/data/{}.json?ts=1698345615000&value=302 HTTP/1.1",
+                    POWER
+                ),
+                format!(
+                    "POST /middleware.php/data/{}.json?ts=1698345615000&value=32159 HTTP/1.1",
+                    DELIVERED
+                ),
+            ]
+        );
+        assert!(consumer.health.snapshot()[&consumer.url].healthy());
+    }
+
+    #[test]
+    fn report_values_that_were_not_accepted() {
+        let (url, server) = server(2, "400 Bad Request");
+
+        let mut consumer = VolkszaehlerConsumer::new(&settings(&url), Health::default());
+        consumer.consume(&telegram());
+
+        server.join().unwrap();
+        let status = &consumer.health.snapshot()[&consumer.url];
+        assert!(!status.healthy());
+        assert_eq!(
+            status.last_error.as_deref(),
+            Some("Response with status 400 Bad Request")
+        );
+    }
+}
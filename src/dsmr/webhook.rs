@@ -0,0 +1,229 @@
+use reqwest::Method;
+
+use super::settings::{BodyFormat, Webhook, WebhookAuth};
+use super::status::Health;
+use super::telegram::Telegram;
+use super::TelegramConsumer;
+
+// Returns the value of a placeholder: `raw`, `header`, `timestamp` or an OBIS code, of which
+// the value is given without its unit.
+fn placeholder(telegram: &Telegram, name: &str) -> Option<String> {
+    match name {
+        "raw" => Some(telegram.raw.clone()),
+        "header" => Some(telegram.header.clone()),
+        "timestamp" => telegram.timestamp().map(|timestamp| timestamp.to_rfc3339()),
+        obis => telegram.object(obis).map(|object| match object.quantity() {
+            Some(quantity) => quantity.value.to_string(),
+            None => object.values.first().cloned().unwrap_or_default(),
+        }),
+    }
+}
+
+// Replaces each `{{name}}` in the template with the escaped value of the placeholder.
+// Placeholders without a value in the telegram are left empty.
+pub fn render(template: &str, telegram: &Telegram, escape: impl Fn(&str) -> String) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let name = rest[start + 2..end].trim();
+        match placeholder(telegram, name) {
+            Some(value) => result.push_str(&escape(&value)),
+            None => log::debug!("No value for placeholder {} in telegram", name),
+        }
+        rest = &rest[end + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+// Percent-encodes everything but unreserved characters, as in `application/x-www-form-urlencoded`.
+pub fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            b' ' => String::from("+"),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// Escapes a value to put between the quotes of a JSON string.
+pub fn json_escape(value: &str) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+    String::from(&quoted[1..quoted.len() - 1])
+}
+
+// Sends telegrams to an HTTP endpoint, with the URL and body rendered from templates.
+pub struct WebhookConsumer {
+    url: String,
+    method: Method,
+    headers: Vec<(String, String)>,
+    auth: WebhookAuth,
+    format: BodyFormat,
+    body: String,
+    client: reqwest::blocking::Client,
+    health: Health,
+}
+impl WebhookConsumer {
+    pub fn new(webhook: &Webhook, health: Health) -> Self {
+        WebhookConsumer {
+            url: webhook.url.clone(),
+            method: Method::from_bytes(webhook.method.as_bytes()).unwrap_or(Method::POST),
+            headers: webhook.headers.clone(),
+            auth: webhook.auth.clone(),
+            format: webhook.format,
+            body: webhook.body.clone(),
+            client: reqwest::blocking::Client::new(),
+            health,
+        }
+    }
+
+    fn request(&self, telegram: &Telegram) -> reqwest::blocking::RequestBuilder {
+        let url = render(&self.url, telegram, url_encode);
+        let mut request = self.client.request(self.method.clone(), url);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request = match &self.auth {
+            WebhookAuth::None => request,
+            WebhookAuth::Token(key) => request.header("Authorization", format!("Token {}", key)),
+            WebhookAuth::Bearer(token) => request.bearer_auth(token),
+            WebhookAuth::Basic(user, password) => request.basic_auth(user, Some(password)),
+        };
+        if self.method == Method::GET {
+            return request;
+        }
+
+        let (content_type, body) = match self.format {
+            BodyFormat::Form => (
+                "application/x-www-form-urlencoded",
+                render(&self.body, telegram, url_encode),
+            ),
+            BodyFormat::Json => (
+                "application/json",
+                render(&self.body, telegram, json_escape),
+            ),
+            BodyFormat::Raw => (
+                "text/plain",
+                render(&self.body, telegram, |value| value.to_string()),
+            ),
+        };
+        request.header("Content-Type", content_type).body(body)
+    }
+}
+impl TelegramConsumer for WebhookConsumer {
+    fn consume(&mut self, telegram: &Telegram) {
+        log::trace!("- sending telegram to {}", self.url);
+        match self.request(telegram).send() {
+            Ok(response) if !response.status().is_success() => {
+                log::warn!(
+                    "Webhook {} answered with status {}",
+                    self.url,
+                    response.status()
+                );
+                let error = format!("Response with status {}", response.status());
+                self.health.failure(&self.url, &error);
+            }
+            Ok(response) => {
+                log::trace!("Got response with status {}", response.status());
+                self.health.success(&self.url);
+            }
+            Err(msg) => {
+                log::warn!("Could not send telegram to webhook due to {}", msg);
+                self.health.failure(&self.url, &msg.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn telegram() -> Telegram {
+        Telegram::parse(
+            "/ISK5\\2M550T-1013\r\n0-0:1.0.0(231026204015S)\r\n1-0:1.7.0(00.302*kW)\r\n!\r\n",
+        )
+    }
+
+    #[test]
+    fn render_placeholders() {
+        let result = render(
+            "{{timestamp}} {{ 1-0:1.7.0 }} {{0-0:1.0.0}} [{{1-0:2.7.0}}] {{header",
+            &telegram(),
+            |value| value.to_string(),
+        );
+
+        assert_eq!(
+            result,
+            "2023-10-26T20:40:15+02:00 0.302 231026204015S [] {{header"
+        );
+    }
+
+    #[test]
+    fn render_escaped_placeholders() {
+        assert_eq!(
+            render("telegram={{raw}}", &telegram(), url_encode),
+            "telegram=%2FISK5%5C2M550T-1013%0D%0A0-0%3A1.0.0%28231026204015S%29%0D%0A1-0%3A1.7.0%2800.302%2AkW%29%0D%0A%21%0D%0A"
+        );
+        assert_eq!(
+            render(r#"{"header": "{{header}}"}"#, &telegram(), json_escape),
+            r#"{"header": "/ISK5\\2M550T-1013"}"#
+        );
+    }
+
+    #[test]
+    fn send_telegram_to_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !String::from_utf8_lossy(&request).contains("0.302\"}") {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let health = Health::default();
+        let mut consumer = WebhookConsumer::new(
+            &Webhook {
+                url: format!("http://{}/collect?at={{{{timestamp}}}}", address),
+                method: String::from("PUT"),
+                headers: vec![(String::from("X-Source"), String::from("dsmr-rs"))],
+                auth: WebhookAuth::Bearer(String::from("secret")),
+                format: BodyFormat::Json,
+                body: String::from(r#"{"power": "{{1-0:1.7.0}}"}"#),
+                pipeline: Vec::new(),
+            },
+            health.clone(),
+        );
+        consumer.consume(&telegram());
+
+        let request = server.join().unwrap().to_lowercase();
+        assert!(request.starts_with("put /collect?at=2023-10-26t20%3a40%3a15%2b02%3a00 http/1.1"));
+        assert!(request.contains("x-source: dsmr-rs"));
+        assert!(request.contains("authorization: bearer secret"));
+        assert!(request.contains("content-type: application/json"));
+        assert!(request.ends_with(r#"{"power": "0.302"}"#));
+        assert_eq!(health.snapshot().len(), 1);
+    }
+}
@@ -1,7 +1,7 @@
 mod dsmr;
 mod scheduler;
 
-fn init_logger(debug_logging: bool) {
+fn init_logger(debug_logging: bool, terminal_mode: simplelog::TerminalMode) {
     let console_level = if debug_logging {
         simplelog::LevelFilter::Debug
     } else {
@@ -20,7 +20,7 @@ fn init_logger(debug_logging: bool) {
             simplelog::TermLogger::new(
                 console_level,
                 config.clone(),
-                simplelog::TerminalMode::Mixed,
+                terminal_mode,
                 simplelog::ColorChoice::Never,
             ),
             simplelog::WriteLogger::new(file_level, config, file),
@@ -30,7 +30,7 @@ fn init_logger(debug_logging: bool) {
         simplelog::CombinedLogger::init(vec![simplelog::TermLogger::new(
             console_level,
             config,
-            simplelog::TerminalMode::Mixed,
+            terminal_mode,
             simplelog::ColorChoice::Never,
         )])
         .unwrap()
@@ -45,12 +45,18 @@ pub fn main() {
     let settings = builder.build().unwrap();
 
     let debug_logging = settings.get_bool("debug_logging").unwrap_or(false);
-    init_logger(debug_logging);
+    // Standard output is kept for readings when they are written to it as JSON lines
+    let terminal_mode = match settings.get_string("jsonl_output").as_deref() {
+        Ok("stdout") | Ok("-") => simplelog::TerminalMode::Stderr,
+        _ => simplelog::TerminalMode::Mixed,
+    };
+    init_logger(debug_logging, terminal_mode);
 
     let read_interval = settings.get_float("sleep").unwrap_or(0.5);
 
     log::info!("dsmr-rs starting...");
-    let (serial_settings, api_settings) = dsmr::settings::settings(settings).unwrap();
+    let settings = dsmr::settings::settings(settings).unwrap();
+    let serial_settings = &settings.serial;
 
     log::info!(
         "Using serial port {} with baud rate {}, byte size {} and parity bit {:#?}",
@@ -60,5 +66,5 @@ pub fn main() {
         &serial_settings.parity_bit
     );
 
-    scheduler::main_loop(api_settings, serial_settings, read_interval);
+    scheduler::main_loop(settings, read_interval);
 }
@@ -2,19 +2,23 @@ use std::{thread, time};
 
 use crate::dsmr;
 
-pub fn main_loop(
-    api_settings: dsmr::settings::HostSettings,
-    serial_settings: dsmr::settings::SerialSettings,
-    read_interval: f64,
-) {
+pub fn main_loop(settings: dsmr::settings::Settings, read_interval: f64) {
     const FAILURE_THRESHOLD: i8 = 20;
 
     let interval = time::Duration::from_millis((read_interval * 1_000.0).round() as u64);
-    let mut consumer = dsmr::sender::DelegatingConsumer::new(&api_settings.hosts);
+    let serial_settings = &settings.serial;
+    let mut delegate: Box<dyn dsmr::TelegramConsumer> =
+        Box::new(dsmr::sender::DelegatingConsumer::new(&settings));
+    if !settings.cost.tariffs.is_empty() || settings.cost.dynamic_prices.is_some() {
+        delegate = Box::new(dsmr::cost::CostConsumer::new(settings.cost, delegate));
+    }
+    delegate = Box::new(dsmr::totals::TotalsConsumer::new(settings.totals, delegate));
+    delegate = Box::new(dsmr::derived::DerivingConsumer::new(delegate));
+    let mut consumer = dsmr::validator::ValidatingConsumer::new(settings.validation, delegate);
     let mut failure_count: i8 = 0;
 
     loop {
-        let result = dsmr::reader::connect_to_meter(&serial_settings);
+        let result = dsmr::reader::connect_to_meter(serial_settings);
         if let Ok(port) = result {
             dsmr::reader::read_from_serial_port(port, &mut consumer);
             failure_count = 0;